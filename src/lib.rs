@@ -6,6 +6,9 @@ pub mod common;
 pub use mmg_server::http_server::start_mmg_server;
 pub use mmg_server::http_server::start_mmg_server_sub_thread;
 pub use minimongo::minimongo::get_mgdb;
+pub use minimongo::minimongo::get_mgdb_with_config;
+pub use minimongo::config::{set_default_config, MgDbConfig, MgDurability};
+pub use minimongo::workspace::{close_workspace, delete_workspace, evict_idle_workspaces, list_workspaces, open_workspace, start_idle_eviction_sub_thread};
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
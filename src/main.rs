@@ -1,4 +1,7 @@
 use crate::common::helper::empty_loop;
+use std::time::Duration;
+use crate::minimongo::config::{set_default_config, MgDbConfig};
+use crate::minimongo::workspace::start_idle_eviction_sub_thread;
 use crate::mmg_server::http_server::start_mmg_server_sub_thread;
 
 mod minimongo;
@@ -7,7 +10,25 @@ pub mod common;
 
 fn main() {
     println!("mmg_server is starting!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match MgDbConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("启动参数错误: {}", err);
+            std::process::exit(2);
+        }
+    };
+    println!("数据目录: {:?}, 只读: {}", config.data_root, config.read_only);
+    println!("admin 接口: {}", if config.admin_token.is_some() { "已开启" } else { "未配置 --admin-token, 已关闭" });
+    let idle_timeout = config.idle_timeout;
+    if let Err(err) = set_default_config(config) {
+        println!("设置数据库配置失败: {}", err);
+        std::process::exit(2);
+    }
     println!("准备开启mmg_server 100001");
+    if let Some(idle_timeout) = idle_timeout {
+        start_idle_eviction_sub_thread(Duration::from_secs(idle_timeout));
+    }
     let _ = start_mmg_server_sub_thread();
     println!("完成开启mmg_server");
     empty_loop();
@@ -0,0 +1,216 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{LazyLock, RwLock};
+use redb::{Builder, Database, Durability, WriteTransaction};
+use redb::backends::InMemoryBackend;
+use serde::{Deserialize, Serialize};
+use crate::minimongo::error::MgError;
+
+/// 数据文件名中的 workspace 占位符
+const WORKSPACE_PLACEHOLDER: &str = "{workspace}";
+
+static DEFAULT_CONFIG: LazyLock<RwLock<MgDbConfig>> = LazyLock::new(|| RwLock::new(MgDbConfig::default()));
+
+/// 写事务提交时的持久化级别, 对应 redb 的 Durability
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum MgDurability {
+    /// 不落盘, 直到之后有更高级别的提交
+    None,
+    /// 由 redb 择机落盘
+    Eventual,
+    /// 每次提交都落盘
+    #[default]
+    Immediate,
+    /// 每次提交都落盘, 并使用两阶段提交
+    Paranoid,
+}
+
+impl MgDurability {
+    /// 按持久化级别设置写事务
+    pub(crate) fn apply(&self, write_txn: &mut WriteTransaction) {
+        let durability = match self {
+            MgDurability::None => Durability::None,
+            MgDurability::Eventual => Durability::Eventual,
+            MgDurability::Immediate | MgDurability::Paranoid => Durability::Immediate,
+        };
+        write_txn.set_durability(durability);
+        write_txn.set_two_phase_commit(matches!(self, MgDurability::Paranoid));
+    }
+}
+
+/// 打开 workspace 数据库的配置
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MgDbConfig {
+    /// 数据文件所在目录
+    pub data_root: PathBuf,
+    /// 数据文件名, 其中的 `{workspace}` 替换为 workspace id
+    pub file_name: String,
+    /// redb 缓存大小 (字节), None 时使用 redb 的默认值
+    pub cache_size: Option<usize>,
+    pub durability: MgDurability,
+    /// 只读打开: 数据文件必须已存在, 写入返回错误
+    pub read_only: bool,
+    /// 空闲超过该秒数的 workspace 自动关闭, None 时不自动关闭
+    pub idle_timeout: Option<u64>,
+    /// 使用内存存储: 不读写磁盘也不登记, 每次打开都是空的, 关闭后数据丢失; 单个 workspace 可由 `open_workspace` 另行指定
+    pub in_memory: bool,
+    /// admin 接口的请求头 `X-Admin-Token` 须与之一致, None 时 admin 接口不可用
+    #[serde(skip_serializing)]
+    pub admin_token: Option<String>,
+}
+
+impl Default for MgDbConfig {
+    fn default() -> Self {
+        MgDbConfig {
+            data_root: PathBuf::from("MMG"),
+            file_name: format!("W_{WORKSPACE_PLACEHOLDER}.db"),
+            cache_size: None,
+            durability: MgDurability::default(),
+            read_only: false,
+            idle_timeout: None,
+            in_memory: false,
+            admin_token: None,
+        }
+    }
+}
+
+impl MgDbConfig {
+    /// 从命令行参数读取配置, 未指定的项使用默认值
+    /// `--data-root MMG --file-name W_{workspace}.db --cache-size 1048576 --durability immediate --idle-timeout 600 --read-only --in-memory --admin-token xxx`
+    pub fn from_args(args: &[String]) -> Result<MgDbConfig, MgError> {
+        let mut config = MgDbConfig::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--read-only" {
+                config.read_only = true;
+                continue;
+            }
+            if arg == "--in-memory" {
+                config.in_memory = true;
+                continue;
+            }
+            let Some(value) = iter.next() else {
+                return Err(MgError::Parse(format!("参数缺少值: {arg}")));
+            };
+            match arg.as_str() {
+                "--data-root" => { config.data_root = PathBuf::from(value); }
+                "--file-name" => { config.file_name = value.clone(); }
+                "--cache-size" => {
+                    let cache_size = value.parse().map_err(|_| MgError::Parse(format!("--cache-size 必须是字节数: {value}")))?;
+                    config.cache_size = Some(cache_size);
+                }
+                "--idle-timeout" => {
+                    let idle_timeout = value.parse().map_err(|_| MgError::Parse(format!("--idle-timeout 必须是秒数: {value}")))?;
+                    config.idle_timeout = Some(idle_timeout);
+                }
+                "--admin-token" => {
+                    if value.is_empty() {
+                        return Err(MgError::Parse("--admin-token 不能为空".to_string()));
+                    }
+                    config.admin_token = Some(value.clone());
+                }
+                "--durability" => {
+                    config.durability = match value.to_lowercase().as_str() {
+                        "none" => MgDurability::None,
+                        "eventual" => MgDurability::Eventual,
+                        "immediate" => MgDurability::Immediate,
+                        "paranoid" => MgDurability::Paranoid,
+                        _ => { return Err(MgError::Parse(format!("未知的 durability: {value}"))); }
+                    };
+                }
+                _ => { return Err(MgError::Parse(format!("未知的参数: {arg}"))); }
+            }
+        }
+        Ok(config)
+    }
+
+    /// workspace 对应的数据文件路径
+    pub fn db_path(&self, workspace_nanoid: &str) -> PathBuf {
+        self.data_root.join(self.file_name.replace(WORKSPACE_PLACEHOLDER, workspace_nanoid))
+    }
+
+    /// 只改变存储方式的配置, 用于按 workspace 选择内存或文件存储
+    pub fn with_in_memory(&self, in_memory: bool) -> MgDbConfig {
+        MgDbConfig {
+            in_memory,
+            ..self.clone()
+        }
+    }
+
+    /// 已打开数据库的 key, 内存 workspace 不对应文件
+    pub(crate) fn db_key(&self, workspace_nanoid: &str) -> String {
+        if self.in_memory {
+            return format!("memory:{workspace_nanoid}");
+        }
+        self.db_path(workspace_nanoid).to_string_lossy().to_string()
+    }
+
+    /// 按配置打开数据文件, 非只读时目录与文件不存在则创建
+    pub(crate) fn open_db(&self, workspace_nanoid: &str) -> Result<Database, MgError> {
+        if self.in_memory {
+            if self.read_only {
+                return Err(MgError::Parse(format!("内存 workspace 不能只读打开: {workspace_nanoid}")));
+            }
+            return Ok(Builder::new().create_with_backend(InMemoryBackend::new())?);
+        }
+        if !self.file_name.contains(WORKSPACE_PLACEHOLDER) {
+            return Err(MgError::Parse(format!("file_name 必须包含 {WORKSPACE_PLACEHOLDER}: {}", self.file_name)));
+        }
+        let db_path = self.db_path(workspace_nanoid);
+        let mut builder = Builder::new();
+        if let Some(cache_size) = self.cache_size {
+            builder.set_cache_size(cache_size);
+        }
+        if self.read_only {
+            return Ok(builder.open(db_path)?);
+        }
+        fs::create_dir_all(&self.data_root)?;
+        Ok(builder.create(db_path)?)
+    }
+}
+
+/// 设置 `get_mgdb` 使用的默认配置, 只影响之后新打开的 workspace
+pub fn set_default_config(config: MgDbConfig) -> Result<(), MgError> {
+    *DEFAULT_CONFIG.write()? = config;
+    Ok(())
+}
+
+pub fn default_config() -> Result<MgDbConfig, MgError> {
+    Ok(DEFAULT_CONFIG.read()?.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::minimongo::config::{MgDbConfig, MgDurability};
+    use crate::minimongo::error::MgError;
+
+    //cargo test test_config_from_args -- --show-output
+    #[test]
+    fn test_config_from_args() -> Result<(), Box<i32>> {
+        println!("准备测试: test_config_from_args");
+        let to_args = |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+
+        assert_eq!(MgDbConfig::from_args(&[]).unwrap(), MgDbConfig::default());
+        let config = MgDbConfig::from_args(&to_args(&["--data-root", "/tmp/mmg", "--file-name", "{workspace}.redb", "--cache-size", "1048576", "--durability", "Eventual", "--idle-timeout", "600", "--read-only", "--in-memory", "--admin-token", "secret"])).unwrap();
+        assert_eq!(config, MgDbConfig {
+            data_root: PathBuf::from("/tmp/mmg"),
+            file_name: "{workspace}.redb".to_string(),
+            cache_size: Some(1048576),
+            durability: MgDurability::Eventual,
+            read_only: true,
+            idle_timeout: Some(600),
+            in_memory: true,
+            admin_token: Some("secret".to_string()),
+        });
+        assert_eq!(config.db_path("w1"), PathBuf::from("/tmp/mmg/w1.redb"));
+        assert_eq!(MgDbConfig::default().db_path("w1"), PathBuf::from("MMG/W_w1.db"));
+
+        for args in [vec!["--cache-size", "1MB"], vec!["--durability", "fast"], vec!["--idle-timeout", "-1"], vec!["--data-root"], vec!["--admin-token", ""], vec!["--port", "80"]] {
+            assert!(matches!(MgDbConfig::from_args(&to_args(&args)), Err(MgError::Parse(_))), "{args:?}");
+        }
+        println!("测试完毕: test_config_from_args");
+        Ok(())
+    }
+}
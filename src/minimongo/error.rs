@@ -0,0 +1,65 @@
+use std::fmt::{Display, Formatter};
+use std::sync::PoisonError;
+use serde::{Deserialize, Serialize};
+
+/// minimongo 对外返回的错误
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MgError {
+    /// collection 不存在
+    UnknownCollection(String),
+    /// workspace 不存在
+    UnknownWorkspace(String),
+    /// 查询语句或请求参数无法解析
+    Parse(String),
+    /// redb 读写失败, 文件或事务错误
+    Storage(String),
+    /// 主键/唯一索引冲突, 索引已存在等约束错误
+    Constraint(String),
+    /// JSON 序列化与反序列化失败
+    Serialization(String),
+    /// 缺少或错误的 admin token
+    Unauthorized(String),
+}
+
+impl Display for MgError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MgError::UnknownCollection(collection_name) => write!(f, "collection不存在: {collection_name}"),
+            MgError::UnknownWorkspace(workspace_id) => write!(f, "workspace不存在: {workspace_id}"),
+            MgError::Parse(message) => write!(f, "解析失败: {message}"),
+            MgError::Storage(message) => write!(f, "存储错误: {message}"),
+            MgError::Constraint(message) => write!(f, "约束冲突: {message}"),
+            MgError::Serialization(message) => write!(f, "序列化失败: {message}"),
+            MgError::Unauthorized(message) => write!(f, "无权限: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for MgError {}
+
+macro_rules! impl_from_storage_error {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for MgError {
+                fn from(error: $error) -> Self {
+                    MgError::Storage(error.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_storage_error!(redb::Error, redb::DatabaseError, redb::TransactionError, redb::TableError, redb::StorageError, redb::CommitError, std::io::Error);
+
+impl From<serde_json::Error> for MgError {
+    fn from(error: serde_json::Error) -> Self {
+        MgError::Serialization(error.to_string())
+    }
+}
+
+/// 持有锁的线程 panic 后锁被标记为损坏
+impl<T> From<PoisonError<T>> for MgError {
+    fn from(error: PoisonError<T>) -> Self {
+        MgError::Storage(error.to_string())
+    }
+}
@@ -1,14 +1,18 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
-use redb::{MultimapTableDefinition, ReadableMultimapTable, ReadableTable, ReadableTableMetadata, ReadOnlyMultimapTable, ReadOnlyTable,
+use std::cmp::{Ordering, Reverse};
+use std::ops::Bound;
+use redb::{AccessGuard, MultimapTableDefinition, MultimapValue, ReadableMultimapTable, ReadableTable, ReadableTableMetadata, ReadOnlyMultimapTable, ReadOnlyTable,
            ReadTransaction, };
 use regex::Regex;
 use serde_json::Value;
 use serde::{Deserialize, Serialize};
 use crate::common::helper::hash_to_u32;
-use crate::minimongo::lazy_set::{LazySet, MAX_FULL_LEN};
-use crate::minimongo::minimongo::{Collection, MgDb};
-use crate::minimongo::query::{Condition, ConditionExpression, ConditionOperation, ConditionResult, Expression, ExpressionEntity, Field, MainAction, Number, OrderBy, OrderDirection, parse_query, Query, ReturnAction, ValueRef, Where};
-use crate::minimongo::query_helper::{MyF64, open_table_read};
+use crate::minimongo::expression::{apply_statement, array_query_root, compare_values, evaluate, evaluate_aggregate, Expr, get_value_by_path, get_values_by_path, parse_statement, remove_value_by_path, Scope, set_value_by_path, Statement, values_equal};
+use crate::minimongo::lazy_set::{LazySet, SetOperation};
+use crate::minimongo::minimongo::{Collection, compound_table_name, MgDb};
+use crate::minimongo::error::MgError;
+use crate::minimongo::query::{Condition, ConditionExpression, Expression, ExpressionEntity, Field, MainAction, Number, OrderBy, OrderDirection, OrderKey, parse_query, Query, ReturnAction, ValueRef, Where, WriteAction};
+use crate::minimongo::query_helper::{COMPOUND_TAG_END, COMPOUND_TAG_NUMBER, COMPOUND_TAG_STRING, compound_key_prefix_len, encode_compound_string_prefix, encode_compound_value, MyF64, open_table_read};
 
 #[derive(Debug, Serialize, Deserialize)]
 enum ValuePack {
@@ -25,9 +29,23 @@ struct QueryContext {
 
 pub const DEFAULT_LIMIT: usize = 10;
 
+impl Scope for QueryContext {
+    fn param(&self, name: &str) -> Option<Value> {
+        self.params.get(name).cloned()
+    }
+
+    fn variable(&self, name: &str) -> Option<Value> {
+        match self.variables.get(name)? {
+            ValuePack::List(list) => Some(Value::Array(list.clone())),
+            ValuePack::Value(value) => Some(value.clone()),
+            ValuePack::IdList(_) => None,
+        }
+    }
+}
+
 impl MgDb {
-    pub fn query_records(&self, query: &String, params: BTreeMap<String, Value>) -> BTreeMap<String, Value> {
-        let query_chain = parse_query(query.as_str());
+    pub fn query_records(&self, query: &String, params: BTreeMap<String, Value>) -> Result<BTreeMap<String, Value>, MgError> {
+        let query_chain = parse_query(query.as_str())?;
         // println!("{query_chain:#?}");
 
         let mut context = QueryContext {
@@ -41,13 +59,13 @@ impl MgDb {
         for query in query_chain {
             match query.main_action {
                 MainAction::CREATE { .. } => {
-                    self.execute_create(&query, &mut context);
+                    self.execute_create(&query, &mut context)?;
                 }
                 MainAction::SELECT { .. } => {
-                    self.execute_select(&query, &mut context);
+                    self.execute_select(&query, &mut context)?;
                 }
                 MainAction::GROUP { .. } => {
-                    self.execute_group(&query, &mut context);
+                    self.execute_group(&query, &mut context)?;
                 }
                 _ => {}
             }
@@ -82,34 +100,80 @@ impl MgDb {
         // println!("final_result: {final_result:#?}");
 
 
-        final_result
+        Ok(final_result)
     }
 
-    fn execute_create(&self, _query: &Query, _context: &mut QueryContext) {}
-    fn execute_select(&self, query: &Query, context: &mut QueryContext) {
-        if let MainAction::SELECT { target_collection, one } = &query.main_action {
-            let collection = self.get_collection(target_collection);
-            let read_txn = self.db.begin_read().unwrap();
-
-            let mut filtered_record_ids_option = None;
+    fn execute_create(&self, query: &Query, context: &mut QueryContext) -> Result<(), MgError> {
+        if let MainAction::CREATE { one, update_type, target_collection, value_ref } = &query.main_action {
+            let mut records = resolve_list_value_ref(value_ref, context);
+            if *one {
+                records.truncate(1);
+            }
 
-            if let Some(wheres) = &query.wheres {
-                let filtered_record_ids = filter_records(&collection, wheres, context, &read_txn);
-                // println!("filtered_record_ids: {filtered_record_ids:#?}");
-                filtered_record_ids_option = Some(filtered_record_ids);
+            //FIELD 写入前解析, 解析失败时不写入
+            let projection = get_projection(query)?;
+            if let WriteAction::UPDATE { expressions } = &query.write_action {
+                let statements = parse_statements(expressions)?;
+                for record in records.iter_mut() {
+                    for statement in &statements {
+                        apply_statement(record, statement, context);
+                    }
+                }
             }
 
-            let mut ordered_ids: Vec<u32> = if let Some(order_by) = &query.order_by {
-                order_record_ids(&collection, order_by, &read_txn, filtered_record_ids_option, context)
+            //有 record 未写入时整条语句失败, 不写入任何 record
+            let (created_ids, _update_result) = self.write_records(target_collection, records, update_type, true)?;
+
+            let collection = self.get_collection(target_collection)?;
+            let read_txn = self.db.begin_read()?;
+            export_data(created_ids, projection, collection, context, read_txn, &query.as_action, one)?;
+        }
+        Ok(())
+    }
+    fn execute_select(&self, query: &Query, context: &mut QueryContext) -> Result<(), MgError> {
+        if let MainAction::SELECT { target_collection, one } = &query.main_action {
+            let projection = get_projection(query)?;
+            //UPDATE/DELETE 先开启写事务再选取 records: 写事务提交前没有其他写入, 之后开启的读事务与写事务看到的数据一致
+            let write_txn_option = match &query.write_action {
+                WriteAction::NONE => None,
+                _ => Some(self.begin_write()?),
+            };
+            //UPDATE/DELETE 未指定 LIMIT 时作用于全部匹配的 records
+            let limit = match query.write_action {
+                WriteAction::NONE => DEFAULT_LIMIT,
+                _ => usize::MAX,
+            };
+            let collection = self.get_collection(target_collection)?;
+            let mut read_txn = self.db.begin_read()?;
+
+            //条件与排序都落在同一个复合索引上时, 直接按索引顺序取结果
+            let compound_ordered_ids_option = match (&query.wheres, &query.order_by) {
+                (Some(wheres), Some(order_by)) => compound_ordered_ids(&collection, wheres, order_by, limit, context, &read_txn)?,
+                _ => None,
+            };
+
+            let mut ordered_ids: Vec<u32> = if let Some(compound_ordered_ids) = compound_ordered_ids_option {
+                compound_ordered_ids
             } else {
-                match filtered_record_ids_option {
-                    None =>
-                        { default_record_ids(&collection, &read_txn) }
-                    Some(record_id_map) =>
-                        {
-                            let limit = DEFAULT_LIMIT;
-                            record_id_map.iter().take(limit).cloned().collect()
-                        }
+                let mut filtered_record_ids_option = None;
+
+                if let Some(wheres) = &query.wheres {
+                    let filtered_record_ids = filter_records(&collection, wheres, context, &read_txn)?;
+                    // println!("filtered_record_ids: {filtered_record_ids:#?}");
+                    filtered_record_ids_option = Some(filtered_record_ids);
+                }
+
+                if let Some(order_by) = &query.order_by {
+                    order_record_ids(&collection, order_by, limit, &read_txn, filtered_record_ids_option, context)?
+                } else {
+                    match filtered_record_ids_option {
+                        Some(record_id_map) if !record_id_map.is_complement() =>
+                            {
+                                record_id_map.ids.iter().take(limit).cloned().collect()
+                            }
+                        _ =>
+                            { default_record_ids(&collection, &read_txn, &filtered_record_ids_option, limit)? }
+                    }
                 }
             };
 
@@ -119,58 +183,323 @@ impl MgDb {
                 // println!("ordered_ids ONE: {ordered_ids:#?}");
             }
 
+            match (&query.write_action, write_txn_option) {
+                (WriteAction::DELETE, Some(write_txn)) => {
+                    drop(read_txn);
+                    let deleted_records = self.delete_records_by_id(write_txn, target_collection, &ordered_ids)?;
+                    export_records(deleted_records, projection, context, &query.as_action, one);
+                    return Ok(());
+                }
+                (WriteAction::UPDATE { expressions }, Some(write_txn)) => {
+                    drop(read_txn);
+                    let statements = parse_statements(expressions)?;
+                    ordered_ids = self.update_records_by_id(write_txn, target_collection, &ordered_ids, |record| {
+                        for statement in &statements {
+                            apply_statement(record, statement, context);
+                        }
+                    })?;
+                    read_txn = self.db.begin_read()?;
+                }
+                _ => {}
+            }
+
+            // println!("projection: {projection:#?}");
+
+            export_data(ordered_ids, projection, collection, context, read_txn, &query.as_action, one)?;
+        }
+        Ok(())
+    }
+
+    fn execute_group(&self, query: &Query, context: &mut QueryContext) -> Result<(), MgError> {
+        if let MainAction::GROUP { target_collection, by } = &query.main_action {
+            //GROUP ... DELETE 同样先开启写事务再分组
+            let write_txn_option = match &query.write_action {
+                WriteAction::DELETE => Some(self.begin_write()?),
+                _ => None,
+            };
+            let collection = self.get_collection(target_collection)?;
+            let read_txn = self.db.begin_read()?;
+
+            let mut filtered_record_ids_option = None;
+            if let Some(wheres) = &query.wheres {
+                let filtered_record_ids = filter_records(&collection, wheres, context, &read_txn)?;
+                filtered_record_ids_option = Some(filtered_record_ids);
+            }
+
+            let groups = group_record_ids(&collection, by, &read_txn, &filtered_record_ids_option)?;
+            // println!("groups: {groups:#?}");
+
+            let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn)?;
+            let mut rows = Vec::new();
+            let mut grouped_record_ids = Vec::new();
+            for (key, record_ids) in groups {
+                let mut records = Vec::new();
+                for &record_id in &record_ids {
+                    if let Some(record_lock) = collection_table.get(record_id)? {
+                        let record: Value = serde_json::from_str(record_lock.value().as_str())?;
+                        records.push(record);
+                    }
+                }
+                //优先使用record中的原始值, f64索引中的key会丢失整数类型
+                let key = records.first().and_then(|r| get_value_by_path(r, by)).cloned().unwrap_or(key);
+                let row = build_group_row(query, by, key, &records, context)?;
+
+                if let Some(having) = &query.having {
+                    if !match_where(&row, having, context) {
+                        continue;
+                    }
+                }
+                rows.push(row);
+                grouped_record_ids.extend(record_ids);
+            }
+            drop(collection_table);
+            drop(read_txn);
+
+            //GROUP ... DELETE 删除满足 HAVING 的分组中的全部 records
+            if let Some(write_txn) = write_txn_option {
+                self.delete_records_by_id(write_txn, target_collection, &grouped_record_ids)?;
+            }
 
-            let mut field_name_list = Vec::new();
+            if let Some(order_by) = &query.order_by {
+                let (skip, limit) = resolve_skip_limit(order_by, DEFAULT_LIMIT, context)?;
+                let keys: Vec<(&str, &OrderDirection)> = order_by.keys.iter().map(|key| (key.field.as_str(), &key.order_direction)).collect();
+                rows.sort_by(|a, b| compare_by_order_keys(a, b, &keys));
+                rows = paginate(&rows, skip, limit);
+            }
+
+            context.variables.insert(query.as_action.clone(), ValuePack::List(rows));
+        }
+        Ok(())
+    }
+}
 
-            if let Some(field_define) = &query.field {
-                for field in &field_define.fields {
-                    match field {
-                        Field::Name(field_name) => {
-                            field_name_list.push(field_name.clone());
+/// 按 by 字段分组, 有索引时直接遍历索引表, 否则扫描 collection
+fn group_record_ids(collection: &Collection, by: &String, read_txn: &ReadTransaction, filtered_record_ids_option: &Option<LazySet>) -> Result<Vec<(Value, Vec<u32>)>, MgError> {
+    let is_selected = |record_id: &u32| is_selected(filtered_record_ids_option, record_id);
+    let mut groups: Vec<(Value, Vec<u32>)> = Vec::new();
+
+    let by = index_path(collection, by);
+    let by_field_type = check_field_type(collection, by);
+    match by_field_type {
+        ConditionFieldType::PrimaryKey | ConditionFieldType::StringUnique => {
+            let collection_name_index = if matches!(by_field_type, ConditionFieldType::PrimaryKey) {
+                format!("{}@primary", collection.collection_name)
+            } else {
+                format!("{}@stringU@{}", collection.collection_name, by)
+            };
+            let index_table = open_table_read::<&str, u32>(&collection_name_index, read_txn)?;
+            for kv in index_table.iter()? {
+                let (key_lock, value_lock) = kv?;
+                let record_id = value_lock.value();
+                if is_selected(&record_id) {
+                    groups.push((Value::from(key_lock.value()), vec![record_id]));
+                }
+            }
+        }
+        ConditionFieldType::String => {
+            let collection_name_index = format!("{}@string@{}", collection.collection_name, by);
+            let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
+            let index_table = read_txn.open_multimap_table(index_table_define)?;
+            for kv in index_table.iter()? {
+                let (key_lock, values) = kv?;
+                let record_ids = values.map(|v| v.map(|id| id.value())).collect::<Result<Vec<u32>, _>>()?;
+                let record_ids: Vec<u32> = record_ids.into_iter().filter(|id| is_selected(id)).collect();
+                if !record_ids.is_empty() {
+                    groups.push((Value::from(key_lock.value()), record_ids));
+                }
+            }
+        }
+        ConditionFieldType::F64 => {
+            let collection_name_index = format!("{}@f64@{}", collection.collection_name, by);
+            let index_table = open_table_read::<(MyF64, u32), ()>(&collection_name_index, read_txn)?;
+            let mut current_key: Option<f64> = None;
+            for kv in index_table.iter()? {
+                let (key, record_id) = {
+                    let key_lock = kv?.0;
+                    let key = key_lock.value();
+                    (key.0.0, key.1)
+                };
+                if !is_selected(&record_id) {
+                    continue;
+                }
+                match groups.last_mut() {
+                    Some(group) if current_key == Some(key) => { group.1.push(record_id); }
+                    _ => {
+                        current_key = Some(key);
+                        groups.push((Value::from(key), vec![record_id]));
+                    }
+                }
+            }
+        }
+        ConditionFieldType::NoIndex => {
+            let mut group_map: BTreeMap<String, (Value, Vec<u32>)> = BTreeMap::new();
+            let collection_table = open_table_read::<u32, String>(&collection.collection_name, read_txn)?;
+            for kv in collection_table.iter()? {
+                let (key_lock, value_lock) = kv?;
+                let record_id = key_lock.value();
+                if !is_selected(&record_id) {
+                    continue;
+                }
+                let record: Value = serde_json::from_str(value_lock.value().as_str())?;
+                if let Some(key) = get_value_by_path(&record, by) {
+                    if key.is_null() {
+                        continue;
+                    }
+                    let key_str = serde_json::to_string(key).unwrap_or_default();
+                    group_map.entry(key_str).or_insert_with(|| (key.clone(), Vec::new())).1.push(record_id);
+                }
+            }
+            groups = group_map.into_values().collect();
+            groups.sort_by(|a, b| compare_values(&a.0, &b.0));
+        }
+    }
+    Ok(groups)
+}
+
+/// 生成分组结果行: FIELD 中的字段名取组内第一条 record 的值, 表达式按聚合函数求值
+fn build_group_row(query: &Query, by: &String, key: Value, records: &[Value], context: &QueryContext) -> Result<Value, MgError> {
+    let mut row = Value::Object(serde_json::Map::new());
+    row[by.as_str()] = key;
+
+    match &query.field {
+        None => {
+            row["count"] = Value::from(records.len());
+        }
+        Some(field_define) => {
+            for field in &field_define.fields {
+                match field {
+                    Field::Name(field_name) => {
+                        if field_name != by {
+                            let value = records.first().and_then(|r| get_value_by_path(r, field_name)).cloned().unwrap_or(Value::Null);
+                            row[field_name.as_str()] = value;
+                        }
+                    }
+                    Field::Expression(expression) => {
+                        if expression.string == "*" {
+                            continue;
                         }
-                        Field::Expression(Expression { string }) => {
-                            if string == "*" {
-                                field_name_list.push("ALL".to_string());
+                        match parse_statement(expression.string.as_str()) {
+                            Ok(Statement::Assign { target, expr }) => {
+                                let value = evaluate_aggregate(&expr, &row, records, context);
+                                row[target.as_str()] = value;
+                            }
+                            Ok(Statement::Eval(expr)) => {
+                                let value = evaluate_aggregate(&expr, &row, records, context);
+                                row[expression.string.as_str()] = value;
                             }
+                            Err(message) => { return Err(MgError::Parse(format!("表达式解析失败: {} -> {message}", expression.string))); }
                         }
                     }
                 }
-            } else {
-                field_name_list.push("ALL".to_string());
             }
+        }
+    }
+    Ok(row)
+}
+
+/// FIELD 定义: `*`, 字段名, `a=b+2c` 计算字段, `age.hide()` 隐藏字段
+#[derive(Debug, Default)]
+struct Projection {
+    all: bool,
+    field_names: Vec<String>,
+    hidden_fields: Vec<String>,
+    computed_fields: Vec<(String, Expr)>,
+}
 
-            // println!("field_name_list: {field_name_list:#?}");
+fn get_projection(query: &Query) -> Result<Projection, MgError> {
+    let mut projection = Projection::default();
 
-            export_data(ordered_ids, field_name_list, collection, context, read_txn, &query.as_action, one);
+    if let Some(field_define) = &query.field {
+        for field in &field_define.fields {
+            match field {
+                Field::Name(field_name) => {
+                    projection.field_names.push(field_name.clone());
+                }
+                Field::Expression(Expression { string }) => {
+                    if string == "*" {
+                        projection.all = true;
+                        continue;
+                    }
+                    match parse_statement(string.as_str()) {
+                        Ok(Statement::Assign { target, expr }) => {
+                            projection.computed_fields.push((target, expr));
+                        }
+                        Ok(Statement::Eval(Expr::Method { target, name, .. })) if name == "hide" => {
+                            if let Expr::Field(path) = *target {
+                                projection.hidden_fields.push(path);
+                            }
+                        }
+                        Ok(Statement::Eval(expr)) => {
+                            //数组子查询的结果覆盖原字段
+                            let field_name = array_query_root(&expr).unwrap_or(string.as_str()).to_string();
+                            projection.computed_fields.push((field_name, expr));
+                        }
+                        Err(message) => { return Err(MgError::Parse(format!("表达式解析失败: {string} -> {message}"))); }
+                    }
+                }
+            }
         }
+    } else {
+        projection.all = true;
     }
+    Ok(projection)
+}
 
-    fn execute_group(&self, _query: &Query, _context: &mut QueryContext) {}
+fn parse_statements(expressions: &[Expression]) -> Result<Vec<Statement>, MgError> {
+    let mut statements = Vec::new();
+    for expression in expressions {
+        match parse_statement(expression.string.as_str()) {
+            Ok(statement) => { statements.push(statement); }
+            Err(message) => { return Err(MgError::Parse(format!("表达式解析失败: {} -> {message}", expression.string))); }
+        }
+    }
+    Ok(statements)
 }
 
-fn export_data(ordered_ids: Vec<u32>, field_name_list: Vec<String>, collection: Collection, context: &mut QueryContext, read_txn: ReadTransaction, as_action: &String, one: &bool) {
-    let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn);
-    let mut results = Vec::new();
+fn export_data(ordered_ids: Vec<u32>, projection: Projection, collection: Collection, context: &mut QueryContext, read_txn: ReadTransaction, as_action: &String, one: &bool) -> Result<(), MgError> {
+    let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn)?;
+    let mut records = Vec::new();
     for id in ordered_ids {
-        let record_option = collection_table.get(id).unwrap();
+        let record_option = collection_table.get(id)?;
         if let Some(record_lock) = record_option {
             let record_str = record_lock.value();
-            let mut record: Value = serde_json::from_str(record_str.as_str()).unwrap();
-            if let Value::Object(ref mut record_map) = record {
-                if field_name_list.contains(&"ALL".to_string()) {
-                    results.push(record);
-                } else {
-                    let mut sub_record = serde_json::Map::new();
+            let record: Value = serde_json::from_str(record_str.as_str())?;
+            records.push(record);
+        }
+    }
+    export_records(records, projection, context, as_action, one);
+    Ok(())
+}
 
-                    for field_name in &field_name_list {
-                        if let Some(value) = record_map.remove(field_name) {
-                            sub_record.insert(field_name.clone(), value);
-                        }
-                    }
-                    results.push(Value::from(sub_record));
+fn export_records(records: Vec<Value>, projection: Projection, context: &mut QueryContext, as_action: &String, one: &bool) {
+    let mut results = Vec::new();
+    for mut record in records {
+        if !record.is_object() {
+            continue;
+        }
+        //计算字段基于完整的 record 求值
+        let computed_values: Vec<Value> = projection.computed_fields.iter()
+            .map(|(_, expr)| evaluate(expr, &record, context)).collect();
+
+        let mut result = if projection.all {
+            for hidden_field in &projection.hidden_fields {
+                remove_value_by_path(&mut record, hidden_field);
+            }
+            record
+        } else {
+            let mut sub_record = Value::Object(serde_json::Map::new());
+            for field_name in &projection.field_names {
+                if let Some(value) = remove_value_by_path(&mut record, field_name) {
+                    set_value_by_path(&mut sub_record, field_name, value);
                 }
             }
+            sub_record
+        };
+
+        for ((field_name, _), value) in projection.computed_fields.iter().zip(computed_values) {
+            set_value_by_path(&mut result, field_name, value);
         }
+        results.push(result);
     }
 
     if *one {
@@ -186,15 +515,15 @@ fn export_data(ordered_ids: Vec<u32>, field_name_list: Vec<String>, collection:
 }
 
 impl MgDb {
-    fn get_collection(&self, collection_name: &String) -> Collection {
+    pub(crate) fn get_collection(&self, collection_name: &String) -> Result<Collection, MgError> {
         let cloned_collection;
         {
-            let collection_map_lock = self.collection_map.read().unwrap();
-            let collection = collection_map_lock.get(collection_name).unwrap();
+            let collection_map_lock = self.collection_map.read()?;
+            let collection = collection_map_lock.get(collection_name).ok_or_else(|| MgError::UnknownCollection(collection_name.clone()))?;
             cloned_collection = collection.clone();
             drop(collection_map_lock);
         }
-        cloned_collection
+        Ok(cloned_collection)
     }
 }
 
@@ -205,242 +534,655 @@ where
     vec.iter().skip(skip).take(limit).cloned().collect()
 }
 
-fn default_record_ids(collection: &Collection, read_txn: &ReadTransaction) -> Vec<u32> {
-    let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn);
-    let table_iter = collection_table.iter().unwrap();
-    let limit = DEFAULT_LIMIT;
-    let lock_ids = table_iter.take(limit);
-    let ids: Vec<u32> = lock_ids.map(|id_result| id_result.unwrap().0.value()).collect();
-    ids
+/// 按 id 顺序遍历 collection, 补集条件时跳过被排除的 id
+fn default_record_ids(collection: &Collection, read_txn: &ReadTransaction, filtered_record_ids_option: &Option<LazySet>, limit: usize) -> Result<Vec<u32>, MgError> {
+    let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn)?;
+    let table_iter = collection_table.iter()?;
+    let all_ids = table_iter.map(|id_result| id_result.map(|kv| kv.0.value()));
+    let ids = all_ids.filter(|id_result| id_result.as_ref().map_or(true, |id| is_selected(filtered_record_ids_option, id))).take(limit).collect::<Result<Vec<u32>, _>>()?;
+    Ok(ids)
 }
 
-fn order_record_ids(collection: &Collection, order_by: &OrderBy, read_txn: &ReadTransaction, filtered_record_ids_option: Option<BTreeSet<u32>>, context: &mut QueryContext) -> Vec<u32> {
+fn is_selected(filtered_record_ids_option: &Option<LazySet>, record_id: &u32) -> bool {
+    match filtered_record_ids_option {
+        None => true,
+        Some(filtered_record_ids) => filtered_record_ids.contains(record_id),
+    }
+}
+
+/// 解析 SKIP 与 LIMIT, 未指定 LIMIT 时使用 default_limit
+fn resolve_skip_limit(order_by: &OrderBy, default_limit: usize, context: &QueryContext) -> Result<(usize, usize), MgError> {
     let mut skip: usize = 0;
-    let mut limit: usize = DEFAULT_LIMIT;
+    let mut limit: usize = default_limit;
     let skip_value = resolve_one_value_ref(&order_by.skip, context);
     if let Value::Number(skip_number) = skip_value {
-        skip = skip_number.as_u64().unwrap() as usize;
+        skip = skip_number.as_u64().ok_or_else(|| MgError::Parse(format!("SKIP 必须是非负整数: {skip_number}")))? as usize;
     }
     let limit_value = resolve_one_value_ref(&order_by.limit, context);
     if let Value::Number(limit_number) = limit_value {
-        limit = limit_number.as_u64().unwrap() as usize;
+        limit = limit_number.as_u64().ok_or_else(|| MgError::Parse(format!("LIMIT 必须是非负整数: {limit_number}")))? as usize;
     }
+    Ok((skip, limit))
+}
 
-    let order_field_type = check_field_type(collection, &order_by.field);
-    let ordered_ids: Vec<u32> = match order_field_type {
-        ConditionFieldType::F64 => {
-            let field_id = hash_to_u32(&order_by.field);
-
-            match filtered_record_ids_option {
-                None => {
-                    let collection_name_index = format!("{}@f64@{}", collection.collection_name, order_by.field);
-                    let index_table = open_table_read::<(MyF64, u32), ()>(&collection_name_index, &read_txn);
-                    let index_table_iter = index_table.iter().unwrap();
-                    let ordered_ids = match order_by.order_direction {
-                        OrderDirection::ASC => {
-                            let lock_ids = index_table_iter.skip(skip).take(limit);
-                            lock_ids.map(|id_result| id_result.unwrap().0.value().1).collect()
-                        }
-                        OrderDirection::DESC => {
-                            let lock_ids = index_table_iter.rev().skip(skip).take(limit);
-                            lock_ids.map(|id_result| id_result.unwrap().0.value().1).collect()
-                        }
-                    };
-                    ordered_ids
-                }
-                Some(record_ids) => {
-                    let mut results = Vec::new();
-                    let collection_name_f64 = format!("{}#f64#", collection.collection_name);
-                    let f64_table = open_table_read::<(u32, u32), f64>(&collection_name_f64, &read_txn);
-                    for &record_id in &record_ids {
-                        if let Some(value_lock) = f64_table.get((record_id, field_id)).unwrap() {
-                            let value = value_lock.value();
-                            results.push((value, record_id));
+fn order_record_ids(collection: &Collection, order_by: &OrderBy, default_limit: usize, read_txn: &ReadTransaction, filtered_record_ids_option: Option<LazySet>, context: &mut QueryContext) -> Result<Vec<u32>, MgError> {
+    let (skip, limit) = resolve_skip_limit(order_by, default_limit, context)?;
+    let Some(first_key) = order_by.keys.first() else {
+        return default_record_ids(collection, read_txn, &filtered_record_ids_option, limit);
+    };
+    let selected = |record_id: &u32| is_selected(&filtered_record_ids_option, record_id);
+
+    let ordered_ids: Vec<u32> = match &filtered_record_ids_option {
+        //已有筛选结果: 单个 f64 排序字段从 #f64# 表取值排序, 其余情况读取 record 排序
+        Some(record_ids) if !record_ids.is_complement() => {
+            let order_field_type = check_field_type(collection, &first_key.field);
+            if let (ConditionFieldType::F64, 1) = (order_field_type, order_by.keys.len()) {
+                let order_field = index_path(collection, &first_key.field);
+                let field_id = hash_to_u32(order_field);
+                //#f64# 只存数字值, 数组路径存最小元素; 降序的数组路径与没有数字值的 record 读取后取排序值
+                let use_stored = !order_field.contains("[]") || matches!(first_key.order_direction, OrderDirection::ASC);
+                let mut results: Vec<(Value, u32)> = Vec::new();
+                let mut unresolved_ids = Vec::new();
+                let collection_name_f64 = format!("{}#f64#", collection.collection_name);
+                let f64_table = open_table_read::<(u32, u32), f64>(&collection_name_f64, &read_txn)?;
+                for &record_id in &record_ids.ids {
+                    match f64_table.get((record_id, field_id))? {
+                        Some(value_lock) if use_stored => { results.push((Value::from(value_lock.value()), record_id)); }
+                        _ => { unresolved_ids.push(record_id); }
+                    }
+                }
+                if !unresolved_ids.is_empty() {
+                    let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn)?;
+                    for record_id in unresolved_ids {
+                        if let Some(record_lock) = collection_table.get(record_id)? {
+                            let record: Value = serde_json::from_str(record_lock.value().as_str()).unwrap_or(Value::Null);
+                            results.push((order_value(&record, order_field, &first_key.order_direction).clone(), record_id));
                         }
                     }
-                    match order_by.order_direction {
-                        OrderDirection::ASC => {
-                            results.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                }
+                match first_key.order_direction {
+                    OrderDirection::ASC => {
+                        results.sort_by(|a, b| compare_values(&a.0, &b.0).then(a.1.cmp(&b.1)));
+                    }
+                    OrderDirection::DESC => {
+                        results.sort_by(|a, b| compare_values(&b.0, &a.0).then(a.1.cmp(&b.1)));
+                    }
+                }
+                let all_ordered_ids: Vec<u32> = results.iter().map(|(_value, id)| *id).collect();
+                paginate(&all_ordered_ids, skip, limit)
+            } else {
+                let all_ordered_ids = sort_record_ids_in_memory(collection, &order_by.keys, read_txn, record_ids.ids.iter().cloned())?;
+                paginate(&all_ordered_ids, skip, limit)
+            }
+        }
+        //无条件或补集条件: 索引包含全部 records 时顺序遍历第一个排序字段的索引, 跳过被排除的 id, 否则读取 records 排序
+        _ => {
+            let entries_option = if index_covers_all(collection, &first_key.field, read_txn)? {
+                index_entries(collection, &first_key.field, read_txn, &first_key.order_direction)?
+            } else {
+                None
+            };
+            match entries_option {
+                Some(entries) if order_by.keys.len() == 1 => {
+                    let record_ids = entries.map(|entry| entry.map(|(_key, record_id)| record_id));
+                    record_ids.filter(|id_result| id_result.as_ref().map_or(true, selected)).skip(skip).take(limit).collect::<Result<Vec<u32>, _>>()?
+                }
+                Some(entries) => {
+                    //多个排序字段: 取够 skip + limit 条并读完最后一个值相同的分组, 再按全部字段排序
+                    let needed = skip.saturating_add(limit);
+                    let mut record_ids = Vec::new();
+                    let mut last_key: Option<Value> = None;
+                    for entry in entries {
+                        let (key, record_id) = entry?;
+                        if !selected(&record_id) {
+                            continue;
                         }
-                        OrderDirection::DESC => {
-                            results.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+                        if record_ids.len() >= needed && last_key.as_ref() != Some(&key) {
+                            break;
                         }
+                        last_key = Some(key);
+                        record_ids.push(record_id);
                     }
-                    let all_ordered_ids: Vec<u32> = results.iter().map(|(_n, id)| *id).collect();
-                    let ordered_ids = paginate(&all_ordered_ids, skip, limit);
-                    ordered_ids
+                    let all_ordered_ids = sort_record_ids_in_memory(collection, &order_by.keys, read_txn, record_ids.into_iter())?;
+                    paginate(&all_ordered_ids, skip, limit)
+                }
+                None => {
+                    let all_ids = default_record_ids(collection, read_txn, &filtered_record_ids_option, usize::MAX)?;
+                    let all_ordered_ids = sort_record_ids_in_memory(collection, &order_by.keys, read_txn, all_ids.into_iter())?;
+                    paginate(&all_ordered_ids, skip, limit)
                 }
             }
         }
-        _ => { //todo order by string
-            Vec::new()
-        }
     };
-    ordered_ids
+    Ok(ordered_ids)
 }
 
-fn filter_records(collection: &Collection, wheres: &Where, context: &mut QueryContext, read_txn: &ReadTransaction) -> BTreeSet<u32> {
-    let mut condition_results = Vec::new();
+/// 按索引顺序遍历的 (字段值, record id)
+type IndexEntries = Box<dyn Iterator<Item=Result<(Value, u32), MgError>>>;
 
-    for condition in &wheres.conditions {
-        match condition {
-            Condition::OPERATION(operation) => {
-                let operation_1 = operation.clone();
-                let condition_result = ConditionResult::OPERATION(operation_1);
-                condition_results.push(condition_result);
-            }
-            Condition::EXPRESSION(expression) => {
-                let ids = filter_records_by_condition(collection, expression, context, read_txn);
-                let condition_result = ConditionResult::IDS(ids);
-                condition_results.push(condition_result);
-            }
+/// 按索引顺序返回 (字段值, record id), 同一字段值下 id 升序; 字段无索引时返回 None
+fn index_entries(collection: &Collection, field: &String, read_txn: &ReadTransaction, order_direction: &OrderDirection) -> Result<Option<IndexEntries>, MgError> {
+    let is_desc = matches!(order_direction, OrderDirection::DESC);
+    let field = index_path(collection, field);
+    let entries: IndexEntries = match check_field_type(collection, field) {
+        ConditionFieldType::F64 => {
+            let collection_name_index = format!("{}@f64@{}", collection.collection_name, field);
+            let index_table = open_table_read::<(MyF64, u32), ()>(&collection_name_index, &read_txn)?;
+            let index_table_iter = index_table.range::<(MyF64, u32)>(..)?;
+            let entries = index_table_iter.map(|id_result| {
+                let (MyF64(value), record_id) = id_result?.0.value();
+                Ok((Value::from(value), record_id))
+            });
+            if is_desc { Box::new(ascending_ids_within_equal_keys(entries.rev())) } else { Box::new(entries) }
         }
-    }
-    // println!("判断 condition_results: {condition_results:#?}");
+        order_field_type @ (ConditionFieldType::PrimaryKey | ConditionFieldType::StringUnique) => {
+            let collection_name_index = match order_field_type {
+                ConditionFieldType::PrimaryKey => format!("{}@primary", collection.collection_name),
+                _ => format!("{}@stringU@{}", collection.collection_name, field),
+            };
+            let index_table = open_table_read::<&str, u32>(&collection_name_index, &read_txn)?;
+            let index_table_iter = index_table.range::<&str>(..)?;
+            let entries = index_table_iter.map(|id_result| {
+                let (key_lock, value_lock) = id_result?;
+                Ok((Value::from(key_lock.value()), value_lock.value()))
+            });
+            if is_desc { Box::new(entries.rev()) } else { Box::new(entries) }
+        }
+        ConditionFieldType::String => {
+            let collection_name_index = format!("{}@string@{}", collection.collection_name, field);
+            let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
+            let index_table = read_txn.open_multimap_table(index_table_define)?;
+            let index_table_iter = index_table.range::<&str>(..)?;
+            let expand = |kv: redb::Result<(AccessGuard<'static, &'static str>, MultimapValue<'static, u32>)>| -> Vec<Result<(Value, u32), MgError>> {
+                let expanded = kv.and_then(|(key_lock, values)| {
+                    let record_ids = values.map(|id_result| id_result.map(|id| id.value())).collect::<Result<Vec<u32>, _>>()?;
+                    Ok((Value::from(key_lock.value()), record_ids))
+                });
+                match expanded {
+                    Ok((key, record_ids)) => {
+                        record_ids.into_iter().map(|record_id| Ok((key.clone(), record_id))).collect()
+                    }
+                    Err(error) => vec![Err(error.into())],
+                }
+            };
+            if is_desc { Box::new(index_table_iter.rev().flat_map(expand)) } else { Box::new(index_table_iter.flat_map(expand)) }
+        }
+        ConditionFieldType::NoIndex => { return Ok(None); }
+    };
+    Ok(Some(entries))
+}
 
-    let record_ids = resolve_condition_results_if(condition_results, read_txn, collection);
+/// 把按 key 排好序的 (key, record id) 中 key 相同的连续项改为 id 升序, 各种排序方式在排序值相同时顺序一致
+fn ascending_ids_within_equal_keys<K: Clone + PartialEq + 'static>(entries: impl Iterator<Item=Result<(K, u32), MgError>> + 'static) -> impl Iterator<Item=Result<(K, u32), MgError>> {
+    let mut entries = entries.peekable();
+    //同一 key 的分组按 id 降序存放, 从末尾取出
+    let mut group: Vec<(K, u32)> = Vec::new();
+    std::iter::from_fn(move || {
+        if group.is_empty() {
+            let (key, record_id) = match entries.next()? {
+                Ok(entry) => entry,
+                Err(error) => { return Some(Err(error)); }
+            };
+            group.push((key.clone(), record_id));
+            while let Some(Ok(entry)) = entries.next_if(|entry| matches!(entry, Ok((next_key, _)) if *next_key == key)) {
+                group.push(entry);
+            }
+            group.sort_by_key(|(_key, record_id)| Reverse(*record_id));
+        }
+        group.pop().map(Ok)
+    })
+}
 
-    // println!("合并结果: {record_ids:#?}");
+/// 索引是否包含全部 records: 每条 record 在非数组路径的索引中最多一项, 数量相同即全部包含; 数组路径无法按数量判断, 视为不包含
+fn index_covers_all(collection: &Collection, field: &String, read_txn: &ReadTransaction) -> Result<bool, MgError> {
+    let field = index_path(collection, field);
+    if field.contains("[]") {
+        return Ok(false);
+    }
+    let index_len = match check_field_type(collection, field) {
+        ConditionFieldType::PrimaryKey => open_table_read::<&str, u32>(&format!("{}@primary", collection.collection_name), read_txn)?.len()?,
+        ConditionFieldType::StringUnique => open_table_read::<&str, u32>(&format!("{}@stringU@{}", collection.collection_name, field), read_txn)?.len()?,
+        ConditionFieldType::String => {
+            let collection_name_index = format!("{}@string@{}", collection.collection_name, field);
+            let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
+            read_txn.open_multimap_table(index_table_define)?.len()?
+        }
+        ConditionFieldType::F64 => open_table_read::<(MyF64, u32), ()>(&format!("{}@f64@{}", collection.collection_name, field), read_txn)?.len()?,
+        ConditionFieldType::NoIndex => { return Ok(false); }
+    };
+    let collection_len = open_table_read::<u32, String>(&collection.collection_name, read_txn)?.len()?;
+    Ok(index_len == collection_len)
+}
 
-    record_ids
+/// 读取 records 后按全部排序字段排序, 全部相同时按 record id 升序
+fn sort_record_ids_in_memory(collection: &Collection, keys: &[OrderKey], read_txn: &ReadTransaction, record_ids: impl Iterator<Item=u32>) -> Result<Vec<u32>, MgError> {
+    let collection_table = open_table_read::<u32, String>(&collection.collection_name, read_txn)?;
+    let mut records: Vec<(Value, u32)> = Vec::new();
+    for record_id in record_ids {
+        if let Some(record_lock) = collection_table.get(record_id)? {
+            let record: Value = serde_json::from_str(record_lock.value().as_str()).unwrap_or(Value::Null);
+            records.push((record, record_id));
+        }
+    }
+    //与索引排序一致, 字段按索引路径取值, 如 tags 对应索引 tags[]
+    let keys: Vec<(&str, &OrderDirection)> = keys.iter().map(|key| (index_path(collection, &key.field).as_str(), &key.order_direction)).collect();
+    records.sort_by(|a, b| compare_by_order_keys(&a.0, &b.0, &keys).then(a.1.cmp(&b.1)));
+    Ok(records.into_iter().map(|(_record, record_id)| record_id).collect())
 }
 
-fn resolve_condition_results_recursive(condition_results: &[ConditionResult]) -> LazySet {
-    if condition_results.len() == 1 {
-        if let ConditionResult::IDS(ids) = &condition_results[0] {
-            return LazySet::new(ids.clone());
-        } else {
-            return LazySet::new(BTreeSet::new());
+fn compare_by_order_keys(a: &Value, b: &Value, keys: &[(&str, &OrderDirection)]) -> Ordering {
+    for (field, order_direction) in keys {
+        let ordering = match order_direction {
+            OrderDirection::ASC => compare_values(order_value(a, field, order_direction), order_value(b, field, order_direction)),
+            OrderDirection::DESC => compare_values(order_value(b, field, order_direction), order_value(a, field, order_direction)),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
         }
     }
+    Ordering::Equal
+}
 
-    let mut min_priority = u64::MAX;
-    let mut min_priority_index = None;
+/// 排序值, 字段不存在时为 Null; 数组路径 (如 tags[]) 与索引一致, 升序取最小元素, 降序取最大元素
+fn order_value<'v>(record: &'v Value, field: &str, order_direction: &OrderDirection) -> &'v Value {
+    if !field.contains("[]") {
+        return get_value_by_path(record, field).unwrap_or(&Value::Null);
+    }
+    let values = get_values_by_path(record, field).into_iter();
+    let value = match order_direction {
+        OrderDirection::ASC => values.min_by(|a, b| compare_values(a, b)),
+        OrderDirection::DESC => values.max_by(|a, b| compare_values(a, b)),
+    };
+    value.unwrap_or(&Value::Null)
+}
 
-    for (i, result) in condition_results.iter().enumerate() {
-        if let ConditionResult::OPERATION(operation) = result {
-            let priority = match operation {
-                ConditionOperation::NOT(p) | ConditionOperation::OR(p) | ConditionOperation::AND(p)
-                => *p,
+fn filter_records(collection: &Collection, wheres: &Where, context: &mut QueryContext, read_txn: &ReadTransaction) -> Result<LazySet, MgError> {
+    let record_ids = filter_records_by_condition_tree(collection, &wheres.condition, context, read_txn, &None)?;
+
+    // println!("合并结果: {record_ids:#?}");
+
+    Ok(record_ids)
+}
+
+/// 递归计算条件树, candidate_ids 为上层 AND 中已算出的结果, 结果只需在候选范围内正确
+fn filter_records_by_condition_tree(collection: &Collection, condition: &Condition, context: &mut QueryContext, read_txn: &ReadTransaction, candidate_ids: &Option<BTreeSet<u32>>) -> Result<LazySet, MgError> {
+    match condition {
+        Condition::EXPRESSION(expression) => {
+            let ids = match check_field_type(collection, &expression.target_field) {
+                ConditionFieldType::NoIndex => filter_id_by_scan(collection, expression, context, read_txn, candidate_ids)?,
+                _ => filter_records_by_condition(collection, expression, context, read_txn)?,
             };
-            if priority < min_priority {
-                min_priority = priority;
-                min_priority_index = Some(i);
+            Ok(LazySet::new(ids))
+        }
+        Condition::NOT(inner) => {
+            Ok(filter_records_by_condition_tree(collection, inner, context, read_txn, candidate_ids)?.not())
+        }
+        Condition::AND(children) => {
+            let mut children: Vec<&Condition> = children.iter().collect();
+            let mut result = LazySet::complement(BTreeSet::new());
+            //复合索引能覆盖多个子条件, 或覆盖原本需要扫描的子条件时, 先用一次范围扫描代替
+            if let Some(compound_scan) = plan_compound_scan(collection, &children, context) {
+                let used_children: Vec<&Condition> = compound_scan.used_children.iter().map(|i| children[*i]).collect();
+                if used_children.len() > 1 || used_children.iter().any(|child| needs_scan(collection, child)) {
+                    result = LazySet::new(compound_scan_entries(collection, &compound_scan, read_txn, false)?.map(|entry| entry.map(|(_key, record_id)| record_id)).collect::<Result<BTreeSet<u32>, _>>()?);
+                    children = children.iter().enumerate().filter(|(i, _)| !compound_scan.used_children.contains(i)).map(|(_, child)| *child).collect();
+                }
+            }
+            //先算只用索引的子条件, 需要扫描的子条件只扫描已得到的交集
+            let (indexed, scanned): (Vec<&Condition>, Vec<&Condition>) = children.into_iter().partition(|child| !needs_scan(collection, child));
+            for child in indexed {
+                let child_result = filter_records_by_condition_tree(collection, child, context, read_txn, candidate_ids)?;
+                result = result.merge(&child_result, SetOperation::And);
+            }
+            for child in scanned {
+                let narrowed_ids = if result.is_complement() { candidate_ids.clone() } else { Some(result.ids.clone()) };
+                let child_result = filter_records_by_condition_tree(collection, child, context, read_txn, &narrowed_ids)?;
+                result = result.merge(&child_result, SetOperation::And);
             }
+            Ok(result)
+        }
+        Condition::OR(children) => {
+            let mut result = LazySet::new(BTreeSet::new());
+            for child in children {
+                let child_result = filter_records_by_condition_tree(collection, child, context, read_txn, candidate_ids)?;
+                result = result.merge(&child_result, SetOperation::Or);
+            }
+            Ok(result)
         }
     }
+}
 
-    if let Some(operation_index) = min_priority_index {
-        let left = &condition_results[..operation_index];
-        let right = &condition_results[operation_index + 1..];
+/// 复合索引上的一次范围扫描: 前 equal_len 个字段等值, 下一个字段可带范围或前缀条件
+struct CompoundScan<'a> {
+    index_fields: &'a Vec<String>,
+    equal_len: usize,
+    //被这次扫描覆盖的 AND 子条件序号
+    used_children: Vec<usize>,
+    //扫描范围 [start, end)
+    start: Vec<u8>,
+    end: Vec<u8>,
+}
 
-        let left_result = resolve_condition_results_recursive(left);
-        let right_result = resolve_condition_results_recursive(right);
+/// 为 AND 的子条件挑选覆盖子条件最多的复合索引, 没有可用的复合索引时返回 None
+fn plan_compound_scan<'a>(collection: &'a Collection, children: &[&Condition], context: &QueryContext) -> Option<CompoundScan<'a>> {
+    let find_child = |field: &String, used_children: &[usize], is_wanted: &dyn Fn(&ExpressionEntity) -> bool| {
+        children.iter().enumerate().position(|(i, child)| match child {
+            Condition::EXPRESSION(expression) if !used_children.contains(&i) => {
+                (expression.target_field == *field || field.strip_suffix("[]") == Some(expression.target_field.as_str()))
+                    && is_wanted(&expression.expression_entity)
+            }
+            _ => false,
+        })
+    };
 
-        if let ConditionResult::OPERATION(operation) = &condition_results[operation_index] {
-            let result = match operation {
-                ConditionOperation::NOT(_) => LazySet::complement(right_result.evaluate()),
-                _ => {
-                    let result_0 = left_result.merge(&right_result, operation.clone());
-                    result_0
+    let mut best_scan: Option<CompoundScan> = None;
+    for index_fields in &collection.indexes_compound_list {
+        let mut used_children = Vec::new();
+        let mut start = Vec::new();
+        //等值前缀
+        for field in index_fields {
+            let position = find_child(field, &used_children, &|entity| match entity {
+                ExpressionEntity::EQUAL { value_ref } => matches!(resolve_one_value_ref(value_ref, context), Value::String(_) | Value::Number(_)),
+                _ => false,
+            });
+            let Some(i) = position else { break; };
+            if let Condition::EXPRESSION(ConditionExpression { expression_entity: ExpressionEntity::EQUAL { value_ref }, .. }) = children[i] {
+                encode_compound_value(&mut start, &resolve_one_value_ref(value_ref, context));
+            }
+            used_children.push(i);
+        }
+        let equal_len = used_children.len();
+        let mut end = start.clone();
+        end.push(COMPOUND_TAG_END);
+
+        //下一个字段的范围或前缀
+        if let Some(field) = index_fields.get(equal_len) {
+            let position = find_child(field, &used_children, &|entity| matches!(entity, ExpressionEntity::RANGE { .. } | ExpressionEntity::PREFIX { .. }));
+            let bounds = position.and_then(|i| match children[i] {
+                Condition::EXPRESSION(expression) => compound_range_bounds(&start, &expression.expression_entity, context).map(|bounds| (i, bounds)),
+                _ => None,
+            });
+            if let Some((i, (range_start, range_end))) = bounds {
+                used_children.push(i);
+                start = range_start;
+                end = range_end;
+            }
+        }
+
+        if used_children.is_empty() || best_scan.as_ref().is_some_and(|scan| scan.used_children.len() >= used_children.len()) {
+            continue;
+        }
+        best_scan = Some(CompoundScan { index_fields, equal_len, used_children, start, end });
+    }
+    best_scan
+}
+
+/// 等值前缀之后一个字段的范围, 边界类型不一致 (数值与字符串) 时返回 None
+fn compound_range_bounds(prefix: &[u8], expression_entity: &ExpressionEntity, context: &QueryContext) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut start = prefix.to_vec();
+    let mut end = prefix.to_vec();
+    match expression_entity {
+        ExpressionEntity::RANGE { max, min, max_inclusive, min_inclusive } => {
+            let min_value = number_to_value(min, context);
+            let max_value = number_to_value(max, context);
+            let tag = match (&min_value, &max_value) {
+                (Value::Number(_), Value::Number(_) | Value::Null) | (Value::Null, Value::Number(_)) => COMPOUND_TAG_NUMBER,
+                (Value::String(_), Value::String(_) | Value::Null) | (Value::Null, Value::String(_)) => COMPOUND_TAG_STRING,
+                _ => { return None; }
+            };
+            //同一个值后面只会接下一个字段的类型标记, 追加 COMPOUND_TAG_END 即跳过该值的全部 key
+            match min_value {
+                Value::Null => { start.push(tag); }
+                value => {
+                    encode_compound_value(&mut start, &value);
+                    if !*min_inclusive {
+                        start.push(COMPOUND_TAG_END);
+                    }
+                }
+            }
+            match max_value {
+                Value::Null => { end.push(tag + 1); }
+                value => {
+                    encode_compound_value(&mut end, &value);
+                    if *max_inclusive {
+                        end.push(COMPOUND_TAG_END);
+                    }
                 }
+            }
+        }
+        ExpressionEntity::PREFIX { value_ref } => {
+            let Value::String(prefix) = resolve_one_value_ref(value_ref, context) else {
+                return None;
             };
-            return result;
+            encode_compound_string_prefix(&mut start, prefix.as_str());
+            end = start.clone();
+            end.push(COMPOUND_TAG_END);
         }
+        _ => { return None; }
     }
-    LazySet::new(BTreeSet::new())
+    Some((start, end))
 }
 
-fn _resolve_condition_results(condition_results: Vec<ConditionResult>) -> BTreeSet<u32> {
-    let result = resolve_condition_results_recursive(&condition_results);
-    let evaluated = result.evaluate();
-    evaluated
+/// 按复合索引顺序遍历的 (key, record id)
+type CompoundEntries = Box<dyn Iterator<Item=Result<(Vec<u8>, u32), MgError>>>;
+
+/// 按复合索引顺序返回扫描范围内的 (key, record id), 同一 key 下 ASC 时 id 升序, DESC 时 id 降序
+fn compound_scan_entries(collection: &Collection, compound_scan: &CompoundScan, read_txn: &ReadTransaction, is_desc: bool) -> Result<CompoundEntries, MgError> {
+    if compound_scan.start >= compound_scan.end {
+        return Ok(Box::new(std::iter::empty()));
+    }
+    let collection_name_index = compound_table_name(&collection.collection_name, compound_scan.index_fields);
+    let index_table = open_table_read::<(&[u8], u32), ()>(&collection_name_index, read_txn)?;
+    let start = Bound::Included((compound_scan.start.as_slice(), 0));
+    let end = Bound::Excluded((compound_scan.end.as_slice(), 0));
+    let range_cursor = index_table.range::<(&[u8], u32)>((start, end))?;
+    let entries = range_cursor.map(|kv| kv.map(|(key, _value)| {
+        let (key, record_id) = key.value();
+        (key.to_vec(), record_id)
+    }).map_err(MgError::from));
+    if is_desc { Ok(Box::new(entries.rev())) } else { Ok(Box::new(entries)) }
 }
 
-fn resolve_condition_results_if(condition_results: Vec<ConditionResult>, read_txn: &ReadTransaction, collection: &Collection) -> BTreeSet<u32> {
-    let result = resolve_condition_results_recursive(&condition_results);
-    let get_set_len = || {
-        let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn);
-        collection_table.len().unwrap_or(0) as u32
+/// WHERE 全部由复合索引的等值前缀与范围条件组成, 且 ORDERBY 依次是等值前缀之后的字段时,
+/// 一次范围扫描即得到排好序的结果; 否则返回 None
+fn compound_ordered_ids(collection: &Collection, wheres: &Where, order_by: &OrderBy, default_limit: usize, context: &mut QueryContext, read_txn: &ReadTransaction) -> Result<Option<Vec<u32>>, MgError> {
+    let children: Vec<&Condition> = match &wheres.condition {
+        Condition::AND(children) => children.iter().collect(),
+        condition => vec![condition],
     };
-    let get_full_set = || {
-        let collection_table = open_table_read::<u32, String>(&collection.collection_name, &read_txn);
-        let table_iter = collection_table.iter().unwrap();
-        let lock_ids = table_iter.take(MAX_FULL_LEN as usize);
-        let ids: BTreeSet<u32> = lock_ids.map(|id_result| id_result.unwrap().0.value()).collect();
-        ids
+    let Some(compound_scan) = plan_compound_scan(collection, &children, context) else {
+        return Ok(None);
+    };
+    //数组路径的一条 record 有多个索引项, 顺序不可直接使用
+    if compound_scan.used_children.len() != children.len() || compound_scan.index_fields.iter().any(|field| field.contains("[]")) {
+        return Ok(None);
+    }
+    let order_fields = &compound_scan.index_fields[compound_scan.equal_len..];
+    let Some(first_key) = order_by.keys.first() else {
+        return Ok(None);
     };
-    let evaluated = result.evaluate_if(get_full_set, get_set_len);
-    evaluated
+    let is_desc = matches!(first_key.order_direction, OrderDirection::DESC);
+    if order_by.keys.len() > order_fields.len() {
+        return Ok(None);
+    }
+    for (key, field) in order_by.keys.iter().zip(order_fields) {
+        if key.field != *field || matches!(key.order_direction, OrderDirection::DESC) != is_desc {
+            return Ok(None);
+        }
+    }
+
+    let (skip, limit) = resolve_skip_limit(order_by, default_limit, context)?;
+    //排序值只取等值前缀与 ORDERBY 字段, 之后的索引字段不参与排序, 值相同时按 id 升序
+    let key_value_count = compound_scan.equal_len + order_by.keys.len();
+    let entries = compound_scan_entries(collection, &compound_scan, read_txn, is_desc)?.map(move |entry| entry.map(|(mut key, record_id)| {
+        key.truncate(compound_key_prefix_len(&key, key_value_count));
+        (key, record_id)
+    }));
+    let record_ids = ascending_ids_within_equal_keys(entries).map(|entry| entry.map(|(_key, record_id)| record_id)).skip(skip).take(limit).collect::<Result<Vec<u32>, _>>()?;
+    Ok(Some(record_ids))
+}
+
+fn needs_scan(collection: &Collection, condition: &Condition) -> bool {
+    match condition {
+        Condition::EXPRESSION(expression) => matches!(check_field_type(collection, &expression.target_field), ConditionFieldType::NoIndex),
+        Condition::NOT(inner) => needs_scan(collection, inner),
+        Condition::AND(children) | Condition::OR(children) => children.iter().any(|child| needs_scan(collection, child)),
+    }
 }
 
-fn filter_records_by_condition(collection: &Collection, condition: &ConditionExpression, context: &mut QueryContext, read_txn: &ReadTransaction) -> BTreeSet<u32> {
+fn filter_records_by_condition(collection: &Collection, condition: &ConditionExpression, context: &mut QueryContext, read_txn: &ReadTransaction) -> Result<BTreeSet<u32>, MgError> {
     // let record_ids = BTreeSet::new();
 
-    let condition_field_type = check_field_type(collection, &condition.target_field);
+    let target_field = index_path(collection, &condition.target_field);
+    let condition_field_type = check_field_type(collection, target_field);
     let record_ids = match condition_field_type {
         ConditionFieldType::PrimaryKey => {
             let collection_name_primary = format!("{}@primary", collection.collection_name);
-            let primary_key_table = open_table_read::<&str, u32>(&collection_name_primary, &read_txn);
-            filter_id_from_table_string_unique(&primary_key_table, &condition.expression_entity, context)
+            let primary_key_table = open_table_read::<&str, u32>(&collection_name_primary, &read_txn)?;
+            filter_id_from_table_string_unique(&primary_key_table, &condition.expression_entity, context)?
         }
         ConditionFieldType::F64 => {
-            let collection_name_index = format!("{}@f64@{}", collection.collection_name, condition.target_field);
-            let index_table = open_table_read::<(MyF64, u32), ()>(&collection_name_index, &read_txn);
-            filter_id_from_table_f64(&index_table, &condition.expression_entity, context)
+            let collection_name_index = format!("{}@f64@{}", collection.collection_name, target_field);
+            let index_table = open_table_read::<(MyF64, u32), ()>(&collection_name_index, &read_txn)?;
+            filter_id_from_table_f64(&index_table, &condition.expression_entity, context)?
         }
         ConditionFieldType::String => {
-            let collection_name_index = format!("{}@string@{}", collection.collection_name, condition.target_field);
+            let collection_name_index = format!("{}@string@{}", collection.collection_name, target_field);
             let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
-            let index_table = read_txn.open_multimap_table(index_table_define).unwrap();
-            filter_id_from_table_string(&index_table, &condition.expression_entity, context)
+            let index_table = read_txn.open_multimap_table(index_table_define)?;
+            filter_id_from_table_string(&index_table, &condition.expression_entity, context)?
         }
         ConditionFieldType::StringUnique => {
-            let collection_name_index = format!("{}@stringU@{}", collection.collection_name, condition.target_field);
-            let index_table = open_table_read::<&str, u32>(&collection_name_index, &read_txn);
-            filter_id_from_table_string_unique(&index_table, &condition.expression_entity, context)
+            let collection_name_index = format!("{}@stringU@{}", collection.collection_name, target_field);
+            let index_table = open_table_read::<&str, u32>(&collection_name_index, &read_txn)?;
+            filter_id_from_table_string_unique(&index_table, &condition.expression_entity, context)?
         }
         ConditionFieldType::NoIndex => {
-            BTreeSet::new()
+            filter_id_by_scan(collection, condition, context, read_txn, &None)?
         }
     };
     // println!("判断 1 record_ids: {record_ids:#?}");
 
-    record_ids
+    Ok(record_ids)
 }
 
-fn number_to_f64(number: &Number, context: &QueryContext) -> f64 {
-    if let Number::ValueRef(value_ref) = number {
-        let value = resolve_one_value_ref(value_ref, context);
-        if let Value::Number(json_number) = value {
-            let f64 = json_number.as_f64().unwrap_or(-1.0);
-            return f64;
+/// 无索引字段: 逐条读取记录判断, 有候选 id 时只读取候选记录
+fn filter_id_by_scan(collection: &Collection, condition: &ConditionExpression, context: &QueryContext, read_txn: &ReadTransaction, candidate_ids_option: &Option<BTreeSet<u32>>) -> Result<BTreeSet<u32>, MgError> {
+    let collection_table = open_table_read::<u32, String>(&collection.collection_name, read_txn)?;
+    let is_match = |record_id: u32, json_string: &str| {
+        match serde_json::from_str::<Value>(json_string) {
+            Ok(record) => match_condition_expression(&record, condition, context),
+            Err(_) => {
+                println!("记录解析失败: {record_id}");
+                false
+            }
         }
-    }
+    };
 
-    number.to_f64()
+    let mut record_ids = BTreeSet::new();
+    match candidate_ids_option {
+        Some(candidate_ids) => {
+            for record_id in candidate_ids {
+                if let Some(json_string) = collection_table.get(record_id)? {
+                    if is_match(*record_id, &json_string.value()) {
+                        record_ids.insert(*record_id);
+                    }
+                }
+            }
+        }
+        None => {
+            for kv in collection_table.iter()? {
+                let (key_lock, value_lock) = kv?;
+                let record_id = key_lock.value();
+                if is_match(record_id, &value_lock.value()) {
+                    record_ids.insert(record_id);
+                }
+            }
+        }
+    }
+    Ok(record_ids)
 }
 
-fn filter_id_from_table_f64(table: &ReadOnlyTable<(MyF64, u32), ()>, expression_entity: &ExpressionEntity, context: &mut QueryContext) -> BTreeSet<u32> {
+fn filter_id_from_table_f64(table: &ReadOnlyTable<(MyF64, u32), ()>, expression_entity: &ExpressionEntity, context: &mut QueryContext) -> Result<BTreeSet<u32>, MgError> {
+    //同一数值下 record id 从 1000000001 开始, 0 和 u32::MAX 作为边界
+    let point_ids = |number: f64| -> Result<Vec<u32>, MgError> {
+        let range_cursor = table.range((MyF64(number), 0)..=(MyF64(number), u32::MAX))?;
+        Ok(range_cursor.map(|v| v.map(|(key, _value)| key.value().1)).collect::<Result<Vec<u32>, _>>()?)
+    };
+
     let record_ids = match expression_entity {
-        ExpressionEntity::IN { .. } => { BTreeSet::new() }
-        ExpressionEntity::EQUAL { .. } => { BTreeSet::new() }
-        ExpressionEntity::RANGE { max, min } => {
-            let max_f64 = number_to_f64(max, context);
-            let min_f64 = number_to_f64(min, context);
-
-            let range_cursor = table.range((MyF64(min_f64), 0)..=(MyF64(max_f64), u32::MAX)).unwrap();
-            let ids_map = range_cursor.map(|v| v.unwrap().0.value().1);
-            let record_ids: BTreeSet<_> = ids_map.collect();
+        ExpressionEntity::IN { value_ref } => {
+            let value_list = resolve_list_value_ref(value_ref, context);
+            let mut record_ids = BTreeSet::new();
+            for number in value_list.iter().filter_map(|v| v.as_f64()) {
+                record_ids.extend(point_ids(number)?);
+            }
             record_ids
         }
-        ExpressionEntity::REGEX { .. } => { BTreeSet::new() }
+        ExpressionEntity::EQUAL { value_ref } => {
+            let value = resolve_one_value_ref(value_ref, context);
+            value.as_f64().map(point_ids).transpose()?.unwrap_or_default().into_iter().collect()
+        }
+        ExpressionEntity::RANGE { max, min, max_inclusive, min_inclusive } => {
+            let (Some(min_f64), Some(max_f64)) = (bound_to_f64(min, f64::NEG_INFINITY, context), bound_to_f64(max, f64::INFINITY, context)) else {
+                return Ok(BTreeSet::new());
+            };
+            if min_f64 > max_f64 || (min_f64 == max_f64 && !(*min_inclusive && *max_inclusive)) {
+                return Ok(BTreeSet::new());
+            }
+
+            let start = if *min_inclusive { Bound::Included((MyF64(min_f64), 0)) } else { Bound::Excluded((MyF64(min_f64), u32::MAX)) };
+            let end = if *max_inclusive { Bound::Included((MyF64(max_f64), u32::MAX)) } else { Bound::Excluded((MyF64(max_f64), 0)) };
+            let range_cursor = table.range::<(MyF64, u32)>((start, end))?;
+            let ids_map = range_cursor.map(|v| v.map(|(key, _value)| key.value().1));
+            let record_ids = ids_map.collect::<Result<BTreeSet<u32>, _>>()?;
+            record_ids
+        }
+        ExpressionEntity::REGEX { .. } | ExpressionEntity::PREFIX { .. } => { BTreeSet::new() }
     };
 
-    record_ids
+    Ok(record_ids)
+}
+
+/// 范围边界的值, 无穷大/无穷小为 Null 表示不限
+fn number_to_value(number: &Number, context: &QueryContext) -> Value {
+    match number {
+        Number::Int64(int64) => Value::from(*int64),
+        Number::Float64(f64) => Value::from(*f64),
+        Number::Infinity | Number::NegInfinity => Value::Null,
+        Number::ValueRef(value_ref) => resolve_one_value_ref(value_ref, context),
+    }
+}
+
+/// 数值范围的边界, 不限时取 unbounded, 边界不是数值时返回 None
+fn bound_to_f64(number: &Number, unbounded: f64, context: &QueryContext) -> Option<f64> {
+    match number_to_value(number, context) {
+        Value::Null => Some(unbounded),
+        value => value.as_f64(),
+    }
 }
 
-fn filter_id_from_table_string(table: &ReadOnlyMultimapTable<&str, u32>, expression_entity: &ExpressionEntity, context: &mut QueryContext) -> BTreeSet<u32> {
+/// 字符串范围的边界, 边界不是字符串或范围为空时返回 None
+fn string_range_bounds(min: &Number, min_inclusive: bool, max: &Number, max_inclusive: bool, context: &QueryContext) -> Option<(Bound<String>, Bound<String>)> {
+    let to_bound = |number: &Number, is_inclusive: bool| -> Option<Bound<String>> {
+        match number_to_value(number, context) {
+            Value::Null => Some(Bound::Unbounded),
+            Value::String(string) if is_inclusive => Some(Bound::Included(string)),
+            Value::String(string) => Some(Bound::Excluded(string)),
+            _ => None,
+        }
+    };
+    let start = to_bound(min, min_inclusive)?;
+    let end = to_bound(max, max_inclusive)?;
+    if let (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) = (&start, &end) {
+        if a > b || (a == b && !(min_inclusive && max_inclusive)) {
+            return None;
+        }
+    }
+    Some((start, end))
+}
+
+fn filter_id_from_table_string(table: &ReadOnlyMultimapTable<&str, u32>, expression_entity: &ExpressionEntity, context: &mut QueryContext) -> Result<BTreeSet<u32>, MgError> {
     let mut record_ids = BTreeSet::new();
 
     match expression_entity {
@@ -448,8 +1190,8 @@ fn filter_id_from_table_string(table: &ReadOnlyMultimapTable<&str, u32>, express
             let value_list = resolve_list_value_ref(value_ref, context);
             let string_list: Vec<String> = value_list.iter().map(|v| v.as_str().unwrap_or("None").to_string()).collect();
             for key in string_list {
-                let values = table.get(key.as_str()).unwrap();
-                let mut ids: BTreeSet<u32> = values.map(|v| v.unwrap().value()).collect();
+                let values = table.get(key.as_str())?;
+                let mut ids = values.map(|v| v.map(|id| id.value())).collect::<Result<BTreeSet<u32>, _>>()?;
                 record_ids.append(&mut ids);
             }
         }
@@ -458,36 +1200,63 @@ fn filter_id_from_table_string(table: &ReadOnlyMultimapTable<&str, u32>, express
             // println!("判断是否等于value_ref: {value:#?}");
 
             if let Value::String(key) = value {
-                let values = table.get(key.as_str()).unwrap();
-                let mut ids: BTreeSet<u32> = values.map(|v| v.unwrap().value()).collect();
+                let values = table.get(key.as_str())?;
+                let mut ids = values.map(|v| v.map(|id| id.value())).collect::<Result<BTreeSet<u32>, _>>()?;
                 // println!("判断 ids: {ids:#?}");
 
                 record_ids.append(&mut ids);
                 // println!("判断 0 record_ids: {record_ids:#?}");
             }
         }
-        ExpressionEntity::RANGE { .. } => {}
+        ExpressionEntity::RANGE { max, min, max_inclusive, min_inclusive } => {
+            if let Some((start, end)) = string_range_bounds(min, *min_inclusive, max, *max_inclusive, context) {
+                let range_cursor = table.range::<&str>((start.as_ref().map(|s| s.as_str()), end.as_ref().map(|s| s.as_str())))?;
+                for kv in range_cursor {
+                    let (_key_lock, values) = kv?;
+                    for v in values {
+                        record_ids.insert(v?.value());
+                    }
+                }
+            }
+        }
         ExpressionEntity::REGEX { reg } => {
             let this_reg_result = Regex::new(reg.as_str());
             if let Ok(this_reg) = this_reg_result {
-                let mut iter = table.iter().unwrap();
-                while let Some(kv) = iter.next() {
-                    if let Ok((key_lock, values)) = kv {
-                        let key = key_lock.value();
-                        if this_reg.is_match(key) {
-                            let mut ids: BTreeSet<u32> = values.map(|v| v.unwrap().value()).collect();
-                            record_ids.append(&mut ids)
+                //以 `^字面量` 开头的正则只扫描该前缀范围
+                let prefix = regex_literal_prefix(reg).unwrap_or_default();
+                for kv in table.range::<&str>(prefix.as_str()..)? {
+                    let (key_lock, values) = kv?;
+                    let key = key_lock.value();
+                    if !key.starts_with(prefix.as_str()) {
+                        break;
+                    }
+                    if this_reg.is_match(key) {
+                        for v in values {
+                            record_ids.insert(v?.value());
                         }
                     }
                 }
             }
         }
+        ExpressionEntity::PREFIX { value_ref } => {
+            if let Value::String(prefix) = resolve_one_value_ref(value_ref, context) {
+                for kv in table.range::<&str>(prefix.as_str()..)? {
+                    let (key_lock, values) = kv?;
+                    if !key_lock.value().starts_with(prefix.as_str()) {
+                        break;
+                    }
+                    for v in values {
+                        record_ids.insert(v?.value());
+                    }
+                }
+            }
+        }
     }
-    record_ids
+    Ok(record_ids)
 }
 
 
-fn filter_id_from_table_string_unique(table: &ReadOnlyTable<&str, u32>, expression_entity: &ExpressionEntity, context: &mut QueryContext) -> BTreeSet<u32> {
+fn filter_id_from_table_string_unique(table: &ReadOnlyTable<&str, u32>, expression_entity: &ExpressionEntity, context: &mut QueryContext) -> Result<BTreeSet<u32>, MgError> {
     let mut record_ids = BTreeSet::new();
 
     match expression_entity {
@@ -495,7 +1264,7 @@ fn filter_id_from_table_string_unique(table: &ReadOnlyTable<&str, u32>, expressi
             let value_list = resolve_list_value_ref(value_ref, context);
             let string_list: Vec<&str> = value_list.iter().map(|v| v.as_str().unwrap_or("None")).collect();
             for key in string_list {
-                let record_id_option = table.get(key).unwrap();
+                let record_id_option = table.get(key)?;
                 match record_id_option {
                     None => {}
                     Some(record_id_value) => {
@@ -508,7 +1277,7 @@ fn filter_id_from_table_string_unique(table: &ReadOnlyTable<&str, u32>, expressi
         ExpressionEntity::EQUAL { value_ref } => {
             let value = resolve_one_value_ref(value_ref, context);
             if let Value::String(key) = value {
-                let record_id_option = table.get(key.as_str()).unwrap();
+                let record_id_option = table.get(key.as_str())?;
                 match record_id_option {
                     None => {}
                     Some(record_id_value) => {
@@ -518,24 +1287,72 @@ fn filter_id_from_table_string_unique(table: &ReadOnlyTable<&str, u32>, expressi
                 }
             }
         }
-        ExpressionEntity::RANGE { .. } => {}
+        ExpressionEntity::RANGE { max, min, max_inclusive, min_inclusive } => {
+            if let Some((start, end)) = string_range_bounds(min, *min_inclusive, max, *max_inclusive, context) {
+                let range_cursor = table.range::<&str>((start.as_ref().map(|s| s.as_str()), end.as_ref().map(|s| s.as_str())))?;
+                for kv in range_cursor {
+                    record_ids.insert(kv?.1.value());
+                }
+            }
+        }
         ExpressionEntity::REGEX { reg } => {
             let this_reg_result = Regex::new(reg.as_str());
             if let Ok(this_reg) = this_reg_result {
-                let mut iter = table.iter().unwrap();
-                while let Some(kv) = iter.next() {
-                    if let Ok((key_lock, value_lock)) = kv {
-                        let key = key_lock.value();
-                        if this_reg.is_match(key) {
-                            let record_id = value_lock.value();
-                            record_ids.insert(record_id);
-                        }
+                let prefix = regex_literal_prefix(reg).unwrap_or_default();
+                for kv in table.range::<&str>(prefix.as_str()..)? {
+                    let (key_lock, value_lock) = kv?;
+                    let key = key_lock.value();
+                    if !key.starts_with(prefix.as_str()) {
+                        break;
                     }
+                    if this_reg.is_match(key) {
+                        record_ids.insert(value_lock.value());
+                    }
+                }
+            }
+        }
+        ExpressionEntity::PREFIX { value_ref } => {
+            if let Value::String(prefix) = resolve_one_value_ref(value_ref, context) {
+                for kv in table.range::<&str>(prefix.as_str()..)? {
+                    let (key_lock, value_lock) = kv?;
+                    if !key_lock.value().starts_with(prefix.as_str()) {
+                        break;
+                    }
+                    record_ids.insert(value_lock.value());
+                }
+            }
+        }
+    }
+    Ok(record_ids)
+}
+
+/// 正则 `^abc...` 的字面量前缀, 匹配结果必然以该前缀开头; 含 `|` 或没有字面量前缀时返回 None
+fn regex_literal_prefix(reg: &str) -> Option<String> {
+    let body = reg.strip_prefix('^')?;
+    if body.contains('|') {
+        return None;
+    }
+    let mut prefix = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some(&next) if next.is_ascii_punctuation() => {
+                    prefix.push(next);
+                    chars.next();
                 }
+                _ => break,
+            },
+            //量词作用于前一个字符, 该字符不一定出现
+            '*' | '?' | '{' => {
+                prefix.pop();
+                break;
             }
+            '.' | '+' | '(' | ')' | '[' | ']' | '}' | '^' | '$' => break,
+            c => prefix.push(c),
         }
     }
-    record_ids
+    if prefix.is_empty() { None } else { Some(prefix) }
 }
 
 fn resolve_one_value_ref(value_ref: &ValueRef, context: &QueryContext) -> Value {
@@ -547,7 +1364,8 @@ fn resolve_one_value_ref(value_ref: &ValueRef, context: &QueryContext) -> Value
                 let value_pack_option = context.variables.get(key);
                 if let Some(value_pack) = value_pack_option {
                     match value_pack {
-                        ValuePack::List(list) => { value = list[0].clone(); }
+                        //之前的 AS 没有选中 record 时为 null
+                        ValuePack::List(list) => { value = list.first().cloned().unwrap_or(Value::Null); }
                         ValuePack::Value(one_value) => { value = one_value.clone(); }
                         ValuePack::IdList(_) => {}
                     }
@@ -561,7 +1379,7 @@ fn resolve_one_value_ref(value_ref: &ValueRef, context: &QueryContext) -> Value
     value
 }
 
-fn resolve_list_value_ref(value_ref: &ValueRef, context: &mut QueryContext) -> Vec<Value> {
+fn resolve_list_value_ref(value_ref: &ValueRef, context: &QueryContext) -> Vec<Value> {
     let mut value_list = Vec::new();
     let mut this_value = Value::Null;
     match value_ref {
@@ -598,7 +1416,112 @@ fn resolve_list_value_ref(value_ref: &ValueRef, context: &mut QueryContext) -> V
     value_list
 }
 
+/// 在内存中判断单条 record (或 GROUP 结果行) 是否满足条件
+fn match_where(record: &Value, wheres: &Where, context: &QueryContext) -> bool {
+    match_condition(&wheres.condition, record, context)
+}
+
+fn match_condition(condition: &Condition, record: &Value, context: &QueryContext) -> bool {
+    match condition {
+        Condition::EXPRESSION(expression) => match_condition_expression(record, expression, context),
+        Condition::NOT(inner) => !match_condition(inner, record, context),
+        Condition::AND(children) => children.iter().all(|child| match_condition(child, record, context)),
+        Condition::OR(children) => children.iter().any(|child| match_condition(child, record, context)),
+    }
+}
+
+fn match_condition_expression(record: &Value, condition: &ConditionExpression, context: &QueryContext) -> bool {
+    //左侧为 `$参数` (AS 变量或参数, 如 `$User1 IN friends`) 或带引号的字符串时直接取值, 否则取 record 字段, 不存在时为 null
+    let target_field = condition.target_field.as_str();
+    let target_value = if let Some(param_name) = target_field.strip_prefix('$') {
+        resolve_one_value_ref(&ValueRef::Ref(param_name.to_string()), context)
+    } else if target_field.starts_with('"') {
+        Value::from(target_field.trim_matches('"'))
+    } else {
+        match get_value_by_path(record, target_field) {
+            Some(value) => value.clone(),
+            None if target_field.contains("[]") => {
+                Value::Array(get_values_by_path(record, target_field).into_iter().cloned().collect())
+            }
+            None => Value::Null,
+        }
+    };
+    // 数组字段: 任一元素满足即可
+    let target_values = match &target_value {
+        Value::Array(list) => list.clone(),
+        _ => vec![target_value.clone()],
+    };
+
+    match &condition.expression_entity {
+        ExpressionEntity::IN { value_ref } => {
+            let value_list = match value_ref {
+                ValueRef::Value(Value::String(field_name)) if get_value_by_path(record, field_name).is_some() => {
+                    match get_value_by_path(record, field_name) {
+                        Some(Value::Array(list)) => list.clone(),
+                        Some(value) => vec![value.clone()],
+                        None => Vec::new(),
+                    }
+                }
+                _ => resolve_list_value_ref(value_ref, context),
+            };
+            value_list.iter().any(|v| values_equal(v, &target_value) || target_values.iter().any(|t| values_equal(v, t)))
+        }
+        ExpressionEntity::EQUAL { value_ref } => {
+            let value = resolve_one_value_ref(value_ref, context);
+            values_equal(&target_value, &value) || target_values.iter().any(|t| values_equal(t, &value))
+        }
+        ExpressionEntity::RANGE { max, min, max_inclusive, min_inclusive } => {
+            let min_value = number_to_value(min, context);
+            let max_value = number_to_value(max, context);
+            //数值与数值比较, 字符串与字符串比较, 类型不同时不匹配
+            let is_comparable = |bound: &Value, value: &Value| {
+                bound.is_null() || (bound.is_number() && value.is_number()) || (bound.is_string() && value.is_string())
+            };
+            target_values.iter().filter(|t| t.is_number() || t.is_string()).any(|value| {
+                if !is_comparable(&min_value, value) || !is_comparable(&max_value, value) {
+                    return false;
+                }
+                let above_min = min_value.is_null() || match compare_values(&min_value, value) {
+                    Ordering::Less => true,
+                    Ordering::Equal => *min_inclusive,
+                    Ordering::Greater => false,
+                };
+                let below_max = max_value.is_null() || match compare_values(value, &max_value) {
+                    Ordering::Less => true,
+                    Ordering::Equal => *max_inclusive,
+                    Ordering::Greater => false,
+                };
+                above_min && below_max
+            })
+        }
+        ExpressionEntity::REGEX { reg } => {
+            match Regex::new(reg.as_str()) {
+                Ok(this_reg) => target_values.iter().filter_map(|t| t.as_str()).any(|str| this_reg.is_match(str)),
+                Err(_) => false,
+            }
+        }
+        ExpressionEntity::PREFIX { value_ref } => {
+            match resolve_one_value_ref(value_ref, context) {
+                Value::String(prefix) => target_values.iter().filter_map(|t| t.as_str()).any(|str| str.starts_with(prefix.as_str())),
+                _ => false,
+            }
+        }
+    }
+}
+
+/// 字段对应的索引路径, 如索引 `tags[]` 也可以用 `tags` 查询; 没有索引时返回字段本身
+fn index_path<'a>(collection: &'a Collection, field: &'a String) -> &'a String {
+    let index_lists = [&collection.indexes_string_unique_list, &collection.indexes_string_list, &collection.indexes_f64_list];
+    for index_list in index_lists {
+        if let Some(index) = index_list.iter().find(|index| *index == field || index.strip_suffix("[]") == Some(field.as_str())) {
+            return index;
+        }
+    }
+    field
+}
+
 fn check_field_type(collection: &Collection, target_field: &String) -> ConditionFieldType {
+    let target_field = index_path(collection, target_field);
     if *collection.primary_key == *target_field {
         ConditionFieldType::PrimaryKey
     } else if collection.indexes_string_unique_list.contains(target_field) {
@@ -624,10 +1547,13 @@ enum ConditionFieldType {
 mod tests {
     use std::collections::{BTreeMap, BTreeSet};
     use serde_json::{json, Value};
-    use crate::minimongo::executor::{_resolve_condition_results, paginate};
-    use crate::minimongo::minimongo::get_mgdb;
-    use crate::minimongo::minimongo::tests::DB_NAME;
-    use crate::minimongo::query::{ConditionOperation, ConditionResult};
+    use crate::minimongo::error::MgError;
+    use crate::minimongo::executor::{paginate, regex_literal_prefix};
+    use crate::minimongo::lazy_set::{LazySet, SetOperation};
+    use crate::minimongo::minimongo::Schema;
+    use crate::common::helper::get_timestamp;
+    use crate::minimongo::minimongo::tests::{create_books_collection, memory_mgdb, memory_mgdb_with_books};
+    use crate::minimongo::query::UpdateType;
 
     const SQL_STR_2: &str = include_str!("Test2.SQL");
     const SQL_STR_3: &str = include_str!("Test3.SQL");
@@ -648,10 +1574,14 @@ mod tests {
     #[test]
     fn test_db_query_2() -> Result<(), Box<i32>> {
         println!("test_db_query");
-        let mg_db = get_mgdb(DB_NAME.to_string());
+        let mg_db = memory_mgdb_with_books();
         let params = BTreeMap::new();
         let query = SQL_STR_2.to_string();
-        mg_db.query_records(&query, params);
+        let final_result = mg_db.query_records(&query, params).unwrap();
+
+        //排序方向写在 SKIP/LIMIT 之后
+        let prices: Vec<&Value> = final_result["PhysicsBooks"].as_array().unwrap().iter().map(|b| &b["price"]).collect();
+        assert_eq!(prices, vec![&json!(102.5), &json!(99.5), &json!(96.5)]);
 
         println!("test_db_query done");
         Ok(())
@@ -661,7 +1591,7 @@ mod tests {
     #[test]
     fn test_db_query_3() -> Result<(), Box<i32>> {
         println!("test_db_query");
-        let mg_db = get_mgdb(DB_NAME.to_string());
+        let mg_db = memory_mgdb_with_books();
         let params_data = json!({
             "name": "Alice",
             "max_price": 97,
@@ -675,7 +1605,7 @@ mod tests {
         };
 
         let query = SQL_STR_3.to_string();
-        let final_result = mg_db.query_records(&query, params);
+        let final_result = mg_db.query_records(&query, params).unwrap();
 
         let final_result_str = serde_json::to_string_pretty(&final_result).unwrap();
         println!("final_result_str: {final_result_str}");
@@ -684,6 +1614,1273 @@ mod tests {
         Ok(())
     }
 
+    //cargo test test_execute_create -- --show-output
+    #[test]
+    fn test_execute_create() -> Result<(), Box<i32>> {
+        println!("准备测试: test_execute_create");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("NewBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let query = format!("CREATE {collection_name} CREATEONLY $books
+UPDATE create_time = timestamp(), pa = price * 2
+AS NewBooks
+
+RETURN NewBooks");
+        let mut params = BTreeMap::new();
+        params.insert("books".to_string(), json!([
+            {"name": "C1", "price": 10, "book_type": "Math", "book_uid": "c_uid_1"},
+            {"name": "C2", "price": 20.5, "book_type": "Physics", "book_uid": "c_uid_2"}
+        ]));
+
+        let final_result = mg_db.query_records(&query, params.clone()).unwrap();
+        println!("final_result: {final_result:#?}");
+        let new_books = final_result["NewBooks"].as_array().unwrap();
+        assert_eq!(new_books.len(), 2);
+        assert!(new_books[0]["create_time"].as_u64().unwrap() > 0);
+        assert_eq!(new_books[0]["pa"], json!(20));
+        assert_eq!(new_books[1]["pa"], json!(41.0));
+
+        //CREATEONLY 遇到已有record时整条语句失败, 同一语句中的新 record 也不写入
+        params.insert("books".to_string(), json!([
+            {"name": "C4", "price": 30, "book_type": "Math", "book_uid": "c_uid_4"},
+            {"name": "C1", "price": 11, "book_type": "Math", "book_uid": "c_uid_1"}
+        ]));
+        assert!(matches!(mg_db.query_records(&query, params), Err(MgError::Constraint(_))));
+
+        //UPDATEONLY 遇到不存在的 record, 以及唯一索引冲突
+        let query = format!("CREATE {collection_name} UPDATEONLY $books\nAS Books\n\nRETURN Books");
+        let params = BTreeMap::from([("books".to_string(), json!([{"name": "C5", "price": 1}]))]);
+        assert!(matches!(mg_db.query_records(&query, params), Err(MgError::Constraint(_))));
+        let query = format!("CREATE {collection_name} MERGE $books\nAS Books\n\nRETURN Books");
+        let params = BTreeMap::from([("books".to_string(), json!([{"name": "C6", "book_uid": "c_uid_1"}]))]);
+        assert!(matches!(mg_db.query_records(&query, params), Err(MgError::Constraint(_))));
+
+        let query = format!("SELECT {collection_name}\nORDERBY name\nFIELD name, price\nAS Books\n\nRETURN Books");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["Books"], json!([{"name": "C1", "price": 10}, {"name": "C2", "price": 20.5}]));
+
+        let query = format!("CREATE ONE {collection_name} MERGE $book\nAS NewBook\n\nRETURN NewBook");
+        let mut params = BTreeMap::new();
+        params.insert("book".to_string(), json!({"name": "C3", "price": 1}));
+        let final_result = mg_db.query_records(&query, params).unwrap();
+        assert_eq!(final_result["NewBook"]["name"], json!("C3"));
+
+        println!("测试完毕: test_execute_create");
+        Ok(())
+    }
+
+    //cargo test test_execute_group -- --show-output
+    #[test]
+    fn test_execute_group() -> Result<(), Box<i32>> {
+        println!("准备测试: test_execute_group");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("GroupBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        let types = vec!["Math", "Physics", "History"];
+        let colors = vec!["red", "blue"];
+        for i in 0..9 {
+            records.push(json!({
+                "name": format!("G_{i}"),
+                "price": 10 + i,
+                "book_type": types[i % 3],
+                "color": colors[i % 2],
+                "book_uid": format!("g_uid_{i}")
+            }));
+        }
+        records.push(json!({"name": "G_9", "price": 100, "book_type": "Math", "book_uid": "g_uid_9"}));
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("GROUP {collection_name} BY book_type
+FIELD
+    book_type,
+    num_books=count(),
+    total=sum(price),
+    avg_price=avg(price),
+    cheapest=min(price),
+    names=collect(name),
+HAVING num_books > 3.5
+AS Groups
+
+GROUP {collection_name} BY color
+FIELD color, num_books=count(), books=collect($record)
+ORDERBY num_books DESC
+AS ColorGroups
+
+GROUP {collection_name} BY price
+WHERE book_type=Physics
+AS PriceGroups
+
+GROUP {collection_name} BY book_type
+FIELD book_type, names=collect(name)
+HAVING $name IN names OR \"G_0\" IN names
+AS NameGroups
+
+RETURN Groups, ColorGroups, PriceGroups, NameGroups");
+        let mut params = BTreeMap::new();
+        params.insert("name".to_string(), json!("G_4"));
+        let final_result = mg_db.query_records(&query, params).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let groups = final_result["Groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0]["book_type"], json!("Math"));
+        assert_eq!(groups[0]["num_books"], json!(4));
+        assert_eq!(groups[0]["total"], json!(10 + 13 + 16 + 100));
+        assert_eq!(groups[0]["cheapest"], json!(10));
+        assert_eq!(groups[0]["names"], json!(["G_0", "G_3", "G_6", "G_9"]));
+
+        let color_groups = final_result["ColorGroups"].as_array().unwrap();
+        assert_eq!(color_groups.len(), 2);
+        assert_eq!(color_groups[0]["color"], json!("red"));
+        assert_eq!(color_groups[0]["num_books"], json!(5));
+        assert_eq!(color_groups[0]["books"].as_array().unwrap().len(), 5);
+
+        let price_groups = final_result["PriceGroups"].as_array().unwrap();
+        assert_eq!(price_groups, &vec![json!({"price": 11, "count": 1}), json!({"price": 14, "count": 1}), json!({"price": 17, "count": 1})]);
+
+        let name_groups = final_result["NameGroups"].as_array().unwrap();
+        let name_group_types: Vec<&Value> = name_groups.iter().map(|g| &g["book_type"]).collect();
+        assert_eq!(name_group_types, vec![&json!("Math"), &json!("Physics")]);
+
+        println!("测试完毕: test_execute_group");
+        Ok(())
+    }
+
+    //cargo test test_execute_update -- --show-output
+    #[test]
+    fn test_execute_update() -> Result<(), Box<i32>> {
+        println!("准备测试: test_execute_update");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("UpdateBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        for i in 0..5 {
+            records.push(json!({
+                "name": format!("U_{i}"),
+                "price": 10 + i,
+                "book_type": if i % 2 == 0 { "Math" } else { "Physics" },
+                "book_uid": format!("u_uid_{i}")
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE book_type=Physics
+AS MyBooks
+
+SELECT ONE {collection_name}
+WHERE name=U_0
+UPDATE
+    price=price+100,
+    book_type=\"History\",
+    like_books.push($extra),
+    num_books=MyBooks.len(),
+AS User1
+
+SELECT {collection_name}
+WHERE book_type=History
+AS HistoryBooks
+
+SELECT {collection_name}
+WHERE 110<=price<200
+AS ExpensiveBooks
+
+SELECT {collection_name}
+WHERE book_type=Math
+AS MathBooks
+
+RETURN User1, HistoryBooks, ExpensiveBooks, MathBooks");
+        let mut params = BTreeMap::new();
+        params.insert("extra".to_string(), json!("Extra"));
+        let final_result = mg_db.query_records(&query, params).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let user_1 = &final_result["User1"];
+        assert_eq!(user_1["price"], json!(110));
+        assert_eq!(user_1["book_type"], json!("History"));
+        assert_eq!(user_1["like_books"], json!(["Extra"]));
+        assert_eq!(user_1["num_books"], json!(2));
+        assert_eq!(final_result["HistoryBooks"].as_array().unwrap().len(), 1);
+        assert_eq!(final_result["ExpensiveBooks"].as_array().unwrap().len(), 1);
+        let math_names: Vec<&Value> = final_result["MathBooks"].as_array().unwrap().iter().map(|b| &b["name"]).collect();
+        assert_eq!(math_names, vec![&json!("U_2"), &json!("U_4")]);
+
+        //唯一索引冲突时整个 UPDATE 返回错误, 不修改任何 record
+        let query = format!("SELECT {collection_name}
+WHERE book_type=Math
+UPDATE price=0, book_uid=\"u_uid_3\"
+AS Conflict
+
+RETURN Conflict");
+        assert!(matches!(mg_db.query_records(&query, BTreeMap::new()), Err(MgError::Constraint(_))));
+        let query = format!("SELECT {collection_name}\nWHERE book_type=Math\nFIELD price, book_uid\nAS MathBooks\n\nRETURN MathBooks");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["MathBooks"], json!([{"price": 12, "book_uid": "u_uid_2"}, {"price": 14, "book_uid": "u_uid_4"}]));
+
+        println!("测试完毕: test_execute_update");
+        Ok(())
+    }
+
+    //cargo test test_execute_update_concurrent -- --show-output
+    #[test]
+    fn test_execute_update_concurrent() -> Result<(), Box<i32>> {
+        println!("准备测试: test_execute_update_concurrent");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("ClaimBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+        let records = (0..20).map(|i| json!({"name": format!("J_{i}"), "claims": 0})).collect();
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        //多个线程同时认领 claims=0 的 record, 选取与修改在同一个写事务中, 同一个 record 只会被认领一次
+        let query = format!("SELECT ONE {collection_name}
+WHERE claims=0
+UPDATE claims=claims+1
+AS Claimed
+
+RETURN Claimed");
+        let handles: Vec<_> = (0..8).map(|_| {
+            let mg_db = mg_db.clone();
+            let query = query.clone();
+            std::thread::spawn(move || {
+                for _ in 0..2 {
+                    mg_db.query_records(&query, BTreeMap::new()).unwrap();
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let query = format!("SELECT {collection_name}\nWHERE claims>0\nORDERBY claims LIMIT 100\nFIELD claims\nAS Claimed\n\nRETURN Claimed");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        let claimed = final_result["Claimed"].as_array().unwrap();
+        assert_eq!(claimed.len(), 16);
+        assert!(claimed.iter().all(|record| record["claims"] == json!(1)));
+
+        println!("测试完毕: test_execute_update_concurrent");
+        Ok(())
+    }
+
+    //cargo test test_execute_delete -- --show-output
+    #[test]
+    fn test_execute_delete() -> Result<(), Box<i32>> {
+        println!("准备测试: test_execute_delete");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("DeleteBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        let types = vec!["Math", "Physics", "History"];
+        for i in 0..15 {
+            records.push(json!({
+                "name": format!("D_{i}"),
+                "price": 10 + i,
+                "book_type": types[i % 3],
+                "book_uid": format!("d_uid_{i}")
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE 9.5<price<19.5
+DELETE
+AS Deleted
+
+GROUP {collection_name} BY book_type
+HAVING book_type=History
+DELETE
+AS DeletedGroups
+
+SELECT {collection_name}
+ORDERBY price LIMIT 20
+FIELD name
+AS Rest
+
+RETURN Deleted, DeletedGroups, Rest");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        assert_eq!(final_result["Deleted"].as_array().unwrap().len(), 10);
+        assert_eq!(final_result["DeletedGroups"], json!([{"book_type": "History", "count": 2}]));
+        assert_eq!(final_result["Rest"], json!([{"name": "D_10"}, {"name": "D_12"}, {"name": "D_13"}]));
+
+        //ORDERBY 未指定 LIMIT 时 DELETE 作用于全部匹配的 records, 查询仍默认只返回 10 条
+        let records = (0..15).map(|i| json!({"name": format!("E_{i}"), "price": 100 + i})).collect();
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+        let query = format!("SELECT {collection_name}
+WHERE price>=100
+ORDERBY price DESC
+AS Listed
+
+SELECT {collection_name}
+WHERE price>=100
+ORDERBY price DESC
+DELETE
+AS Deleted
+
+RETURN Listed, Deleted");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["Listed"].as_array().unwrap().len(), 10);
+        assert_eq!(final_result["Deleted"].as_array().unwrap().len(), 15);
+        let query = format!("SELECT {collection_name}\nORDERBY price LIMIT 100\nFIELD name\nAS Rest\n\nRETURN Rest");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["Rest"], json!([{"name": "D_10"}, {"name": "D_12"}, {"name": "D_13"}]));
+
+        println!("测试完毕: test_execute_delete");
+        Ok(())
+    }
+
+    //cargo test test_filter_no_index -- --show-output
+    #[test]
+    fn test_filter_no_index() -> Result<(), Box<i32>> {
+        println!("准备测试: test_filter_no_index");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("ScanBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        let colors = vec!["red", "green", "blue"];
+        for i in 0..12 {
+            records.push(json!({
+                "name": format!("S_{i}"),
+                "price": 10 + i,
+                "color": colors[i % 3],
+                "pages": 100 * i,
+                "book_type": "Math",
+                "book_uid": format!("s_uid_{i}")
+            }));
+        }
+        //没有 color 字段的 record
+        records.push(json!({"name": "S_12", "price": 22, "pages": 1200, "book_type": "Math", "book_uid": "s_uid_12"}));
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE color=\"red\"
+FIELD name
+AS Red
+
+SELECT {collection_name}
+WHERE NOT color=red AND price>20.5
+FIELD name
+AS NotRed
+
+SELECT {collection_name}
+WHERE 12.5<price<30 AND color=blue
+FIELD name
+AS CheapBlue
+
+SELECT {collection_name}
+WHERE color IN $colors AND 250<pages<650
+FIELD name
+AS GreenOrBlue
+
+SELECT {collection_name}
+WHERE name=S_0 OR color REGEX ^gr
+FIELD name
+AS Regex
+
+SELECT {collection_name}
+WHERE (color=red OR name=S_1) AND NOT (12.5<price<30 AND color=blue) AND price>10.5
+FIELD name
+AS Grouped
+
+RETURN Red, NotRed, CheapBlue, GreenOrBlue, Regex, Grouped");
+        let mut params = BTreeMap::new();
+        params.insert("colors".to_string(), json!(["green", "blue"]));
+        //与字段同名的参数不会代替缺少的字段
+        params.insert("color".to_string(), json!("red"));
+        let final_result = mg_db.query_records(&query, params).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        assert_eq!(final_result["Red"], json!([{"name": "S_0"}, {"name": "S_3"}, {"name": "S_6"}, {"name": "S_9"}]));
+        assert_eq!(final_result["NotRed"], json!([{"name": "S_11"}, {"name": "S_12"}]));
+        assert_eq!(final_result["CheapBlue"], json!([{"name": "S_5"}, {"name": "S_8"}, {"name": "S_11"}]));
+        assert_eq!(final_result["GreenOrBlue"], json!([{"name": "S_4"}, {"name": "S_5"}]));
+        assert_eq!(final_result["Regex"], json!([{"name": "S_0"}, {"name": "S_1"}, {"name": "S_4"}, {"name": "S_7"}, {"name": "S_10"}]));
+
+        assert_eq!(final_result["Grouped"], json!([{"name": "S_1"}, {"name": "S_3"}, {"name": "S_6"}, {"name": "S_9"}]));
+
+        println!("测试完毕: test_filter_no_index");
+        Ok(())
+    }
+
+    //cargo test test_filter_comparison -- --show-output
+    #[test]
+    fn test_filter_comparison() -> Result<(), Box<i32>> {
+        println!("准备测试: test_filter_comparison");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("CompareBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        let types = vec!["Math", "Physics"];
+        for i in 0..5 {
+            records.push(json!({
+                "name": format!("C_{i}"),
+                "price": 10 + i,
+                "pages": 10 + i,
+                "book_type": types[i % 2],
+                "book_uid": format!("c_uid_{i}")
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE price>12
+FIELD name
+AS Greater
+
+SELECT {collection_name}
+WHERE price>=12
+FIELD name
+AS GreaterEqual
+
+SELECT {collection_name}
+WHERE 11<price<=13
+FIELD name
+AS Between
+
+SELECT {collection_name}
+WHERE 12>pages
+FIELD name
+AS PagesLess
+
+SELECT {collection_name}
+WHERE 12>=pages>10
+FIELD name
+AS PagesBetween
+
+SELECT {collection_name}
+WHERE price!=11 AND book_type!=Physics
+FIELD name
+AS NotEqual
+
+SELECT {collection_name}
+WHERE 12<price<12
+FIELD name
+AS Empty
+
+RETURN Greater, GreaterEqual, Between, PagesLess, PagesBetween, NotEqual, Empty");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        assert_eq!(final_result["Greater"], json!([{"name": "C_3"}, {"name": "C_4"}]));
+        assert_eq!(final_result["GreaterEqual"], json!([{"name": "C_2"}, {"name": "C_3"}, {"name": "C_4"}]));
+        assert_eq!(final_result["Between"], json!([{"name": "C_2"}, {"name": "C_3"}]));
+        assert_eq!(final_result["PagesLess"], json!([{"name": "C_0"}, {"name": "C_1"}]));
+        assert_eq!(final_result["PagesBetween"], json!([{"name": "C_1"}, {"name": "C_2"}]));
+        assert_eq!(final_result["NotEqual"], json!([{"name": "C_0"}, {"name": "C_2"}, {"name": "C_4"}]));
+        assert_eq!(final_result["Empty"], json!([]));
+
+        println!("测试完毕: test_filter_comparison");
+        Ok(())
+    }
+
+    //cargo test test_filter_range_and_in -- --show-output
+    #[test]
+    fn test_filter_range_and_in() -> Result<(), Box<i32>> {
+        println!("准备测试: test_filter_range_and_in");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("RangeBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let names = vec!["Alice", "Bob", "Carol", "Dave", "Eve", "Mallory", "Zed"];
+        let types = vec!["Art", "Biology", "Chemistry"];
+        let mut records = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            records.push(json!({
+                "name": name,
+                "price": 10 + i,
+                "book_type": types[i % 3],
+                "book_uid": format!("uid_{}", name.to_lowercase()),
+                "color": name.to_lowercase()
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE \"B\" <= name < \"M\"
+FIELD name
+AS NameRange
+
+SELECT {collection_name}
+WHERE book_uid>\"uid_eve\"
+FIELD name
+AS UidRange
+
+SELECT {collection_name}
+WHERE \"Art\" < book_type <= \"Biology\"
+FIELD name
+AS TypeRange
+
+SELECT {collection_name}
+WHERE \"c\" <= color < \"e\"
+FIELD name
+AS ColorRange
+
+SELECT {collection_name}
+WHERE price=12
+FIELD name
+AS PriceEqual
+
+SELECT {collection_name}
+WHERE price IN $prices
+FIELD name
+AS PriceIn
+
+RETURN NameRange, UidRange, TypeRange, ColorRange, PriceEqual, PriceIn");
+        let mut params = BTreeMap::new();
+        params.insert("prices".to_string(), json!([10, 14.0, 99, "x"]));
+        let final_result = mg_db.query_records(&query, params).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        assert_eq!(final_result["NameRange"], json!([{"name": "Bob"}, {"name": "Carol"}, {"name": "Dave"}, {"name": "Eve"}]));
+        assert_eq!(final_result["UidRange"], json!([{"name": "Mallory"}, {"name": "Zed"}]));
+        assert_eq!(final_result["TypeRange"], json!([{"name": "Bob"}, {"name": "Eve"}]));
+        assert_eq!(final_result["ColorRange"], json!([{"name": "Carol"}, {"name": "Dave"}]));
+        assert_eq!(final_result["PriceEqual"], json!([{"name": "Carol"}]));
+        assert_eq!(final_result["PriceIn"], json!([{"name": "Alice"}, {"name": "Eve"}]));
+
+        println!("测试完毕: test_filter_range_and_in");
+        Ok(())
+    }
+
+    //cargo test test_filter_prefix -- --show-output
+    #[test]
+    fn test_filter_prefix() -> Result<(), Box<i32>> {
+        println!("准备测试: test_filter_prefix");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("PrefixBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let names = vec!["Apple", "Application", "Apricot", "Banana", "App", "Cherry"];
+        let mut records = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            records.push(json!({
+                "name": name,
+                "price": 10 + i,
+                "book_type": name.to_lowercase(),
+                "book_uid": format!("uid_{}", name.to_lowercase()),
+                "color": name.to_uppercase()
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE name STARTSWITH App
+ORDERBY name
+FIELD name
+AS StartsWith
+
+SELECT {collection_name}
+WHERE book_type LIKE 'ap%'
+ORDERBY name
+FIELD name
+AS LikePrefix
+
+SELECT {collection_name}
+WHERE book_uid LIKE 'uid_%e'
+ORDERBY name
+FIELD name
+AS LikePattern
+
+SELECT {collection_name}
+WHERE name REGEX ^Appl(e|ication)$
+ORDERBY name
+FIELD name
+AS RegexPrefix
+
+SELECT {collection_name}
+WHERE color STARTSWITH $prefix
+ORDERBY name
+FIELD name
+AS ScanPrefix
+
+RETURN StartsWith, LikePrefix, LikePattern, RegexPrefix, ScanPrefix");
+        let mut params = BTreeMap::new();
+        params.insert("prefix".to_string(), json!("AP"));
+        let final_result = mg_db.query_records(&query, params).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let apps = json!([{"name": "App"}, {"name": "Apple"}, {"name": "Application"}]);
+        assert_eq!(final_result["StartsWith"], apps);
+        assert_eq!(final_result["LikePrefix"], json!([{"name": "App"}, {"name": "Apple"}, {"name": "Application"}, {"name": "Apricot"}]));
+        assert_eq!(final_result["LikePattern"], json!([{"name": "Apple"}]));
+        assert_eq!(final_result["RegexPrefix"], json!([{"name": "Apple"}, {"name": "Application"}]));
+        assert_eq!(final_result["ScanPrefix"], json!([{"name": "App"}, {"name": "Apple"}, {"name": "Application"}, {"name": "Apricot"}]));
+
+        println!("测试完毕: test_filter_prefix");
+        Ok(())
+    }
+
+    //cargo test test_regex_literal_prefix -- --show-output
+    #[test]
+    fn test_regex_literal_prefix() {
+        assert_eq!(regex_literal_prefix("^abc"), Some("abc".to_string()));
+        assert_eq!(regex_literal_prefix("^abc.*x$"), Some("abc".to_string()));
+        assert_eq!(regex_literal_prefix("^abc?"), Some("ab".to_string()));
+        assert_eq!(regex_literal_prefix("^ab\\.c+"), Some("ab.c".to_string()));
+        assert_eq!(regex_literal_prefix("^a\\d"), Some("a".to_string()));
+        assert_eq!(regex_literal_prefix("^abc|^x"), None);
+        assert_eq!(regex_literal_prefix("abc"), None);
+        assert_eq!(regex_literal_prefix("^(?i)abc"), None);
+    }
+
+    //cargo test test_nested_path_index -- --show-output
+    #[test]
+    fn test_nested_path_index() -> Result<(), Box<i32>> {
+        println!("准备测试: test_nested_path_index");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("NestedBooks_{}", get_timestamp());
+        let schema: Schema = serde_json::from_value(json!({
+            "primary_key": "isbn.code",
+            "indexes_f64": ["stats.views", "scores[]"],
+            "indexes_string": ["author.name", "tags[]"],
+            "indexes_string_unique": ["codes[]"]
+        })).unwrap();
+        mg_db.create_collection(collection_name.clone(), schema).unwrap();
+
+        let records = vec![
+            json!({"isbn": {"code": "A1"}, "author": {"name": "Lu Xun"}, "stats": {"views": 30}, "tags": ["novel", "classic"], "scores": [3, 9], "codes": ["c1", "c2"]}),
+            json!({"isbn": {"code": "A2"}, "author": {"name": "Lao She"}, "stats": {"views": 10}, "tags": ["drama"], "scores": [5], "codes": ["c3"]}),
+            json!({"isbn": {"code": "A3"}, "author": {"name": "Lu Xun"}, "stats": {"views": 20}, "tags": ["classic", "essay"], "scores": [1, 7], "codes": ["c4"]}),
+            json!({"isbn": {"code": "A4"}, "author": {"name": "Ba Jin"}, "stats": {"views": 40}, "tags": [], "scores": [], "codes": ["c5", "c2"]}),
+        ];
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE author.name=\"Lu Xun\"
+ORDERBY stats.views
+FIELD isbn.code
+AS ByAuthor
+
+SELECT {collection_name}
+WHERE tags=classic
+ORDERBY stats.views DESC
+FIELD isbn.code
+AS Classic
+
+SELECT {collection_name}
+WHERE 6<scores[]<8
+FIELD isbn.code
+AS ScoreRange
+
+SELECT {collection_name}
+ORDERBY scores[]
+FIELD isbn.code
+AS ByScore
+
+SELECT ONE {collection_name}
+WHERE isbn.code=A2
+FIELD author.name
+AS ByPrimary
+
+SELECT ONE {collection_name}
+WHERE isbn.code=A1
+UPDATE tags=$new_tags
+AS Updated
+
+SELECT {collection_name}
+WHERE tags[]=classic OR tags[]=poetry
+ORDERBY isbn.code
+FIELD isbn.code, tags
+AS AfterUpdate
+
+SELECT {collection_name}
+WHERE codes=c5
+FIELD isbn.code
+AS Rejected
+
+RETURN ByAuthor, Classic, ScoreRange, ByScore, ByPrimary, AfterUpdate, Rejected");
+        let mut params = BTreeMap::new();
+        params.insert("new_tags".to_string(), json!(["poetry"]));
+        let final_result = mg_db.query_records(&query, params).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let codes = |list: &Value| -> Vec<String> {
+            list.as_array().unwrap().iter().map(|b| b["isbn"]["code"].as_str().unwrap_or("").to_string()).collect()
+        };
+        assert_eq!(codes(&final_result["ByAuthor"]), vec!["A3", "A1"]);
+        assert_eq!(codes(&final_result["Classic"]), vec!["A1", "A3"]);
+        assert_eq!(codes(&final_result["ScoreRange"]), vec!["A3"]);
+        assert_eq!(codes(&final_result["ByScore"]), vec!["A3", "A1", "A2"]);
+        assert_eq!(final_result["ByPrimary"], json!({"author": {"name": "Lao She"}}));
+        assert_eq!(final_result["Rejected"], json!([]));
+        assert_eq!(final_result["AfterUpdate"], json!([
+            {"isbn": {"code": "A1"}, "tags": ["poetry"]},
+            {"isbn": {"code": "A3"}, "tags": ["classic", "essay"]}
+        ]));
+
+        println!("测试完毕: test_nested_path_index");
+        Ok(())
+    }
+
+    //cargo test test_order_by_string -- --show-output
+    #[test]
+    fn test_order_by_string() -> Result<(), Box<i32>> {
+        println!("准备测试: test_order_by_string");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("OrderBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        let types = vec!["Math", "Physics", "History"];
+        for i in 0..6 {
+            records.push(json!({
+                "name": format!("O_{}", 5 - i),
+                "price": 10 + i,
+                "book_type": types[i % 3],
+                "book_uid": format!("o_uid_{i}")
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+ORDERBY name LIMIT 3
+FIELD name
+AS ByName
+
+SELECT {collection_name}
+WHERE price>11
+ORDERBY book_uid DESC SKIP 1 LIMIT 2
+FIELD book_uid
+AS ByUidDesc
+
+SELECT {collection_name}
+ORDERBY book_type
+FIELD name, book_type
+AS ByType
+
+SELECT {collection_name}
+WHERE NOT book_type=Math
+ORDERBY book_type DESC LIMIT 3
+FIELD name
+AS ByTypeDesc
+
+RETURN ByName, ByUidDesc, ByType, ByTypeDesc");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        assert_eq!(final_result["ByName"], json!([{"name": "O_0"}, {"name": "O_1"}, {"name": "O_2"}]));
+        assert_eq!(final_result["ByUidDesc"], json!([{"book_uid": "o_uid_4"}, {"book_uid": "o_uid_3"}]));
+        let type_names: Vec<&Value> = final_result["ByType"].as_array().unwrap().iter().map(|b| &b["name"]).collect();
+        assert_eq!(type_names, vec![&json!("O_3"), &json!("O_0"), &json!("O_5"), &json!("O_2"), &json!("O_4"), &json!("O_1")]);
+        assert_eq!(final_result["ByTypeDesc"], json!([{"name": "O_4"}, {"name": "O_1"}, {"name": "O_3"}]));
+
+        println!("测试完毕: test_order_by_string");
+        Ok(())
+    }
+
+    //cargo test test_compound_index -- --show-output
+    #[test]
+    fn test_compound_index() -> Result<(), Box<i32>> {
+        println!("准备测试: test_compound_index");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("CompoundBooks_{}", get_timestamp());
+        let schema: Schema = serde_json::from_value(json!({
+            "primary_key": "name",
+            "indexes_f64": [],
+            "indexes_string": [],
+            "indexes_string_unique": [],
+            "indexes_compound": [["book_type", "price"], ["book_type", "name"]]
+        })).unwrap();
+        mg_db.create_collection(collection_name.clone(), schema).unwrap();
+
+        let books = vec![("C_1", "Math", json!(5)), ("C_2", "Math", json!(12)), ("C_3", "Math", json!(15)),
+                         ("C_4", "Math", json!(18)), ("C_5", "Math", json!(25)), ("C_6", "Physics", json!(12)),
+                         ("C_7", "Physics", json!(14)), ("C_8", "Math", Value::Null), ("C_9", "Math", json!(15))];
+        let records = books.iter().map(|(name, book_type, price)| {
+            let mut record = json!({"name": name, "book_type": book_type});
+            if !price.is_null() {
+                record["price"] = price.clone();
+            }
+            record
+        }).collect();
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE book_type=\"Math\" AND 10<price<20
+ORDERBY price
+FIELD name
+AS MathRange
+
+SELECT {collection_name}
+WHERE 10<price<20 AND book_type=Math
+ORDERBY price DESC LIMIT 2
+FIELD name
+AS MathRangeDesc
+
+SELECT {collection_name}
+WHERE book_type=Math AND price>=15
+ORDERBY name DESC
+FIELD name
+AS MathByName
+
+SELECT {collection_name}
+WHERE book_type=Physics AND name STARTSWITH C_7
+FIELD name
+AS PhysicsPrefix
+
+SELECT {collection_name}
+WHERE book_type=Math
+ORDERBY price LIMIT 3
+FIELD name
+AS MathByPrice
+
+SELECT {collection_name}
+WHERE book_type=Math AND 20<price<10
+FIELD name
+AS EmptyRange
+
+SELECT ONE {collection_name}
+WHERE name=C_1
+UPDATE price=19
+AS Updated
+
+SELECT {collection_name}
+WHERE book_type=Math AND 10<price<20
+ORDERBY price
+FIELD name
+AS AfterUpdate
+
+RETURN MathRange, MathRangeDesc, MathByName, PhysicsPrefix, MathByPrice, EmptyRange, AfterUpdate");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let names = |list: &Value| -> Vec<String> {
+            list.as_array().unwrap().iter().map(|b| b["name"].as_str().unwrap_or("").to_string()).collect()
+        };
+        assert_eq!(names(&final_result["MathRange"]), vec!["C_2", "C_3", "C_9", "C_4"]);
+        assert_eq!(names(&final_result["MathRangeDesc"]), vec!["C_4", "C_3"]);
+        assert_eq!(names(&final_result["MathByName"]), vec!["C_9", "C_5", "C_4", "C_3"]);
+        assert_eq!(names(&final_result["PhysicsPrefix"]), vec!["C_7"]);
+        assert_eq!(names(&final_result["MathByPrice"]), vec!["C_8", "C_1", "C_2"]);
+        assert_eq!(names(&final_result["EmptyRange"]), Vec::<String>::new());
+        assert_eq!(names(&final_result["AfterUpdate"]), vec!["C_2", "C_3", "C_9", "C_4", "C_1"]);
+
+        println!("测试完毕: test_compound_index");
+        Ok(())
+    }
+
+    //cargo test test_order_by_equal_keys -- --show-output
+    #[test]
+    fn test_order_by_equal_keys() -> Result<(), Box<i32>> {
+        println!("准备测试: test_order_by_equal_keys");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("EqualKeyBooks_{}", get_timestamp());
+        let schema: Schema = serde_json::from_value(json!({
+            "primary_key": "name",
+            "indexes_f64": ["price"],
+            "indexes_string": ["book_type"],
+            "indexes_string_unique": [],
+            "indexes_compound": [["book_type", "price", "name"]]
+        })).unwrap();
+        mg_db.create_collection(collection_name.clone(), schema).unwrap();
+
+        //price 与 rank 都只有 3 个不同的值, name 与 id 顺序相反
+        let mut records = Vec::new();
+        for i in 0..9 {
+            records.push(json!({"name": format!("T_{}", 8 - i), "price": 10 + i % 3, "rank": i % 3, "book_type": "Math"}));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+ORDERBY price DESC LIMIT 20
+FIELD name
+AS ByIndex
+
+SELECT {collection_name}
+WHERE rank>=0
+ORDERBY price DESC LIMIT 20
+FIELD name
+AS ByF64Values
+
+SELECT {collection_name}
+ORDERBY rank DESC LIMIT 20
+FIELD name
+AS InMemory
+
+SELECT {collection_name}
+WHERE book_type=Math
+ORDERBY price DESC LIMIT 20
+FIELD name
+AS ByCompound
+
+SELECT {collection_name}
+WHERE book_type=Math
+ORDERBY price LIMIT 20
+FIELD name
+AS ByCompoundAsc
+
+SELECT {collection_name}
+ORDERBY price DESC LIMIT 4
+FIELD name
+AS Page1
+
+SELECT {collection_name}
+ORDERBY price DESC SKIP 4 LIMIT 4
+FIELD name
+AS Page2
+
+SELECT {collection_name}
+WHERE book_type=Math
+ORDERBY price DESC SKIP 8 LIMIT 4
+FIELD name
+AS Page3
+
+RETURN ByIndex, ByF64Values, InMemory, ByCompound, ByCompoundAsc, Page1, Page2, Page3");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let names = |list: &Value| -> Vec<String> {
+            list.as_array().unwrap().iter().map(|b| b["name"].as_str().unwrap_or("").to_string()).collect()
+        };
+        //排序值相同时都按 id 升序
+        let expected = vec!["T_6", "T_3", "T_0", "T_7", "T_4", "T_1", "T_8", "T_5", "T_2"];
+        assert_eq!(names(&final_result["ByIndex"]), expected);
+        assert_eq!(names(&final_result["ByF64Values"]), expected);
+        assert_eq!(names(&final_result["InMemory"]), expected);
+        assert_eq!(names(&final_result["ByCompound"]), expected);
+        assert_eq!(names(&final_result["ByCompoundAsc"]), vec!["T_8", "T_5", "T_2", "T_7", "T_4", "T_1", "T_6", "T_3", "T_0"]);
+
+        //分页既不重复也不遗漏
+        let mut pages = names(&final_result["Page1"]);
+        pages.extend(names(&final_result["Page2"]));
+        pages.extend(names(&final_result["Page3"]));
+        assert_eq!(pages, expected);
+
+        println!("测试完毕: test_order_by_equal_keys");
+        Ok(())
+    }
+
+    //cargo test test_order_by_missing_field -- --show-output
+    #[test]
+    fn test_order_by_missing_field() -> Result<(), Box<i32>> {
+        println!("准备测试: test_order_by_missing_field");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("MissingBooks_{}", get_timestamp());
+        let schema: Schema = serde_json::from_value(json!({
+            "primary_key": "name",
+            "indexes_f64": ["price"],
+            "indexes_string": ["book_type"],
+            "indexes_string_unique": [],
+            "indexes_compound": [["book_type", "price", "name"]]
+        })).unwrap();
+        mg_db.create_collection(collection_name.clone(), schema).unwrap();
+
+        //M_2 与 M_5 没有 price, M_4 的 price 是数组, 不在 f64 索引中
+        let records = vec![
+            json!({"name": "M_1", "price": 30, "rank": 30, "book_type": "Math"}),
+            json!({"name": "M_2", "book_type": "Math"}),
+            json!({"name": "M_3", "price": 10, "rank": 10, "book_type": "Math"}),
+            json!({"name": "M_4", "price": [5, 1], "rank": [5, 1], "book_type": "Math"}),
+            json!({"name": "M_5", "book_type": "Math"}),
+            json!({"name": "M_6", "price": 20, "rank": 20, "book_type": "Math"}),
+        ];
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+ORDERBY price
+FIELD name
+AS ByIndex
+
+SELECT {collection_name}
+WHERE name>M_0
+ORDERBY price
+FIELD name
+AS ByF64Values
+
+SELECT {collection_name}
+ORDERBY rank
+FIELD name
+AS InMemory
+
+SELECT {collection_name}
+ORDERBY price, name
+FIELD name
+AS MultiKey
+
+SELECT {collection_name}
+ORDERBY price DESC
+FIELD name
+AS ByIndexDesc
+
+SELECT {collection_name}
+WHERE name>M_0
+ORDERBY price DESC
+FIELD name
+AS ByF64ValuesDesc
+
+SELECT {collection_name}
+ORDERBY rank DESC
+FIELD name
+AS InMemoryDesc
+
+SELECT {collection_name}
+WHERE name>M_0
+ORDERBY price SKIP 1 LIMIT 2
+FIELD name
+AS Page
+
+RETURN ByIndex, ByF64Values, InMemory, MultiKey, ByIndexDesc, ByF64ValuesDesc, InMemoryDesc, Page");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let names = |list: &Value| -> Vec<String> {
+            list.as_array().unwrap().iter().map(|b| b["name"].as_str().unwrap_or("").to_string()).collect()
+        };
+        //缺少字段按 Null 排在数字之前, 数组排在数字之后, 每种排序方式结果一致
+        let expected = vec!["M_2", "M_5", "M_3", "M_6", "M_1", "M_4"];
+        assert_eq!(names(&final_result["ByIndex"]), expected);
+        assert_eq!(names(&final_result["ByF64Values"]), expected);
+        assert_eq!(names(&final_result["InMemory"]), expected);
+        assert_eq!(names(&final_result["MultiKey"]), expected);
+        let expected_desc = vec!["M_4", "M_1", "M_6", "M_3", "M_2", "M_5"];
+        assert_eq!(names(&final_result["ByIndexDesc"]), expected_desc);
+        assert_eq!(names(&final_result["ByF64ValuesDesc"]), expected_desc);
+        assert_eq!(names(&final_result["InMemoryDesc"]), expected_desc);
+        assert_eq!(names(&final_result["Page"]), vec!["M_5", "M_3"]);
+
+        println!("测试完毕: test_order_by_missing_field");
+        Ok(())
+    }
+
+    //cargo test test_query_errors -- --show-output
+    #[test]
+    fn test_query_errors() -> Result<(), Box<i32>> {
+        println!("准备测试: test_query_errors");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("ErrorBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+        mg_db.update_records(&collection_name, vec![json!({"name": "E_1", "price": 10})], UpdateType::CreateOnlY).unwrap();
+
+        //不存在的 collection
+        let query = "SELECT NoSuchBooks\nAS Books\n\nRETURN Books".to_string();
+        assert_eq!(mg_db.query_records(&query, BTreeMap::new()).err(), Some(MgError::UnknownCollection("NoSuchBooks".to_string())));
+        let result = mg_db.update_records(&"NoSuchBooks".to_string(), vec![json!({"name": "E_2"})], UpdateType::Merge);
+        assert_eq!(result.err(), Some(MgError::UnknownCollection("NoSuchBooks".to_string())));
+
+        //无法解析的条件与表达式
+        let query = format!("SELECT {collection_name}\nWHERE price ~~ 10\nAS Books\n\nRETURN Books");
+        assert!(matches!(mg_db.query_records(&query, BTreeMap::new()), Err(MgError::Parse(_))));
+        let query = format!("SELECT {collection_name}\nUPDATE price = (1 +\nAS Books\n\nRETURN Books");
+        assert!(matches!(mg_db.query_records(&query, BTreeMap::new()), Err(MgError::Parse(_))));
+        let query = format!("SELECT {collection_name}\nORDERBY price ASC LIMIT $limit\nAS Books\n\nRETURN Books");
+        let params = BTreeMap::from([("limit".to_string(), json!(-1))]);
+        assert!(matches!(mg_db.query_records(&query, params), Err(MgError::Parse(_))));
+
+        //括号不成对与缺少操作数
+        for condition in ["price > 1 AND", "(price > 1", "price > 1)", "(price > 1 OR name=E_1))", "AND price > 1", "price > 1 AND OR name=E_1", "NOT", "()", "price > 1 AND (name=E_1 OR)"] {
+            let query = format!("SELECT {collection_name}\nWHERE {condition}\nAS Books\n\nRETURN Books");
+            assert!(matches!(mg_db.query_records(&query, BTreeMap::new()), Err(MgError::Parse(_))), "{condition}");
+        }
+
+        //FIELD 中无法解析的表达式与未知函数, DELETE 不会执行
+        let query = format!("SELECT {collection_name}\nDELETE\nFIELD name, total=price*(2\nAS Books\n\nRETURN Books");
+        assert!(matches!(mg_db.query_records(&query, BTreeMap::new()), Err(MgError::Parse(_))));
+        let query = format!("GROUP {collection_name} BY name\nFIELD name, total=summ(price)\nAS Books\n\nRETURN Books");
+        assert!(matches!(mg_db.query_records(&query, BTreeMap::new()), Err(MgError::Parse(_))));
+
+        //ORDERBY 中无法识别的排序方向与多余的内容
+        for order_by in ["price desc", "price LIMIT 5 foo", "price DESC LIMIT 5 ASC", "price, name LIMIT 5 DESC", "price LIMIT", "price name DESC", "price,"] {
+            let query = format!("SELECT {collection_name}\nORDERBY {order_by}\nAS Books\n\nRETURN Books");
+            assert!(matches!(mg_db.query_records(&query, BTreeMap::new()), Err(MgError::Parse(_))), "{order_by}");
+        }
+
+        //引用没有选中 record 的变量时按 null 处理, 不会 panic
+        let query = format!("SELECT {collection_name}\nWHERE name=\"E_9\"\nAS Empty\n\nSELECT {collection_name}\nWHERE name=$Empty\nAS Books\n\nRETURN Books");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["Books"], json!([]));
+
+        //出错的查询不影响已有数据
+        let query = format!("SELECT {collection_name}\nAS Books\n\nRETURN Books");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["Books"], json!([{"name": "E_1", "price": 10}]));
+
+        println!("测试完毕: test_query_errors");
+        Ok(())
+    }
+
+    //cargo test test_order_by_multi_key -- --show-output
+    #[test]
+    fn test_order_by_multi_key() -> Result<(), Box<i32>> {
+        println!("准备测试: test_order_by_multi_key");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("MultiOrderBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        let types = vec!["Math", "Physics"];
+        for i in 0..8 {
+            records.push(json!({
+                "name": format!("M_{}", 7 - i),
+                "price": 10 + (i % 3),
+                "created_at": i * 10,
+                "book_type": types[i % 2],
+                "book_uid": format!("m_uid_{i}")
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+ORDERBY price DESC, name ASC LIMIT 4
+FIELD name
+AS ByPriceName
+
+SELECT {collection_name}
+ORDERBY book_type, price DESC, created_at DESC SKIP 3 LIMIT 3
+FIELD name
+AS ByTypePriceCreated
+
+SELECT {collection_name}
+ORDERBY created_at DESC, name LIMIT 2
+FIELD name
+AS ByCreated
+
+SELECT {collection_name}
+WHERE price>=11
+ORDERBY price, name DESC LIMIT 4
+FIELD name
+AS FilteredByPriceName
+
+RETURN ByPriceName, ByTypePriceCreated, ByCreated, FilteredByPriceName");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        assert_eq!(final_result["ByPriceName"], json!([{"name": "M_2"}, {"name": "M_5"}, {"name": "M_0"}, {"name": "M_3"}]));
+        assert_eq!(final_result["ByTypePriceCreated"], json!([{"name": "M_7"}, {"name": "M_2"}, {"name": "M_0"}]));
+        assert_eq!(final_result["ByCreated"], json!([{"name": "M_0"}, {"name": "M_1"}]));
+        assert_eq!(final_result["FilteredByPriceName"], json!([{"name": "M_6"}, {"name": "M_3"}, {"name": "M_0"}, {"name": "M_5"}]));
+
+        println!("测试完毕: test_order_by_multi_key");
+        Ok(())
+    }
+
+    //cargo test test_not_large_collection -- --show-output
+    #[test]
+    fn test_not_large_collection() -> Result<(), Box<i32>> {
+        println!("准备测试: test_not_large_collection");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("NotBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        let types = vec!["Math", "Physics", "History"];
+        for i in 0..1500 {
+            records.push(json!({
+                "name": format!("N_{i}"),
+                "price": i,
+                "book_type": types[i % 3],
+                "book_uid": format!("n_uid_{i}")
+            }));
+        }
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+WHERE NOT book_type=Physics
+ORDERBY price DESC LIMIT 3
+FIELD name
+AS NotPhysics
+
+SELECT {collection_name}
+WHERE NOT NOT book_type=Physics
+ORDERBY price LIMIT 2
+FIELD name
+AS Physics
+
+GROUP {collection_name} BY book_type
+WHERE NOT book_type=Math
+AS Groups
+
+RETURN NotPhysics, Physics, Groups");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        assert_eq!(final_result["NotPhysics"], json!([{"name": "N_1499"}, {"name": "N_1497"}, {"name": "N_1496"}]));
+        assert_eq!(final_result["Physics"], json!([{"name": "N_1"}, {"name": "N_4"}]));
+        assert_eq!(final_result["Groups"], json!([{"book_type": "History", "count": 500}, {"book_type": "Physics", "count": 500}]));
+
+        let query = format!("SELECT {collection_name}
+WHERE NOT book_type=Physics
+UPDATE tag=\"not_physics\"
+AS Updated
+
+RETURN Updated");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["Updated"].as_array().unwrap().len(), 1000);
+
+        println!("测试完毕: test_not_large_collection");
+        Ok(())
+    }
+
+    //cargo test test_execute_field_expression -- --show-output
+    #[test]
+    fn test_execute_field_expression() -> Result<(), Box<i32>> {
+        println!("准备测试: test_execute_field_expression");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("FieldBooks_{}", get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let records = vec![
+            json!({"name": "F_0", "price": 12, "book_type": "Math", "book_uid": "f_uid_0", "age": 3, "author": {"name": "Ann", "age": 40}}),
+            json!({"name": "F_1", "price": 25.5, "book_type": "Physics", "book_uid": "f_uid_1", "age": 5, "author": {"name": "Bob", "age": 50}}),
+        ];
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let query = format!("SELECT {collection_name}
+ORDERBY price
+FIELD
+    *,
+    double_price = price * 2,
+    label = name + \" (\" + lower(book_type) + \")\",
+    is_cheap = price < 20,
+    age.hide(),
+    author.age.hide(),
+AS AllBooks
+
+SELECT ONE {collection_name}
+WHERE name=F_1
+FIELD name, author.name, name_len=len(name)
+AS OneBook
+
+RETURN AllBooks, OneBook");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+
+        let all_books = final_result["AllBooks"].as_array().unwrap();
+        assert_eq!(all_books[0]["double_price"], json!(24));
+        assert_eq!(all_books[0]["label"], json!("F_0 (math)"));
+        assert_eq!(all_books[0]["is_cheap"], json!(true));
+        assert_eq!(all_books[1]["is_cheap"], json!(false));
+        assert!(all_books[0].get("age").is_none());
+        assert_eq!(all_books[0]["author"], json!({"name": "Ann"}));
+        assert_eq!(all_books[0]["book_uid"], json!("f_uid_0"));
+        assert_eq!(final_result["OneBook"], json!({"name": "F_1", "author": {"name": "Bob"}, "name_len": 3}));
+
+        let records = vec![
+            json!({"name": "F_2", "like_books": [{"name": "L1", "num_liked": 3}, {"name": "L2", "num_liked": 9}, {"name": "L3", "num_liked": 1}]}),
+        ];
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+        let query = format!("SELECT ONE {collection_name}
+WHERE name=F_2
+FIELD
+    name,
+    like_books.select(\"name\").orderby(\"num_liked\", \"DESC\").limit(2),
+    num_popular=like_books.where(num_liked > 2).len(),
+AS LikedBooks
+
+RETURN LikedBooks");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["LikedBooks"], json!({"name": "F_2", "like_books": [{"name": "L2"}, {"name": "L1"}], "num_popular": 2}));
+
+        println!("测试完毕: test_execute_field_expression");
+        Ok(())
+    }
+
     //cargo test test_lazy_set_condition_0 -- --show-output
     #[test]
     fn test_lazy_set_condition_0() {
@@ -691,17 +2888,12 @@ mod tests {
         let set2: BTreeSet<u32> = [3, 4, 5].into_iter().collect();
         let set3: BTreeSet<u32> = [5, 6, 7].into_iter().collect();
 
-        let condition_results = vec![
-            ConditionResult::IDS(set1),
-            ConditionResult::OPERATION(ConditionOperation::OR(1)),
-            ConditionResult::IDS(set2),
-            ConditionResult::OPERATION(ConditionOperation::AND(2)),
-            ConditionResult::IDS(set3),
-        ];
-
-        let result = _resolve_condition_results(condition_results);
+        // set1 OR (set2 AND set3)
+        let and_result = LazySet::new(set2).merge(&LazySet::new(set3), SetOperation::And);
+        let result = LazySet::new(set1).merge(&and_result, SetOperation::Or);
 
-        println!("Final Result: {:?}", result)
+        println!("Final Result: {:?}", result);
+        assert_eq!(result.ids, [1, 2, 3, 5].into_iter().collect());
     }
 
     //cargo test test_lazy_set_condition_1 -- --show-output
@@ -710,15 +2902,14 @@ mod tests {
         let set1: BTreeSet<u32> = [1000000002, 1000000005, 1000000008].into_iter().collect();
         let set2: BTreeSet<u32> = [1000000003, 1000000006, 1000000009].into_iter().collect();
 
-        let condition_results = vec![
-            ConditionResult::IDS(set1),
-            ConditionResult::OPERATION(ConditionOperation::OR(0)),
-            ConditionResult::IDS(set2),
-        ];
-
-        let result = _resolve_condition_results(condition_results);
+        // NOT set1 OR set2
+        let result = LazySet::new(set1).not().merge(&LazySet::new(set2), SetOperation::Or);
 
-        println!("Final Result: {:?}", result)
+        println!("Final Result: {:?}", result);
+        assert!(result.is_complement());
+        assert!(result.contains(&1000000003));
+        assert!(result.contains(&1000000004));
+        assert!(!result.contains(&1000000005));
     }
 
     //cargo test test_paginate -- --show-output
@@ -0,0 +1,1016 @@
+//表达式解析与求值: FIELD 中的 a=b+2c, UPDATE 中的 create_time=timestamp(), age=age+1 等
+
+use std::cmp::Ordering;
+use serde_json::{Map, Value};
+use crate::common::helper::get_timestamp;
+
+/// 表达式求值时, 用于解析 `$param` 与 AS 变量
+pub trait Scope {
+    fn param(&self, name: &str) -> Option<Value>;
+    fn variable(&self, name: &str) -> Option<Value>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(Value),
+    Ident(String),
+    Param(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    /// 字段路径 `a` / `a.b`, 当前 record 中不存在时按 AS 变量解析
+    Field(String),
+    /// `$name`
+    Param(String),
+    /// `$record`, 当前 record 本身
+    Record,
+    Neg(Box<Expr>),
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Method {
+        target: Box<Expr>,
+        name: String,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// `field=expr`
+    Assign { target: String, expr: Expr },
+    Eval(Expr),
+}
+
+fn double_char_op(c: char, next: Option<&char>) -> Option<&'static str> {
+    match (c, next) {
+        ('=', Some('=')) => Some("=="),
+        ('!', Some('=')) => Some("!="),
+        ('<', Some('=')) => Some("<="),
+        ('>', Some('=')) => Some(">="),
+        _ => None,
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                //`1.len()` 这类情况下 '.' 属于方法调用
+                if chars[i] == '.' && !(i + 1 < chars.len() && chars[i + 1].is_ascii_digit()) {
+                    break;
+                }
+                i += 1;
+            }
+            let number_str: String = chars[start..i].iter().collect();
+            let value = if let Ok(int) = number_str.parse::<i64>() {
+                Value::from(int)
+            } else {
+                let float = number_str.parse::<f64>().map_err(|_| format!("无效数字: {number_str}"))?;
+                Value::from(float)
+            };
+            tokens.push(Token::Literal(value));
+        } else if c == '"' || c == '\'' || c == '`' {
+            let quote = c;
+            let mut string = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != quote {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    i += 1;
+                }
+                string.push(chars[i]);
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(format!("字符串未闭合: {input}"));
+            }
+            i += 1;
+            tokens.push(Token::Literal(Value::String(string)));
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = if c == '$' { i + 1 } else { i };
+            i = start;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            if name.is_empty() {
+                return Err(format!("无效变量名: {input}"));
+            }
+            if c == '$' {
+                tokens.push(Token::Param(name));
+            } else {
+                match name.as_str() {
+                    "true" => tokens.push(Token::Literal(Value::Bool(true))),
+                    "false" => tokens.push(Token::Literal(Value::Bool(false))),
+                    "null" => tokens.push(Token::Literal(Value::Null)),
+                    _ => tokens.push(Token::Ident(name)),
+                }
+            }
+        } else if let Some(op) = double_char_op(c, chars.get(i + 1)) {
+            tokens.push(Token::Op(op));
+            i += 2;
+        } else {
+            let token = match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ',' => Token::Comma,
+                '.' => Token::Dot,
+                '+' => Token::Op("+"),
+                '-' => Token::Op("-"),
+                '*' => Token::Op("*"),
+                '/' => Token::Op("/"),
+                '%' => Token::Op("%"),
+                '<' => Token::Op("<"),
+                '>' => Token::Op(">"),
+                '=' => Token::Op("="),
+                _ => { return Err(format!("无法识别的字符 '{c}' in {input}")); }
+            };
+            tokens.push(token);
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            other => Err(format!("期望 {expected:?}, 实际 {other:?}")),
+        }
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Some(Token::Op(o)) if *o == op)
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_additive()?;
+        let op = match self.peek() {
+            Some(Token::Op("==")) => BinaryOp::Eq,
+            Some(Token::Op("!=")) => BinaryOp::Ne,
+            Some(Token::Op("<")) => BinaryOp::Lt,
+            Some(Token::Op("<=")) => BinaryOp::Le,
+            Some(Token::Op(">")) => BinaryOp::Gt,
+            Some(Token::Op(">=")) => BinaryOp::Ge,
+            _ => { return Ok(left); }
+        };
+        self.position += 1;
+        let right = self.parse_additive()?;
+        Ok(Expr::Binary { op, left: Box::new(left), right: Box::new(right) })
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_term()?;
+        loop {
+            let op = if self.is_op("+") {
+                BinaryOp::Add
+            } else if self.is_op("-") {
+                BinaryOp::Sub
+            } else {
+                break;
+            };
+            self.position += 1;
+            let right = self.parse_term()?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = if self.is_op("*") {
+                BinaryOp::Mul
+            } else if self.is_op("/") {
+                BinaryOp::Div
+            } else if self.is_op("%") {
+                BinaryOp::Mod
+            } else if matches!(left, Expr::Literal(Value::Number(_))) && matches!(self.peek(), Some(Token::Ident(_)) | Some(Token::LParen)) {
+                //`2c` 视为 `2*c`
+                let right = self.parse_unary()?;
+                left = Expr::Binary { op: BinaryOp::Mul, left: Box::new(left), right: Box::new(right) };
+                continue;
+            } else {
+                break;
+            };
+            self.position += 1;
+            let right = self.parse_unary()?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.is_op("-") {
+            self.position += 1;
+            let expr = self.parse_unary()?;
+            return Ok(Expr::Neg(Box::new(expr)));
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        while let Some(Token::Dot) = self.peek() {
+            self.position += 1;
+            let name = match self.next() {
+                Some(Token::Ident(name)) => name,
+                other => { return Err(format!("'.' 之后期望名称, 实际 {other:?}")); }
+            };
+            if let Some(Token::LParen) = self.peek() {
+                if !METHODS.contains(&name.as_str()) && !ARRAY_QUERY_METHODS.contains(&name.as_str()) {
+                    return Err(format!("未知方法: .{name}()"));
+                }
+                let args = self.parse_args()?;
+                expr = Expr::Method { target: Box::new(expr), name, args };
+            } else if let Expr::Field(path) = expr {
+                expr = Expr::Field(format!("{path}.{name}"));
+            } else {
+                return Err(format!("无法访问 .{name}"));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<Expr>, String> {
+        self.expect(Token::LParen)?;
+        let mut args = Vec::new();
+        if let Some(Token::RParen) = self.peek() {
+            self.position += 1;
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_expr()?);
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(Token::RParen) => { break; }
+                other => { return Err(format!("参数列表中期望 ',' 或 ')', 实际 {other:?}")); }
+            }
+        }
+        Ok(args)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::Param(name)) => {
+                if name == "record" {
+                    Ok(Expr::Record)
+                } else {
+                    Ok(Expr::Param(name))
+                }
+            }
+            Some(Token::Ident(name)) => {
+                if let Some(Token::LParen) = self.peek() {
+                    if !FUNCTIONS.contains(&name.as_str()) && !AGGREGATE_FUNCTIONS.contains(&name.as_str()) {
+                        return Err(format!("未知函数: {name}()"));
+                    }
+                    let args = self.parse_args()?;
+                    Ok(Expr::Call { name, args })
+                } else {
+                    Ok(Expr::Field(name))
+                }
+            }
+            Some(Token::LParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            other => Err(format!("无法解析表达式, 位置 {} : {other:?}", self.position)),
+        }
+    }
+}
+
+pub fn parse_expr(input: &str) -> Result<Expr, String> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens, position: 0 };
+    let expr = parser.parse_expr()?;
+    if parser.position < parser.tokens.len() {
+        return Err(format!("表达式存在多余内容: {input}"));
+    }
+    Ok(expr)
+}
+
+/// 解析 `field=expr` 或单独的表达式
+pub fn parse_statement(input: &str) -> Result<Statement, String> {
+    let tokens = tokenize(input)?;
+    let mut depth = 0;
+    let assign_index = tokens.iter().position(|t| {
+        match t {
+            Token::LParen => { depth += 1; }
+            Token::RParen => { depth -= 1; }
+            _ => {}
+        }
+        depth == 0 && *t == Token::Op("=")
+    });
+    if let Some(index) = assign_index {
+        let mut target = String::new();
+        for (i, token) in tokens[..index].iter().enumerate() {
+            match token {
+                Token::Ident(name) if i % 2 == 0 => target.push_str(name),
+                Token::Dot if i % 2 == 1 => target.push('.'),
+                _ => { return Err(format!("无效赋值目标: {input}")); }
+            }
+        }
+        if target.is_empty() || target.ends_with('.') {
+            return Err(format!("无效赋值目标: {input}"));
+        }
+        let mut parser = Parser { tokens: tokens[index + 1..].to_vec(), position: 0 };
+        let expr = parser.parse_expr()?;
+        if parser.position < parser.tokens.len() {
+            return Err(format!("表达式存在多余内容: {input}"));
+        }
+        return Ok(Statement::Assign { target, expr });
+    }
+    let expr = parse_expr(input)?;
+    Ok(Statement::Eval(expr))
+}
+
+/// 按 `a.b.c` 路径读取
+pub fn get_value_by_path<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut current = record;
+    for key in path.split('.') {
+        current = current.as_object()?.get(key)?;
+    }
+    Some(current)
+}
+
+/// 按路径取全部值, `tags[]` 展开数组中的每个元素, 也可继续取下层字段, 如 `items[].name`
+pub fn get_values_by_path<'a>(record: &'a Value, path: &str) -> Vec<&'a Value> {
+    let mut current = vec![record];
+    for key in path.split('.') {
+        let (key, is_array) = match key.strip_suffix("[]") {
+            Some(key) => (key, true),
+            None => (key, false),
+        };
+        let mut next = Vec::new();
+        for value in current {
+            match value.as_object().and_then(|object| object.get(key)) {
+                Some(Value::Array(list)) if is_array => next.extend(list.iter()),
+                Some(child) => next.push(child),
+                None => {}
+            }
+        }
+        current = next;
+    }
+    current
+}
+
+/// 按 `a.b.c` 路径删除
+pub fn remove_value_by_path(record: &mut Value, path: &str) -> Option<Value> {
+    match path.rsplit_once('.') {
+        None => record.as_object_mut()?.remove(path),
+        Some((parent, key)) => {
+            let mut current = record;
+            for parent_key in parent.split('.') {
+                current = current.as_object_mut()?.get_mut(parent_key)?;
+            }
+            current.as_object_mut()?.remove(key)
+        }
+    }
+}
+
+/// 按 `a.b.c` 路径写入, 中间层不存在时自动创建
+pub fn set_value_by_path(record: &mut Value, path: &str, value: Value) {
+    let mut current = record;
+    let mut keys = path.split('.').peekable();
+    while let Some(key) = keys.next() {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+        let map = current.as_object_mut().unwrap();
+        if keys.peek().is_none() {
+            map.insert(key.to_string(), value);
+            return;
+        }
+        current = map.entry(key.to_string()).or_insert(Value::Object(Map::new()));
+    }
+}
+
+fn resolve_field(path: &str, record: &Value, scope: &dyn Scope) -> Value {
+    if let Some(value) = get_value_by_path(record, path) {
+        return value.clone();
+    }
+    let (head, rest) = match path.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (path, None),
+    };
+    if let Some(variable) = scope.variable(head) {
+        return match rest {
+            None => variable,
+            Some(rest) => get_value_by_path(&variable, rest).cloned().unwrap_or(Value::Null),
+        };
+    }
+    Value::Null
+}
+
+/// 字符串拼接时的文本形式, null 视为空串
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Value {
+    match op {
+        BinaryOp::Eq => { return Value::Bool(values_equal(left, right)); }
+        BinaryOp::Ne => { return Value::Bool(!values_equal(left, right)); }
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            //不同类型之间不比较大小
+            if left.is_null() || right.is_null() || type_rank(left) != type_rank(right) {
+                return Value::Bool(false);
+            }
+            let ordering = compare_values(left, right);
+            let result = match op {
+                BinaryOp::Lt => ordering == Ordering::Less,
+                BinaryOp::Le => ordering != Ordering::Greater,
+                BinaryOp::Gt => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            };
+            return Value::Bool(result);
+        }
+        BinaryOp::Add if left.is_string() || right.is_string() => {
+            return Value::String(value_to_string(left) + value_to_string(right).as_str());
+        }
+        _ => {}
+    }
+    if let (Some(l), Some(r)) = (left.as_i64(), right.as_i64()) {
+        let result = match op {
+            BinaryOp::Add => l.checked_add(r),
+            BinaryOp::Sub => l.checked_sub(r),
+            BinaryOp::Mul => l.checked_mul(r),
+            BinaryOp::Div => {
+                if r != 0 && l % r == 0 { l.checked_div(r) } else { None }
+            }
+            BinaryOp::Mod => l.checked_rem(r),
+            _ => None,
+        };
+        if let Some(int) = result {
+            return Value::from(int);
+        }
+    }
+    if let (Some(l), Some(r)) = (left.as_f64(), right.as_f64()) {
+        let result = match op {
+            BinaryOp::Add => l + r,
+            BinaryOp::Sub => l - r,
+            BinaryOp::Mul => l * r,
+            BinaryOp::Div => l / r,
+            BinaryOp::Mod => l % r,
+            _ => f64::NAN,
+        };
+        return serde_json::Number::from_f64(result).map(Value::Number).unwrap_or(Value::Null);
+    }
+    Value::Null
+}
+
+fn round_number(value: &Value, round: fn(f64) -> f64) -> Value {
+    if value.is_i64() || value.is_u64() {
+        return value.clone();
+    }
+    match value.as_f64() {
+        Some(number) => {
+            let rounded = round(number);
+            if rounded.abs() < i64::MAX as f64 {
+                Value::from(rounded as i64)
+            } else {
+                Value::from(rounded)
+            }
+        }
+        None => Value::Null,
+    }
+}
+
+/// call_function 支持的函数, 分组时另有 AGGREGATE_FUNCTIONS
+const FUNCTIONS: [&str; 12] = ["timestamp", "len", "lower", "upper", "trim", "concat", "str", "abs", "round", "floor", "ceil", "ifnull"];
+
+fn call_function(name: &str, args: &[Value]) -> Value {
+    let first = args.first().unwrap_or(&Value::Null);
+    match name {
+        "timestamp" => Value::from(get_timestamp() as u64),
+        "len" | "lower" | "upper" | "trim" => call_method(first, name, &args[args.len().min(1)..]),
+        "concat" => Value::String(args.iter().map(value_to_string).collect()),
+        "str" => Value::String(value_to_string(first)),
+        "abs" => arithmetic(BinaryOp::Mul, first, &Value::from(if first.as_f64().unwrap_or(0.0) < 0.0 { -1 } else { 1 })),
+        "round" => round_number(first, f64::round),
+        "floor" => round_number(first, f64::floor),
+        "ceil" => round_number(first, f64::ceil),
+        "ifnull" => if first.is_null() { args.get(1).cloned().unwrap_or(Value::Null) } else { first.clone() },
+        //未知函数在解析时已拒绝, 聚合函数在分组之外为 null
+        _ => Value::Null,
+    }
+}
+
+/// 对单条 record 求值, 无法求值时返回 Null
+pub fn evaluate(expr: &Expr, record: &Value, scope: &dyn Scope) -> Value {
+    match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Field(path) => resolve_field(path, record, scope),
+        Expr::Param(name) => scope.param(name).or_else(|| scope.variable(name)).unwrap_or(Value::Null),
+        Expr::Record => record.clone(),
+        Expr::Neg(inner) => {
+            let value = evaluate(inner, record, scope);
+            arithmetic(BinaryOp::Sub, &Value::from(0), &value)
+        }
+        Expr::Binary { op, left, right } => {
+            let left_value = evaluate(left, record, scope);
+            let right_value = evaluate(right, record, scope);
+            arithmetic(*op, &left_value, &right_value)
+        }
+        Expr::Call { name, args } => {
+            let arg_values: Vec<Value> = args.iter().map(|arg| evaluate(arg, record, scope)).collect();
+            call_function(name, &arg_values)
+        }
+        Expr::Method { name, .. } if ARRAY_QUERY_METHODS.contains(&name.as_str()) => {
+            evaluate_array_query(expr, record, scope)
+        }
+        Expr::Method { target, name, args } => {
+            let target_value = evaluate(target, record, scope);
+            let arg_values: Vec<Value> = args.iter().map(|arg| evaluate(arg, record, scope)).collect();
+            call_method(&target_value, name, &arg_values)
+        }
+    }
+}
+
+/// 数组字段上的子查询方法, 如 `like_books.select().orderby("num_liked").limit(30).skip(20)`
+const ARRAY_QUERY_METHODS: [&str; 5] = ["select", "where", "orderby", "limit", "skip"];
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(bool) => *bool,
+        Value::Number(number) => number.as_f64().unwrap_or(0.0) != 0.0,
+        Value::String(string) => !string.is_empty(),
+        _ => true,
+    }
+}
+
+/// 子查询方法链的根字段, 用作 FIELD 中的输出字段名
+pub fn array_query_root(expr: &Expr) -> Option<&str> {
+    let mut current = expr;
+    while let Expr::Method { target, name, .. } = current {
+        if !ARRAY_QUERY_METHODS.contains(&name.as_str()) {
+            return None;
+        }
+        current = target;
+    }
+    match current {
+        Expr::Field(path) if !std::ptr::eq(current, expr) => Some(path.as_str()),
+        _ => None,
+    }
+}
+
+/// 整条方法链按 where -> orderby -> skip -> limit -> select 的顺序执行, 与链中的书写顺序无关
+fn evaluate_array_query(expr: &Expr, record: &Value, scope: &dyn Scope) -> Value {
+    let mut chain = Vec::new();
+    let mut current = expr;
+    while let Expr::Method { target, name, args } = current {
+        if !ARRAY_QUERY_METHODS.contains(&name.as_str()) {
+            break;
+        }
+        chain.push((name.as_str(), args));
+        current = target;
+    }
+    chain.reverse();
+
+    let mut list = match evaluate(current, record, scope) {
+        Value::Array(list) => list,
+        Value::Null => { return Value::Null; }
+        other => vec![other],
+    };
+    let mut order_keys: Vec<(String, bool)> = Vec::new();
+    let mut skip = 0;
+    let mut limit = usize::MAX;
+    let mut select_fields: Vec<String> = Vec::new();
+
+    for (name, args) in chain {
+        let arg_value = |index: usize| args.get(index).map(|arg| evaluate(arg, record, scope)).unwrap_or(Value::Null);
+        match name {
+            "where" => {
+                //条件以数组元素为当前 record 求值
+                for arg in args {
+                    list.retain(|item| is_truthy(&evaluate(arg, item, scope)));
+                }
+            }
+            "orderby" => {
+                if let Some(field) = arg_value(0).as_str() {
+                    let is_desc = arg_value(1).as_str().map(|d| d.eq_ignore_ascii_case("DESC")).unwrap_or(false);
+                    match field.strip_prefix('-') {
+                        Some(field) => order_keys.push((field.to_string(), true)),
+                        None => order_keys.push((field.to_string(), is_desc)),
+                    }
+                }
+            }
+            "skip" => { skip = arg_value(0).as_u64().unwrap_or(0) as usize; }
+            "limit" => { limit = arg_value(0).as_u64().map(|n| n as usize).unwrap_or(usize::MAX); }
+            _ => {
+                select_fields = (0..args.len()).filter_map(|i| arg_value(i).as_str().map(|f| f.to_string())).collect();
+            }
+        }
+    }
+
+    if !order_keys.is_empty() {
+        list.sort_by(|a, b| {
+            for (field, is_desc) in &order_keys {
+                let a_value = get_value_by_path(a, field).unwrap_or(&Value::Null);
+                let b_value = get_value_by_path(b, field).unwrap_or(&Value::Null);
+                let ordering = if *is_desc { compare_values(b_value, a_value) } else { compare_values(a_value, b_value) };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+    }
+
+    let list = list.into_iter().skip(skip).take(limit).map(|item| {
+        if select_fields.is_empty() {
+            return item;
+        }
+        let mut sub_item = Value::Object(Map::new());
+        for field in &select_fields {
+            if let Some(value) = get_value_by_path(&item, field) {
+                set_value_by_path(&mut sub_item, field, value.clone());
+            }
+        }
+        sub_item
+    }).collect();
+    Value::Array(list)
+}
+
+/// UPDATE 中单独出现时会写回目标字段的方法, 如 `like_books.push(NewBook)`
+const MUTATING_METHODS: [&str; 1] = ["push"];
+
+/// call_method 支持的方法, `hide` 只用于 FIELD 中隐藏字段; 数组子查询方法见 ARRAY_QUERY_METHODS
+const METHODS: [&str; 6] = ["len", "lower", "upper", "trim", "push", "hide"];
+
+fn call_method(target: &Value, name: &str, args: &[Value]) -> Value {
+    match name {
+        "len" => match target {
+            Value::Array(list) => Value::from(list.len()),
+            Value::String(string) => Value::from(string.chars().count()),
+            Value::Object(map) => Value::from(map.len()),
+            Value::Null => Value::from(0),
+            _ => Value::Null,
+        },
+        "lower" => target.as_str().map(|s| Value::from(s.to_lowercase())).unwrap_or(Value::Null),
+        "upper" => target.as_str().map(|s| Value::from(s.to_uppercase())).unwrap_or(Value::Null),
+        "trim" => target.as_str().map(|s| Value::from(s.trim())).unwrap_or(Value::Null),
+        "push" => {
+            let mut list = match target {
+                Value::Array(list) => list.clone(),
+                Value::Null => Vec::new(),
+                other => vec![other.clone()],
+            };
+            list.extend(args.iter().cloned());
+            Value::Array(list)
+        }
+        //未知方法在解析时已拒绝, `hide` 在 FIELD 之外为 null
+        _ => Value::Null,
+    }
+}
+
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// JSON 值的全序: null < bool < number < string < array < object
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Number(x), Value::Number(y)) => {
+            if let (Some(x), Some(y)) = (x.as_i64(), y.as_i64()) {
+                x.cmp(&y)
+            } else {
+                x.as_f64().unwrap_or(0.0).total_cmp(&y.as_f64().unwrap_or(0.0))
+            }
+        }
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Array(x), Value::Array(y)) => {
+            for (item_x, item_y) in x.iter().zip(y.iter()) {
+                let ordering = compare_values(item_x, item_y);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (Value::Object(_), Value::Object(_)) => {
+            serde_json::to_string(a).unwrap_or_default().cmp(&serde_json::to_string(b).unwrap_or_default())
+        }
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+/// 数值按大小比较(1 == 1.0), 其余按JSON相等
+pub fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(_), Value::Number(_)) => compare_values(a, b) == Ordering::Equal,
+        _ => a == b,
+    }
+}
+
+const AGGREGATE_FUNCTIONS: [&str; 6] = ["count", "sum", "avg", "min", "max", "collect"];
+
+fn aggregate(name: &str, args: &[Expr], records: &[Value], scope: &dyn Scope) -> Value {
+    let values: Vec<Value> = match args.first() {
+        None => records.to_vec(),
+        Some(arg) => records.iter().map(|record| evaluate(arg, record, scope)).collect(),
+    };
+    match name {
+        "count" => {
+            if args.is_empty() {
+                Value::from(records.len())
+            } else {
+                Value::from(values.iter().filter(|v| !v.is_null()).count())
+            }
+        }
+        "sum" => {
+            values.iter().filter(|v| v.is_number()).fold(Value::from(0), |sum, v| arithmetic(BinaryOp::Add, &sum, v))
+        }
+        "avg" => {
+            let numbers: Vec<f64> = values.iter().filter_map(|v| v.as_f64()).collect();
+            if numbers.is_empty() {
+                Value::Null
+            } else {
+                Value::from(numbers.iter().sum::<f64>() / numbers.len() as f64)
+            }
+        }
+        "min" => values.into_iter().filter(|v| !v.is_null()).min_by(compare_values).unwrap_or(Value::Null),
+        "max" => values.into_iter().filter(|v| !v.is_null()).max_by(compare_values).unwrap_or(Value::Null),
+        "collect" => Value::Array(values),
+        _ => Value::Null,
+    }
+}
+
+/// GROUP 中对分组行求值, `count()` `sum(x)` 等聚合函数作用于组内全部 records
+pub fn evaluate_aggregate(expr: &Expr, row: &Value, records: &[Value], scope: &dyn Scope) -> Value {
+    match expr {
+        Expr::Call { name, args } if AGGREGATE_FUNCTIONS.contains(&name.as_str()) => {
+            aggregate(name, args, records, scope)
+        }
+        Expr::Neg(inner) => {
+            let value = evaluate_aggregate(inner, row, records, scope);
+            arithmetic(BinaryOp::Sub, &Value::from(0), &value)
+        }
+        Expr::Binary { op, left, right } => {
+            let left_value = evaluate_aggregate(left, row, records, scope);
+            let right_value = evaluate_aggregate(right, row, records, scope);
+            arithmetic(*op, &left_value, &right_value)
+        }
+        _ => evaluate(expr, row, scope),
+    }
+}
+
+/// 将 UPDATE 语句作用到 record 上
+pub fn apply_statement(record: &mut Value, statement: &Statement, scope: &dyn Scope) {
+    match statement {
+        Statement::Assign { target, expr } => {
+            let value = evaluate(expr, record, scope);
+            set_value_by_path(record, target, value);
+        }
+        Statement::Eval(expr) => {
+            if let Expr::Method { target, name, .. } = expr {
+                if let Expr::Field(path) = target.as_ref() {
+                    if MUTATING_METHODS.contains(&name.as_str()) {
+                        let value = evaluate(expr, record, scope);
+                        set_value_by_path(record, path, value);
+                        return;
+                    }
+                }
+            }
+            let _ = evaluate(expr, record, scope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::minimongo::expression::{apply_statement, array_query_root, evaluate, evaluate_aggregate, parse_expr, parse_statement, Scope, Statement};
+
+    struct EmptyScope;
+
+    impl Scope for EmptyScope {
+        fn param(&self, _name: &str) -> Option<Value> { None }
+        fn variable(&self, _name: &str) -> Option<Value> { None }
+    }
+
+    //cargo test test_evaluate_arithmetic -- --show-output
+    #[test]
+    fn test_evaluate_arithmetic() {
+        let record = json!({"b": 3, "c": 1.5, "author": {"age": 40}});
+        let cases = vec![
+            ("b+2c", json!(6.0)),
+            ("(b+1)*2", json!(8)),
+            ("7/2", json!(3.5)),
+            ("-b", json!(-3)),
+            ("author.age-1", json!(39)),
+            ("missing+1", Value::Null),
+        ];
+        for (input, expected) in cases {
+            let expr = parse_expr(input).unwrap();
+            let value = evaluate(&expr, &record, &EmptyScope);
+            println!("{input} => {value}");
+            assert_eq!(value, expected);
+        }
+    }
+
+    //cargo test test_evaluate_projection -- --show-output
+    #[test]
+    fn test_evaluate_projection() {
+        let record = json!({"name": "Rust Book", "price": 12.5, "num": 7, "tags": ["a", "b"]});
+        let cases = vec![
+            ("name + \" by \" + num", json!("Rust Book by 7")),
+            ("lower(name)", json!("rust book")),
+            ("name.upper()", json!("RUST BOOK")),
+            ("len(tags)", json!(2)),
+            ("tags.len() == 2", json!(true)),
+            ("price >= 12.5", json!(true)),
+            ("price < num", json!(false)),
+            ("name != \"Rust Book\"", json!(false)),
+            ("num % 4", json!(3)),
+            ("round(price)", json!(13)),
+            ("concat(name, \":\", num)", json!("Rust Book:7")),
+            ("ifnull(missing, 0)", json!(0)),
+            ("timestamp() > 0", json!(true)),
+        ];
+        for (input, expected) in cases {
+            let expr = parse_expr(input).unwrap();
+            let value = evaluate(&expr, &record, &EmptyScope);
+            println!("{input} => {value}");
+            assert_eq!(value, expected);
+        }
+        assert!(matches!(parse_statement("is_cheap=price<=20").unwrap(), Statement::Assign { ref target, .. } if target == "is_cheap"));
+    }
+
+    //cargo test test_evaluate_array_query -- --show-output
+    #[test]
+    fn test_evaluate_array_query() {
+        let like_books: Vec<Value> = (0..50).map(|i| json!({"name": format!("B{i}"), "num_liked": (i * 7) % 50, "price": i})).collect();
+        let record = json!({"like_books": like_books});
+
+        let expr = parse_expr("like_books.select().orderby(\"num_liked\").limit(30).skip(20)").unwrap();
+        assert_eq!(array_query_root(&expr), Some("like_books"));
+        let value = evaluate(&expr, &record, &EmptyScope);
+        let list = value.as_array().unwrap();
+        assert_eq!(list.len(), 30);
+        assert_eq!(list[0]["num_liked"], json!(20));
+
+        let expr = parse_expr("like_books.where(price >= 45).orderby(\"price\", \"DESC\").select(\"name\")").unwrap();
+        let value = evaluate(&expr, &record, &EmptyScope);
+        assert_eq!(value, json!([{"name": "B49"}, {"name": "B48"}, {"name": "B47"}, {"name": "B46"}, {"name": "B45"}]));
+
+        let expr = parse_expr("like_books.where(num_liked < 10).len()").unwrap();
+        assert_eq!(array_query_root(&expr), None);
+        assert_eq!(evaluate(&expr, &record, &EmptyScope), json!(10));
+
+        let expr = parse_expr("like_books.orderby(\"-price\").skip(48)").unwrap();
+        assert_eq!(evaluate(&expr, &record, &EmptyScope), json!([like_books_at(&record, 1), like_books_at(&record, 0)]));
+
+        let statement = parse_statement("top=like_books.where(num_liked==0)").unwrap();
+        assert!(matches!(statement, Statement::Assign { ref target, .. } if target == "top"));
+    }
+
+    fn like_books_at(record: &Value, index: usize) -> Value {
+        record["like_books"][index].clone()
+    }
+
+    //cargo test test_apply_statement -- --show-output
+    #[test]
+    fn test_apply_statement() {
+        let mut record = json!({"name": "A1", "age": 10});
+        let statement = parse_statement("create_time = timestamp()").unwrap();
+        assert!(matches!(statement, Statement::Assign { ref target, .. } if target == "create_time"));
+        apply_statement(&mut record, &statement, &EmptyScope);
+        assert!(record["create_time"].as_u64().unwrap() > 0);
+
+        let statement = parse_statement("stats.age=age+1").unwrap();
+        apply_statement(&mut record, &statement, &EmptyScope);
+        assert_eq!(record["stats"]["age"], json!(11));
+
+        assert!(parse_statement("a+1=2").is_err());
+        //未知的函数与方法在解析时拒绝
+        assert!(parse_statement("a=timestmp()").is_err());
+        assert!(parse_statement("name.lowercase()").is_err());
+        assert!(parse_statement("n=count()").is_ok());
+    }
+
+    struct BookScope;
+
+    impl Scope for BookScope {
+        fn param(&self, _name: &str) -> Option<Value> { None }
+        fn variable(&self, name: &str) -> Option<Value> {
+            match name {
+                "NewBook" => Some(json!({"name": "B3"})),
+                "MyBooks" => Some(json!([{"name": "B1"}, {"name": "B2"}])),
+                _ => None,
+            }
+        }
+    }
+
+    //cargo test test_apply_method_statement -- --show-output
+    #[test]
+    fn test_apply_method_statement() {
+        let mut record = json!({"name": "U1", "like_books": [{"name": "B1"}]});
+        for input in ["like_books.push(NewBook)", "num_books=MyBooks.len()", "tags.push(\"a\", \"b\")", "name_len=name.len()"] {
+            let statement = parse_statement(input).unwrap();
+            apply_statement(&mut record, &statement, &BookScope);
+        }
+        println!("{record:#}");
+        assert_eq!(record["like_books"], json!([{"name": "B1"}, {"name": "B3"}]));
+        assert_eq!(record["num_books"], json!(2));
+        assert_eq!(record["tags"], json!(["a", "b"]));
+        assert_eq!(record["name_len"], json!(2));
+    }
+
+    //cargo test test_evaluate_aggregate -- --show-output
+    #[test]
+    fn test_evaluate_aggregate() {
+        let records = vec![
+            json!({"name": "A", "price": 10}),
+            json!({"name": "B", "price": 2.5}),
+            json!({"name": "C"}),
+        ];
+        let row = json!({"book_type": "Math"});
+        let cases = vec![
+            ("count()", json!(3)),
+            ("count(price)", json!(2)),
+            ("sum(price)", json!(12.5)),
+            ("avg(price)", json!(6.25)),
+            ("min(price)", json!(2.5)),
+            ("max(name)", json!("C")),
+            ("collect(name)", json!(["A", "B", "C"])),
+            ("sum(price)/count()", json!(12.5 / 3.0)),
+        ];
+        for (input, expected) in cases {
+            let expr = parse_expr(input).unwrap();
+            let value = evaluate_aggregate(&expr, &row, &records, &EmptyScope);
+            println!("{input} => {value}");
+            assert_eq!(value, expected);
+        }
+    }
+}
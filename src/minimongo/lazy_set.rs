@@ -2,7 +2,13 @@
 
 use std::collections::BTreeSet;
 use serde::{Deserialize, Serialize};
-use crate::minimongo::query::ConditionOperation;
+
+/// 两个惰性集合的合并方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SetOperation {
+    And,
+    Or,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LazySet {
@@ -10,8 +16,6 @@ pub struct LazySet {
     is_complement: bool, // 是否是补集
 }
 
-pub const MAX_FULL_LEN: u32 = 1000;
-
 impl LazySet {
     // 创建普通集合
     pub(crate) fn new(ids: BTreeSet<u32>) -> Self {
@@ -29,36 +33,27 @@ impl LazySet {
         }
     }
 
-    // 计算补集（全集减去当前集合）
-    pub(crate) fn evaluate(&self) -> BTreeSet<u32> {
-        if self.is_complement {
-            BTreeSet::new()
-        } else {
-            self.ids.clone()
-        }
+    pub(crate) fn is_complement(&self) -> bool {
+        self.is_complement
     }
 
-    pub(crate) fn evaluate_if<F1, F2>(self, get_full_set: F1, get_set_len: F2) -> BTreeSet<u32>
-    where
-        F1: Fn() -> BTreeSet<u32>,
-        F2: Fn() -> u32,
-    {
-        if self.is_complement {
-            if get_set_len() < MAX_FULL_LEN {
-                let full_set = get_full_set();
-                full_set.difference(&self.ids).cloned().collect()
-            } else {
-                BTreeSet::new()
-            }
-        } else {
-            self.ids
+    // 取反: 普通集合与补集互换, 不需要读取全集
+    pub(crate) fn not(self) -> Self {
+        Self {
+            ids: self.ids,
+            is_complement: !self.is_complement,
         }
     }
 
+    // 判断 id 是否属于集合, 补集时即 id 不在被排除的集合中
+    pub(crate) fn contains(&self, id: &u32) -> bool {
+        self.ids.contains(id) != self.is_complement
+    }
+
     // 合并两个惰性集合（支持 AND 和 OR）
-    pub(crate) fn merge(&self, other: &Self, operation: ConditionOperation) -> Self {
+    pub(crate) fn merge(&self, other: &Self, operation: SetOperation) -> Self {
         match operation {
-            ConditionOperation::AND(_) => {
+            SetOperation::And => {
                 if self.is_complement && other.is_complement {
                     // A' AND B' -> (A OR B)'
                     let mut union = self.ids.clone();
@@ -78,18 +73,18 @@ impl LazySet {
                     Self::new(intersection)
                 }
             }
-            ConditionOperation::OR(_) => {
+            SetOperation::Or => {
                 if self.is_complement && other.is_complement {
                     // A' OR B' -> (A ∩ B)'
                     let intersection = self.ids.intersection(&other.ids).cloned().collect();
                     Self::complement(intersection)
                 } else if self.is_complement {
-                    // A' OR B -> A' ∪ B = 全集 - (A ∩ B)'
-                    let difference = other.ids.difference(&self.ids).cloned().collect();
+                    // A' OR B -> (A - B)'
+                    let difference = self.ids.difference(&other.ids).cloned().collect();
                     Self::complement(difference)
                 } else if other.is_complement {
-                    // A OR B' -> A ∪ B'
-                    let difference = self.ids.difference(&other.ids).cloned().collect();
+                    // A OR B' -> (B - A)'
+                    let difference = other.ids.difference(&self.ids).cloned().collect();
                     Self::complement(difference)
                 } else {
                     // A OR B -> A ∪ B
@@ -97,7 +92,6 @@ impl LazySet {
                     Self::new(union)
                 }
             }
-            _ => panic!("Invalid operation for merge"),
         }
     }
 }
\ No newline at end of file
@@ -1,17 +1,22 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Debug};
-use std::fs;
-use std::path::Path;
 use std::sync::{Arc, LazyLock, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use redb::{Database, ReadableTable, ReadTransaction, TableDefinition, MultimapTableDefinition, ReadableMultimapTable};
+use redb::{Database, ReadableTable, ReadableTableMetadata, ReadTransaction, TableDefinition, TableHandle, MultimapTableDefinition, ReadableMultimapTable, Table, WriteTransaction};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value};
-use crate::common::helper::hash_to_u32;
+use crate::common::helper::{get_timestamp, hash_to_u32};
+use crate::minimongo::config::{default_config, MgDbConfig};
+use crate::minimongo::expression::{get_value_by_path, get_values_by_path};
 use crate::minimongo::query::{UpdateType};
-use crate::minimongo::query_helper::{MyF64, open_table_read, open_table_write};
+use crate::minimongo::error::MgError;
+use crate::minimongo::workspace::{normalize_workspace_id, opened_workspace_config, register_workspace};
+use crate::minimongo::update_operator::build_record;
+use crate::minimongo::query_helper::{encode_compound_value, MyF64, open_table_read, open_table_write};
 
-static MGDB_MAP: LazyLock<RwLock<HashMap<String, Arc<MgDb>>>> = LazyLock::new(|| {
+/// 已打开的数据库, key 为数据文件路径
+pub(crate) static MGDB_MAP: LazyLock<RwLock<HashMap<String, Arc<MgDb>>>> = LazyLock::new(|| {
     let _map = HashMap::new();
     let _map_rw = RwLock::new(_map);
     _map_rw
@@ -20,6 +25,9 @@ static MGDB_MAP: LazyLock<RwLock<HashMap<String, Arc<MgDb>>>> = LazyLock::new(||
 pub struct MgDb {
     pub db: Database,
     pub _workspace_nanoid: String,
+    pub config: MgDbConfig,
+    /// 最近一次被 get_mgdb 取用的时间 (毫秒), 用于空闲关闭
+    pub last_access: AtomicU64,
     pub collection_map: RwLock<BTreeMap<String, Collection>>,
     pub counter_map: RwLock<BTreeMap<String, u32>>,
 }
@@ -30,6 +38,37 @@ pub struct Schema {
     indexes_f64: Vec<String>,
     indexes_string: Vec<String>,
     indexes_string_unique: Vec<String>,
+    /// 复合索引, 每项为按顺序排列的字段, 如 `["book_type", "price"]`
+    #[serde(default)]
+    indexes_compound: Vec<Vec<String>>,
+}
+
+/// update_records 的写入结果
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct UpdateResult {
+    pub num_created: u32,
+    pub num_updated: u32,
+    /// CreateOnlY 时已存在, 或 UpdateOnly 时不存在而未写入的 records
+    pub num_skipped: u32,
+    /// 主键或唯一索引冲突的 records
+    pub num_conflicts: u32,
+    /// 未写入的 records: 唯一索引冲突, 缺少主键或更新操作无法执行
+    pub rejected: Vec<RejectedRecord>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RejectedRecord {
+    pub primary_key: Option<String>,
+    pub reason: String,
+}
+
+/// 单个索引的定义, 与 Schema 中的四类索引对应, 如 `{"F64": "price"}`, `{"Compound": ["book_type", "price"]}`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum IndexDefine {
+    F64(String),
+    String(String),
+    StringUnique(String),
+    Compound(Vec<String>),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -41,6 +80,8 @@ pub struct Collection {
     pub indexes_f64_list: Vec<String>,
     pub indexes_string_list: Vec<String>,
     pub indexes_string_unique_list: Vec<String>,
+    #[serde(default)]
+    pub indexes_compound_list: Vec<Vec<String>>,
     //index
     //field_map
 }
@@ -52,40 +93,53 @@ pub struct Collection {
 //     Self::from_bytes(data1).cmp(&Self::from_bytes(data2))
 // }
 // }
-pub fn get_mgdb(workspace_nanoid: String) -> Arc<MgDb> {
+/// 使用默认配置打开 workspace, 默认配置可由 `set_default_config` 修改
+/// 已由 `open_workspace` 按另一种存储方式打开的 workspace 返回该实例
+pub fn get_mgdb(workspace_nanoid: String) -> Result<Arc<MgDb>, MgError> {
+    let config = opened_workspace_config(&default_config()?, &workspace_nanoid)?;
+    get_mgdb_with_config(workspace_nanoid, &config)
+}
+
+/// 按指定配置打开 workspace, 同一个数据文件只打开一次, 之后返回已打开的实例
+/// workspace id 先经 `normalize_workspace_id` 规范化, 原始 id 记入登记表
+pub fn get_mgdb_with_config(workspace_id: String, config: &MgDbConfig) -> Result<Arc<MgDb>, MgError> {
     let  need_create;
+    let workspace_nanoid = normalize_workspace_id(&workspace_id)?;
+    let db_key = config.db_key(&workspace_nanoid);
 
     {
-        let db_map_lock = MGDB_MAP.read().unwrap();
-        let db_option = db_map_lock.get(&workspace_nanoid);
+        let db_map_lock = MGDB_MAP.read()?;
+        let db_option = db_map_lock.get(&db_key);
         if let Some(db) = db_option {
-            return db.clone();
+            db.touch();
+            return Ok(db.clone());
         }
     }
 
-    let mut db_map_lock = MGDB_MAP.write().unwrap();
-    let db_option = db_map_lock.get(&workspace_nanoid);
+    let mut db_map_lock = MGDB_MAP.write()?;
+    let db_option = db_map_lock.get(&db_key);
     if let Some(db) = db_option {
-        return db.clone();
+        db.touch();
+        return Ok(db.clone());
     } else {
         need_create = true;
     }
 
-    let db = create_db(&workspace_nanoid);
+    let db = config.open_db(&workspace_nanoid)?;
     let mut need_init = false;
     let mut collection_map = BTreeMap::new();
     let mut counter_map = BTreeMap::new();
     {
-        let read_txn = db.begin_read().unwrap();
+        let read_txn = db.begin_read()?;
         let collection_define_table_result = read_txn.open_table(COLLECTION_DEFINE_TABLE);
         if let Ok(table) = collection_define_table_result {
-            let mut iter = table.iter().unwrap();
+            let mut iter = table.iter()?;
             while let Some(kv) = iter.next() {
                 if let Ok((key, value)) = kv {
                     // println!("读取collection value: {:?} @ {:?}", key.value(), value.value());
                     let collection_name = key.value();
                     let collections_str = value.value();
-                    let collection = serde_json::from_str(collections_str.as_str()).unwrap();
+                    let collection = serde_json::from_str(collections_str.as_str())?;
                     collection_map.insert(collection_name, collection);
                 }
             }
@@ -95,7 +149,7 @@ pub fn get_mgdb(workspace_nanoid: String) -> Arc<MgDb> {
 
         let counter_table_result = read_txn.open_table(COUNTER_TABLE);
         if let Ok(table) = counter_table_result {
-            let mut iter = table.iter().unwrap();
+            let mut iter = table.iter()?;
             '_label: while let Some(kv) = iter.next() {
                 if let Ok((key, value)) = kv {
                     let counter_name = key.value();
@@ -107,49 +161,64 @@ pub fn get_mgdb(workspace_nanoid: String) -> Arc<MgDb> {
             need_init = true;
         }
     }
-    if need_init {
+    //只读打开的空文件不做初始化, 读取时按没有 collection 处理
+    if need_init && !config.read_only {
         println!("需要初始化: COLLECTION_TABLE");
-        let write_txn = db.begin_write().unwrap();
+        let write_txn = db.begin_write()?;
         {
-            let _table = write_txn.open_table(COLLECTION_DEFINE_TABLE).unwrap();
-            let _table = write_txn.open_table(COUNTER_TABLE).unwrap();
+            let _table = write_txn.open_table(COLLECTION_DEFINE_TABLE)?;
+            let _table = write_txn.open_table(COUNTER_TABLE)?;
         }
-        write_txn.commit().unwrap();
+        write_txn.commit()?;
+    }
+    if !config.read_only && !config.in_memory {
+        register_workspace(config, &workspace_nanoid, &workspace_id)?;
     }
 
     let mg_db = MgDb {
         db,
         _workspace_nanoid: workspace_nanoid.clone(),
+        config: config.clone(),
+        last_access: AtomicU64::new(get_timestamp() as u64),
         collection_map: RwLock::new(collection_map),
         counter_map: RwLock::new(counter_map),
     };
     let db_arc = Arc::new(mg_db);
 
     if need_create {
-        db_map_lock.insert(workspace_nanoid, db_arc.clone());
+        db_map_lock.insert(db_key, db_arc.clone());
         drop(db_map_lock);
     }
-    return db_arc;
+    return Ok(db_arc);
 }
 
-fn create_db(workspace_nanoid: &String) -> Database {
-    fs::create_dir_all("MMG").unwrap();
-    let db_filename = format!("MMG/W_{}.db", workspace_nanoid);
-    let db_file = Path::new(&db_filename);
-    let db = Database::create(db_file).unwrap();
-    return db;
-}
 
 
 const COLLECTION_DEFINE_TABLE: TableDefinition<String, String> = TableDefinition::new("collection_define");
 const COUNTER_TABLE: TableDefinition<String, u32> = TableDefinition::new("counter");
 
 impl MgDb {
-    pub fn create_collection(&self, collection_name: String, schema: Schema) {
+    fn touch(&self) {
+        self.last_access.store(get_timestamp() as u64, Ordering::Relaxed);
+    }
+
+    /// 开启写事务, 按配置设置持久化级别; 只读打开时返回错误
+    pub(crate) fn begin_write(&self) -> Result<WriteTransaction, MgError> {
+        if self.config.read_only {
+            return Err(MgError::Constraint(format!("workspace 为只读: {}", self._workspace_nanoid)));
+        }
+        let mut write_txn = self.db.begin_write()?;
+        self.config.durability.apply(&mut write_txn);
+        Ok(write_txn)
+    }
+
+    pub fn create_collection(&self, collection_name: String, schema: Schema) -> Result<(), MgError> {
+        check_collection_name(&collection_name)?;
         let primary_key = schema.primary_key;
         let indexes_f64_list: Vec<String> = schema.indexes_f64;
         let indexes_string_list: Vec<String> = schema.indexes_string;
         let indexes_string_unique_list: Vec<String> = schema.indexes_string_unique;
+        let indexes_compound_list: Vec<Vec<String>> = schema.indexes_compound;
 
         let collection = Collection {
             collection_name: collection_name.clone(),
@@ -159,110 +228,293 @@ impl MgDb {
             indexes_f64_list,
             indexes_string_list,
             indexes_string_unique_list,
+            indexes_compound_list,
         };
 
         let mut count_number: u32 = 10_0000_0000;
 
         {
-            let write_txn = self.db.begin_write().unwrap();
+            let write_txn = self.begin_write()?;
             {
-                let mut collection_define_table = write_txn.open_table(COLLECTION_DEFINE_TABLE).unwrap();
-                let collections_str = serde_json::to_string(&collection).unwrap_or("{}".to_string());
-                collection_define_table.insert(collection_name.clone(), collections_str).unwrap();
+                let mut collection_define_table = write_txn.open_table(COLLECTION_DEFINE_TABLE)?;
+                let collections_str = serde_json::to_string(&collection)?;
+                collection_define_table.insert(collection_name.clone(), collections_str)?;
 
                 {
-                    let mut counter_map_lock = self.counter_map.write().unwrap();
+                    let mut counter_map_lock = self.counter_map.write()?;
                     let number_option = counter_map_lock.get(&collection_name);
                     if let Some(number) = number_option {
                         count_number = *number;
                     } else {
-                        let mut counter_table = write_txn.open_table(COUNTER_TABLE).unwrap();
-                        counter_table.insert(collection_name.clone(), count_number).unwrap();
+                        let mut counter_table = write_txn.open_table(COUNTER_TABLE)?;
+                        counter_table.insert(collection_name.clone(), count_number)?;
                     }
                     counter_map_lock.insert(collection_name.clone(), count_number);
 
                     drop(counter_map_lock);
                 }
 
-                let collection_table_define: TableDefinition<u32, String> = TableDefinition::new(collection_name.as_str());
-                let _collection_table = write_txn.open_table(collection_table_define).unwrap();
-
-                println!("新建_collection_table");
-
-                let collection_name_primary = format!("{}@primary", collection_name);
-                let _primary_key_table = open_table_write::<&str, u32>(&collection_name_primary, &write_txn);
+                open_collection_tables(&collection, &write_txn)?;
+            }
+            write_txn.commit()?;
+        }
+        {
+            let mut collection_map_lock = self.collection_map.write()?;
+            collection_map_lock.insert(collection_name, collection);
+            drop(collection_map_lock);
+        }
+        Ok(())
+    }
+    pub fn list_all_collections(&self) -> Result<Vec<Collection>, MgError> {
+        {
+            let collection_map_lock = self.collection_map.read()?;
+            let collections: Vec<Collection> = collection_map_lock.values().map(|c| c.clone()).collect();
+            let collections_clone = collections.clone();
+            return Ok(collections_clone);
+        }
+    }
 
-                {
-                    println!("新建_动态值_table");
-                    let collection_name_f64 = format!("{collection_name}#f64#");
-                    let _f64_table = open_table_write::<(u32, u32), f64>(&collection_name_f64, &write_txn);
-                    println!("新建_动态值 for: {}", collection_name_f64);
+    /// 为已有 collection 新增索引: 遍历全部 records 回填索引表, 并更新 collection_define
+    /// 唯一索引存在重复值时返回冲突的 `字段@值`, 此时不做任何修改
+    pub fn add_index(&self, collection_name: &String, index_define: IndexDefine) -> Result<Collection, MgError> {
+        let write_txn = self.begin_write()?;
+        //写事务期间持有写锁, 其他写入在提交后才能读到新的 collection 定义
+        let mut collection_map_lock = self.collection_map.write()?;
+        let Some(mut collection) = collection_map_lock.get(collection_name).cloned() else {
+            return Err(MgError::UnknownCollection(collection_name.clone()));
+        };
+        match &index_define {
+            IndexDefine::F64(field) | IndexDefine::String(field) | IndexDefine::StringUnique(field) => {
+                let single_indexes = [&collection.indexes_f64_list, &collection.indexes_string_list, &collection.indexes_string_unique_list];
+                if field.is_empty() || *field == collection.primary_key || single_indexes.iter().any(|list| list.contains(field)) {
+                    return Err(MgError::Constraint(format!("字段已有索引: {field}")));
                 }
-
-                println!("新建_indexes_tables");
-                for index_f64 in &collection.indexes_f64_list {
-                    let collection_name_index = format!("{}@f64@{}", collection_name, index_f64);
-                    let _index_table = open_table_write::<(MyF64, u32), ()>(&collection_name_index, &write_txn);
-                    println!("新建_index_f64 for: {}", collection_name_index);
+            }
+            IndexDefine::Compound(fields) => {
+                if fields.len() < 2 {
+                    return Err(MgError::Constraint("复合索引至少需要两个字段".to_string()));
+                }
+                if collection.indexes_compound_list.contains(fields) {
+                    return Err(MgError::Constraint(format!("复合索引已存在: {}", fields.join(","))));
                 }
+            }
+        }
 
-                for index_string in &collection.indexes_string_list {
-                    let collection_name_index = format!("{}@string@{}", collection_name, index_string);
+        let collection_name_index = index_table_name(collection_name, &index_define);
+        {
+            let collection_table = open_table_write::<u32, String>(collection_name, &write_txn)?;
+            let records = collection_table.iter()?.map(|kv| {
+                let (key_lock, value_lock) = kv?;
+                let record: Value = serde_json::from_str(value_lock.value().as_str()).unwrap_or(Value::Null);
+                Ok((key_lock.value(), record))
+            }).collect::<Result<Vec<(u32, Value)>, MgError>>()?;
+            match &index_define {
+                IndexDefine::F64(field) => {
+                    let field_id = hash_to_u32(field);
+                    let mut index_table = open_table_write::<(MyF64, u32), ()>(&collection_name_index, &write_txn)?;
+                    let collection_name_f64 = format!("{collection_name}#f64#");
+                    let mut f64_table = open_table_write::<(u32, u32), f64>(&collection_name_f64, &write_txn)?;
+                    for (record_id, record) in records {
+                        let numbers = index_numbers(Some(&record), field);
+                        for number in &numbers {
+                            index_table.insert((MyF64(*number), record_id), ())?;
+                        }
+                        if let Some(number) = numbers.first() {
+                            f64_table.insert((record_id, field_id), *number)?;
+                        }
+                    }
+                }
+                IndexDefine::String(field) => {
                     let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
-                    let _index_table = write_txn.open_multimap_table(index_table_define).unwrap();
-                    println!("新建index_string for: {}", collection_name_index);
+                    let mut index_table = write_txn.open_multimap_table(index_table_define)?;
+                    for (record_id, record) in records {
+                        for str in index_strings(Some(&record), field) {
+                            index_table.insert(str, record_id)?;
+                        }
+                    }
                 }
-
-                for index_string in &collection.indexes_string_unique_list {
-                    let collection_name_index = format!("{}@stringU@{}", collection_name, index_string);
-                    let _index_table = open_table_write::<&str, u32>(&collection_name_index, &write_txn);
-                    println!("新建index_string_unique for: {}", collection_name_index);
+                IndexDefine::StringUnique(field) => {
+                    let mut index_table = open_table_write::<&str, u32>(&collection_name_index, &write_txn)?;
+                    for (record_id, record) in records {
+                        for str in index_strings(Some(&record), field) {
+                            let conflict_record_id_option = index_table.insert(str, record_id)?.map(|id| id.value());
+                            if conflict_record_id_option.is_some_and(|conflict_record_id| conflict_record_id != record_id) {
+                                return Err(MgError::Constraint(format!("{field}@{str}")));
+                            }
+                        }
+                    }
+                }
+                IndexDefine::Compound(fields) => {
+                    let mut index_table = open_table_write::<(&[u8], u32), ()>(&collection_name_index, &write_txn)?;
+                    for (record_id, record) in records {
+                        for key in compound_keys(Some(&record), fields) {
+                            index_table.insert((key.as_slice(), record_id), ())?;
+                        }
+                    }
                 }
             }
-            write_txn.commit().unwrap();
         }
+
+        match index_define {
+            IndexDefine::F64(field) => { collection.indexes_f64_list.push(field); }
+            IndexDefine::String(field) => { collection.indexes_string_list.push(field); }
+            IndexDefine::StringUnique(field) => { collection.indexes_string_unique_list.push(field); }
+            IndexDefine::Compound(fields) => { collection.indexes_compound_list.push(fields); }
+        }
+        self.commit_collection_define(write_txn, &mut collection_map_lock, collection.clone())?;
+        println!("新增索引: {}", collection_name_index);
+        Ok(collection)
+    }
+
+    /// 删除已有索引及其索引表, 并更新 collection_define
+    pub fn drop_index(&self, collection_name: &String, index_define: IndexDefine) -> Result<Collection, MgError> {
+        let write_txn = self.begin_write()?;
+        let mut collection_map_lock = self.collection_map.write()?;
+        let Some(mut collection) = collection_map_lock.get(collection_name).cloned() else {
+            return Err(MgError::UnknownCollection(collection_name.clone()));
+        };
+        let collection_name_index = index_table_name(collection_name, &index_define);
+        let is_removed = match &index_define {
+            IndexDefine::F64(field) => remove_from_list(&mut collection.indexes_f64_list, field),
+            IndexDefine::String(field) => remove_from_list(&mut collection.indexes_string_list, field),
+            IndexDefine::StringUnique(field) => remove_from_list(&mut collection.indexes_string_unique_list, field),
+            IndexDefine::Compound(fields) => remove_from_list(&mut collection.indexes_compound_list, fields),
+        };
+        if !is_removed {
+            return Err(MgError::Constraint(format!("索引不存在: {collection_name_index}")));
+        }
+
+        match &index_define {
+            IndexDefine::F64(field) => {
+                write_txn.delete_table(TableDefinition::<(MyF64, u32), ()>::new(collection_name_index.as_str()))?;
+                //清理该字段在 #f64# 表中的动态值
+                let field_id = hash_to_u32(field);
+                let collection_name_f64 = format!("{collection_name}#f64#");
+                let mut f64_table = open_table_write::<(u32, u32), f64>(&collection_name_f64, &write_txn)?;
+                f64_table.retain(|(_record_id, id), _number| id != field_id)?;
+            }
+            IndexDefine::String(_) => {
+                write_txn.delete_multimap_table(MultimapTableDefinition::<&str, u32>::new(collection_name_index.as_str()))?;
+            }
+            IndexDefine::StringUnique(_) => {
+                write_txn.delete_table(TableDefinition::<&str, u32>::new(collection_name_index.as_str()))?;
+            }
+            IndexDefine::Compound(_) => {
+                write_txn.delete_table(TableDefinition::<(&[u8], u32), ()>::new(collection_name_index.as_str()))?;
+            }
+        }
+
+        self.commit_collection_define(write_txn, &mut collection_map_lock, collection.clone())?;
+        println!("删除索引: {}", collection_name_index);
+        Ok(collection)
+    }
+
+    /// 删除 collection: 数据表, 全部索引表, counter 与 collection_define 在同一个写事务中删除
+    pub fn drop_collection(&self, collection_name: &String) -> Result<(), MgError> {
+        let write_txn = self.begin_write()?;
+        let mut collection_map_lock = self.collection_map.write()?;
+        let Some(collection) = collection_map_lock.get(collection_name).cloned() else {
+            return Err(MgError::UnknownCollection(collection_name.clone()));
+        };
+        let mut counter_map_lock = self.counter_map.write()?;
+
+        delete_collection_tables(&collection, &write_txn)?;
         {
-            let mut collection_map_lock = self.collection_map.write().unwrap();
-            collection_map_lock.insert(collection_name, collection);
-            drop(collection_map_lock);
+            let mut collection_define_table = write_txn.open_table(COLLECTION_DEFINE_TABLE)?;
+            collection_define_table.remove(collection_name)?;
+            let mut counter_table = write_txn.open_table(COUNTER_TABLE)?;
+            counter_table.remove(collection_name)?;
         }
+        write_txn.commit()?;
+
+        collection_map_lock.remove(collection_name);
+        counter_map_lock.remove(collection_name);
+        println!("删除collection: {collection_name}");
+        Ok(())
     }
-    pub fn list_all_collections(&self) -> Vec<Collection> {
+
+    /// 重命名 collection: 数据表与全部索引表改名, counter 与 collection_define 移到新名称下
+    pub fn rename_collection(&self, collection_name: &String, new_collection_name: &String) -> Result<Collection, MgError> {
+        check_collection_name(new_collection_name)?;
+        let write_txn = self.begin_write()?;
+        let mut collection_map_lock = self.collection_map.write()?;
+        let Some(mut collection) = collection_map_lock.get(collection_name).cloned() else {
+            return Err(MgError::UnknownCollection(collection_name.clone()));
+        };
+        if collection_map_lock.contains_key(new_collection_name) {
+            return Err(MgError::Constraint(format!("collection已存在: {new_collection_name}")));
+        }
+        let mut counter_map_lock = self.counter_map.write()?;
+
+        rename_collection_tables(&collection, new_collection_name, &write_txn)?;
+        let counter_option;
         {
-            let collection_map_lock = self.collection_map.read().unwrap();
-            let collections: Vec<Collection> = collection_map_lock.values().map(|c| c.clone()).collect();
-            let collections_clone = collections.clone();
-            return collections_clone;
+            let mut collection_define_table = write_txn.open_table(COLLECTION_DEFINE_TABLE)?;
+            collection_define_table.remove(collection_name)?;
+            let mut counter_table = write_txn.open_table(COUNTER_TABLE)?;
+            counter_option = counter_table.remove(collection_name)?.map(|counter| counter.value());
+            if let Some(counter) = counter_option {
+                counter_table.insert(new_collection_name.clone(), counter)?;
+            }
         }
+        collection.collection_name = new_collection_name.clone();
+        self.commit_collection_define(write_txn, &mut collection_map_lock, collection.clone())?;
+
+        collection_map_lock.remove(collection_name);
+        if let Some(counter) = counter_map_lock.remove(collection_name) {
+            counter_map_lock.insert(new_collection_name.clone(), counter);
+        }
+        println!("重命名collection: {collection_name} => {new_collection_name}");
+        Ok(collection)
     }
 
-    fn _add_index(&self, _collection_name: &String, _field_name: &String) {}
-    pub fn update_records(&self, collection_name: &String, records: Vec<Value>, update_type: UpdateType) {
-        let primary_key;
-        let indexes_f64_list: Vec<String>;
-        let indexes_string_list: Vec<String>;
-        let indexes_string_unique_list: Vec<String>;
+    /// 清空 collection 的 records 与索引, 保留定义; counter 不重置, record_id 不会重复使用
+    /// 返回删除的 record 数量
+    pub fn truncate_collection(&self, collection_name: &String) -> Result<u64, MgError> {
+        let write_txn = self.begin_write()?;
+        let collection_map_lock = self.collection_map.read()?;
+        let Some(collection) = collection_map_lock.get(collection_name) else {
+            return Err(MgError::UnknownCollection(collection_name.clone()));
+        };
+
+        let num_deleted = open_table_write::<u32, String>(collection_name, &write_txn)?.len()?;
+        delete_collection_tables(collection, &write_txn)?;
+        open_collection_tables(collection, &write_txn)?;
+        write_txn.commit()?;
+        println!("清空collection: {collection_name}, 删除 {num_deleted} 条");
+        Ok(num_deleted)
+    }
 
+    /// 在同一个写事务中保存 collection 定义并提交, 提交后再更新内存中的定义
+    fn commit_collection_define(&self, write_txn: WriteTransaction, collection_map: &mut BTreeMap<String, Collection>, collection: Collection) -> Result<(), MgError> {
         {
-            let collection_map_lock = self.collection_map.read().unwrap();
-            let collection = collection_map_lock.get(collection_name).unwrap();
-            primary_key = collection.primary_key.clone();
-            indexes_f64_list = collection.indexes_f64_list.clone();
-            indexes_string_list = collection.indexes_string_list.clone();
-            indexes_string_unique_list = collection.indexes_string_unique_list.clone();
-            //获取主键
-            drop(collection_map_lock);
+            let mut collection_define_table = write_txn.open_table(COLLECTION_DEFINE_TABLE)?;
+            let collections_str = serde_json::to_string(&collection)?;
+            collection_define_table.insert(collection.collection_name.clone(), collections_str)?;
         }
+        write_txn.commit()?;
+        collection_map.insert(collection.collection_name.clone(), collection);
+        Ok(())
+    }
+
+    pub fn update_records(&self, collection_name: &String, records: Vec<Value>, update_type: UpdateType) -> Result<UpdateResult, MgError> {
+        let (_written_ids, update_result) = self.write_records(collection_name, records, &update_type, false)?;
+        Ok(update_result)
+    }
+
+    /// 写入records, 返回实际写入的record_id 及写入结果
+    /// strict 为 true 时任一 record 未写入 (已存在/不存在/缺少主键/唯一索引冲突) 即返回 Constraint 错误, 写事务不提交
+    pub(crate) fn write_records(&self, collection_name: &String, records: Vec<Value>, update_type: &UpdateType, strict: bool) -> Result<(Vec<u32>, UpdateResult), MgError> {
         let records_len = records.len() as u32;
         let mut count_number = 100;
 
         match update_type {
             UpdateType::UpdateOnly => {}
             _ => {
-                let mut counter_map_lock = self.counter_map.write().unwrap();
+                let mut counter_map_lock = self.counter_map.write()?;
 
                 {
-                    let collection = counter_map_lock.get(collection_name).unwrap();
+                    let collection = counter_map_lock.get(collection_name).ok_or_else(|| MgError::UnknownCollection(collection_name.clone()))?;
                     count_number = *collection;
                     let target_count = count_number + records_len;
                     counter_map_lock.insert(collection_name.clone(), target_count);
@@ -272,183 +524,96 @@ impl MgDb {
         }
 
 
-        let mut created_number: u32 = 0;
+        let mut update_result = UpdateResult::default();
+        let mut written_ids = Vec::new();
 
-        let write_txn = self.db.begin_write().unwrap();
+        let write_txn = self.begin_write()?;
+        //开启写事务后再读取 collection 定义, 避免漏掉并发新增的索引
+        let collection = self.get_collection(collection_name)?;
+        let primary_key = collection.primary_key.clone();
         {
-            let mut collection_table = open_table_write::<u32, String>(&collection_name, &write_txn);
-
-            let collection_name_primary = format!("{}@primary", collection_name);
-            let mut primary_key_table = open_table_write::<&str, u32>(&collection_name_primary, &write_txn);
-
-            let collection_name_f64 = format!("{collection_name}#f64#");
-            let mut f64_table = open_table_write::<(u32, u32), f64>(&collection_name_f64, &write_txn);
+            let mut writer = CollectionWriter::new(&collection, &write_txn)?;
 
             for record in records {
-                let mut need_write = false;
-                let mut need_skip = false;
-                let mut is_new = false;
-                let mut record_id = count_number + 1;
-                let record_key_option = record[primary_key.as_str()].as_str();
-                let mut old_record_option = None;
-                if let Some(record_key) = record_key_option {
-                    {
-                        let old_record_id_option = primary_key_table.get(&record_key).unwrap();
-                        match old_record_id_option {
-                            None => {
-                                is_new = true;
-                            }
-                            Some(old_record_id) => {
-                                record_id = old_record_id.value();
-                                let old_record_str_option = collection_table.get(record_id).unwrap();
-                                if let Some(old_record_str_lock) = old_record_str_option {
-                                    let old_record_str = old_record_str_lock.value();
-                                    let old_record: Value = serde_json::from_str(old_record_str.as_str()).unwrap();
-                                    old_record_option = Some(old_record);
-                                }
-                            }
-                        }
+                let Some(record_key) = primary_key_of(&record, &primary_key).map(|key| key.to_string()) else {
+                    if strict {
+                        return Err(MgError::Constraint(format!("缺少主键: {primary_key}")));
                     }
-
-                    match update_type {
-                        UpdateType::CreateOnlY => {
-                            if is_new {
-                                count_number += 1;
-                                created_number += 1;
-                                need_write = true;
-                            }
-                        }
-                        UpdateType::UpdateOnly => {
-                            if !is_new {
-                                need_write = true;
-                            }
-                        }
-                        UpdateType::Merge => {
-                            if is_new {
-                                count_number += 1;
-                                created_number += 1;
-                            }
-                            need_write = true;
-                        }
+                    update_result.rejected.push(RejectedRecord {
+                        primary_key: None,
+                        reason: format!("缺少主键: {primary_key}"),
+                    });
+                    continue;
+                };
+                let old_record_id_option = writer.get_record_id(&record_key)?;
+                let is_new = old_record_id_option.is_none();
+                let record_id = old_record_id_option.unwrap_or(count_number + 1);
+
+                let need_write = match update_type {
+                    UpdateType::CreateOnlY => is_new,
+                    UpdateType::UpdateOnly => !is_new,
+                    UpdateType::Merge => true,
+                };
+                if !need_write {
+                    if strict {
+                        let reason = if is_new { "record 不存在" } else { "record 已存在" };
+                        return Err(MgError::Constraint(format!("{reason}: {record_key}")));
                     }
-                    if need_write {
-                        for index_string in &indexes_string_unique_list {
-                            let str_option = &record[index_string].as_str();
-                            if let Some(str) = str_option {
-                                let collection_name_index = format!("{}@stringU@{}", collection_name, index_string);
-                                let mut index_table = open_table_write::<&str, u32>(&collection_name_index, &write_txn);
-                                let is_conflict;
-                                {
-                                    let conflict_record_id_option = index_table.get(*str).unwrap();
-                                    is_conflict = conflict_record_id_option.is_some();
-                                }
-
-                                if is_conflict && is_new {
-                                    need_skip = true;
-                                    println!("唯一索引冲突: {index_string}@{str}, record插入失败");
-                                } else {
-                                    let mut need_update_index = true;
-                                    if is_new {
-                                        println!("新建索引 for: {} with {}", collection_name_index, str);
-                                    } else if let Some(ref old_record) = old_record_option {
-                                        let old_str_option = &old_record[index_string].as_str();
-                                        if let Some(old_str) = old_str_option {
-                                            if *str == *old_str {
-                                                println!("索引相同，无需更新, with {str}");
-                                                need_update_index = false;
-                                            } else {
-                                                // println!("更新索引 for: {} with {}", collection_name_index, str);
-                                                index_table.remove(*old_str).unwrap();
-                                            }
-                                        }
-                                    }
-                                    if need_update_index {
-                                        println!("插入索引 for: {} with {}", collection_name_index, str);
-                                        index_table.insert(*str, record_id).unwrap();
-                                    }
-                                }
-                                //A,B  --> B,A  去重和old_str冲突
-                            }
-                        }
-
-                        if need_skip {
-                            continue;
-                        }
-                        for index_f64 in &indexes_f64_list {
-                            let number_option = &record[index_f64].as_f64();
-                            if let Some(number) = number_option {
-                                let collection_name_index = format!("{}@f64@{}", collection_name, index_f64);
-                                let mut index_table = open_table_write::<(MyF64, u32), ()>(&collection_name_index, &write_txn);
-                                let mut need_update_index = true;
-                                let field_id = hash_to_u32(index_f64);
-
-                                if !is_new {
-                                    let old_number_option = f64_table.get((record_id, field_id)).unwrap();
-
-                                    if let Some(old_number_lock) = old_number_option {
-                                        let old_number = old_number_lock.value();
-                                        if old_number == *number {
-                                            println!("索引相同，无需更新, with {number}");
-                                            need_update_index = false;
-                                        } else {
-                                            // println!("更新索引 for: {} with {}", collection_name_index, number);
-                                            index_table.remove((MyF64(old_number), record_id)).unwrap();
-                                        }
-                                    }
-                                }
-
-                                if need_update_index {
-                                    println!("插入索引 for: {} with {}", collection_name_index, number);
-                                    index_table.insert((MyF64(*number), record_id), ()).unwrap();
-                                    f64_table.insert((record_id, field_id), *number).unwrap();
-                                }
-                            }
-                        }
+                    update_result.num_skipped += 1;
+                    continue;
+                }
 
-                        for index_string in &indexes_string_list {
-                            let str_option = &record[index_string].as_str();
-                            if let Some(str) = str_option {
-                                let collection_name_index = format!("{}@string@{}", collection_name, index_string);
-                                let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
-                                let mut index_table = write_txn.open_multimap_table(index_table_define).unwrap();
-                                let mut need_update_index = true;
-                                if !is_new {
-                                    if let Some(ref old_record) = old_record_option {
-                                        let old_str_option = &old_record[index_string].as_str();
-                                        if let Some(old_str) = old_str_option {
-                                            if *old_str == *str {
-                                                println!("索引相同，无需更新, with {str}");
-                                                need_update_index = false;
-                                            } else {
-                                                index_table.remove(old_str, record_id).unwrap();
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if need_update_index {
-                                    index_table.insert(*str, record_id).unwrap();
-                                    println!("插入索引 for: {} with {}", collection_name_index, str);
-                                }
-                            }
+                //在写事务内读取旧 record 再执行合并与更新操作, 并发更新不会互相覆盖
+                let old_record_option = if is_new { None } else { writer.get_record(record_id)? };
+                let record = match build_record(old_record_option.as_ref(), record, update_type) {
+                    Ok(record) => record,
+                    Err(reason) => {
+                        if strict {
+                            return Err(MgError::Constraint(format!("{record_key}: {reason}")));
                         }
-
-                        if !need_skip {
-                            let record_str = serde_json::to_string(&record).unwrap_or("{}".to_string());
-                            collection_table.insert(record_id, record_str).unwrap();
-                            if is_new {
-                                primary_key_table.insert(record_key, record_id).unwrap();
-                            }
+                        update_result.rejected.push(RejectedRecord {
+                            primary_key: Some(record_key),
+                            reason,
+                        });
+                        continue;
+                    }
+                };
+                if old_record_option.as_ref() == Some(&record) {
+                    update_result.num_updated += 1;
+                    written_ids.push(record_id);
+                    continue;
+                }
+                match writer.put_record(record_id, old_record_option.as_ref(), &record) {
+                    Ok(()) => {
+                        if is_new {
+                            count_number += 1;
+                            update_result.num_created += 1;
+                        } else {
+                            update_result.num_updated += 1;
                         }
+                        written_ids.push(record_id);
+                    }
+                    Err(MgError::Constraint(conflict)) if strict => {
+                        return Err(MgError::Constraint(format!("唯一索引冲突: {conflict}")));
                     }
+                    Err(MgError::Constraint(conflict)) => {
+                        println!("唯一索引冲突: {conflict}, record写入失败");
+                        update_result.num_conflicts += 1;
+                        update_result.rejected.push(RejectedRecord {
+                            primary_key: Some(record_key),
+                            reason: format!("唯一索引冲突: {conflict}"),
+                        });
+                    }
+                    Err(error) => { return Err(error); }
                 }
             }
+            drop(writer);
 
-            if created_number > 0 {
-                let mut counter_table = write_txn.open_table(COUNTER_TABLE).unwrap();
+            if update_result.num_created > 0 {
+                let mut counter_table = write_txn.open_table(COUNTER_TABLE)?;
                 let mut need_write = false;
                 {
-                    let old_counter_option = counter_table.get(collection_name).unwrap();
+                    let old_counter_option = counter_table.get(collection_name)?;
                     match old_counter_option {
                         None => {
                             need_write = true;
@@ -462,33 +627,80 @@ impl MgDb {
                 }
 
                 if need_write {
-                    counter_table.insert(collection_name.clone(), count_number).unwrap();
+                    counter_table.insert(collection_name.clone(), count_number)?;
                 }
             }
         }
-        write_txn.commit().unwrap();
+        write_txn.commit()?;
+        Ok((written_ids, update_result))
+    }
+
+    /// 在调用方开启的写事务中修改指定 records 并提交, 返回修改成功的record_id
+    pub(crate) fn update_records_by_id<F>(&self, write_txn: WriteTransaction, collection_name: &String, record_ids: &[u32], mut update: F) -> Result<Vec<u32>, MgError>
+    where
+        F: FnMut(&mut Value),
+    {
+        let mut updated_ids = Vec::new();
+
+        let collection = self.get_collection(collection_name)?;
+        {
+            let mut writer = CollectionWriter::new(&collection, &write_txn)?;
+            for &record_id in record_ids {
+                if let Some(old_record) = writer.get_record(record_id)? {
+                    let mut record = old_record.clone();
+                    update(&mut record);
+                    if record == old_record {
+                        updated_ids.push(record_id);
+                        continue;
+                    }
+                    //唯一索引冲突时返回错误, 写事务不提交, 本次更新的 records 都不会修改
+                    writer.put_record(record_id, Some(&old_record), &record)?;
+                    updated_ids.push(record_id);
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(updated_ids)
     }
 
+    /// 在调用方开启的写事务中删除指定 records 及其全部索引并提交, 返回被删除的 records
+    pub(crate) fn delete_records_by_id(&self, write_txn: WriteTransaction, collection_name: &String, record_ids: &[u32]) -> Result<Vec<Value>, MgError> {
+        let mut deleted_records = Vec::new();
 
-    fn _list_all_records(&self, collection_name: &String) -> Vec<Value> {
+        let collection = self.get_collection(collection_name)?;
+        {
+            let mut writer = CollectionWriter::new(&collection, &write_txn)?;
+            for &record_id in record_ids {
+                if let Some(record) = writer.remove_record(record_id)? {
+                    deleted_records.push(record);
+                }
+            }
+        }
+        write_txn.commit()?;
+        println!("删除records: {} @ {}", deleted_records.len(), collection_name);
+        Ok(deleted_records)
+    }
+
+
+    fn _list_all_records(&self, collection_name: &String) -> Result<Vec<Value>, MgError> {
         let mut records = Vec::new();
         {
-            let read_txn = self.db.begin_read().unwrap();
-            let collection_table = open_table_read::<u32, String>(&collection_name, &read_txn);
-            let mut iter = collection_table.iter().unwrap();
+            let read_txn = self.db.begin_read()?;
+            let collection_table = open_table_read::<u32, String>(&collection_name, &read_txn)?;
+            let mut iter = collection_table.iter()?;
             while let Some(kv) = iter.next() {
                 if let Ok((_key, value)) = kv {
                     // println!("读取collection value: {:?} @ {:?}", key.value(), value.value());
                     let record_str = value.value();
-                    let record: Value = serde_json::from_str(record_str.as_str()).unwrap();
+                    let record: Value = serde_json::from_str(record_str.as_str())?;
                     records.push(record);
                 }
             }
         }
-        return records;
+        return Ok(records);
     }
 
-    fn _show_collection_inner(&self, collection_name: &String) -> (HashMap<String, u32>, HashMap<String, u32>, Vec<String>) {
+    fn _show_collection_inner(&self, collection_name: &String) -> Result<_CollectionInner, MgError> {
         let mut primary_key_map = HashMap::new();
         let mut counter_map = HashMap::new();
         let mut collection_define_map = HashMap::new();
@@ -496,12 +708,12 @@ impl MgDb {
         let mut index_list: Vec<String> = Vec::new();
 
         {
-            let read_txn = self.db.begin_read().unwrap();
+            let read_txn = self.db.begin_read()?;
 
             {
                 let collection_name_primary = format!("{}@primary", collection_name);
-                let primary_key_table = open_table_read::<&str, u32>(&collection_name_primary, &read_txn);
-                let mut primary_key_table_iter = primary_key_table.iter().unwrap();
+                let primary_key_table = open_table_read::<&str, u32>(&collection_name_primary, &read_txn)?;
+                let mut primary_key_table_iter = primary_key_table.iter()?;
                 while let Some(kv_primary_key) = primary_key_table_iter.next() {
                     if let Ok((key_lock, value_lock)) = kv_primary_key {
                         let key = key_lock.value();
@@ -513,8 +725,8 @@ impl MgDb {
 
             {
                 let collection_name_f64 = format!("{collection_name}#f64#");
-                let f64_table = open_table_read::<(u32, u32), f64>(&collection_name_f64, &read_txn);
-                let mut table_iter = f64_table.iter().unwrap();
+                let f64_table = open_table_read::<(u32, u32), f64>(&collection_name_f64, &read_txn)?;
+                let mut table_iter = f64_table.iter()?;
                 while let Some(kv) = table_iter.next() {
                     if let Ok((key_lock, value_lock)) = kv {
                         let key = key_lock.value();
@@ -526,8 +738,8 @@ impl MgDb {
             }
 
             {
-                let counter_table = read_txn.open_table(COUNTER_TABLE).unwrap();
-                let mut counter_table_iter = counter_table.iter().unwrap();
+                let counter_table = read_txn.open_table(COUNTER_TABLE)?;
+                let mut counter_table_iter = counter_table.iter()?;
                 '_label1: while let Some(kv_counter) = counter_table_iter.next() {
                     if let Ok((key_lock, value_lock)) = kv_counter {
                         let counter_name = key_lock.value();
@@ -538,25 +750,25 @@ impl MgDb {
             }
 
             {
-                let collection_define_table = read_txn.open_table(COLLECTION_DEFINE_TABLE).unwrap();
-                let mut collection_define_table_iter = collection_define_table.iter().unwrap();
+                let collection_define_table = read_txn.open_table(COLLECTION_DEFINE_TABLE)?;
+                let mut collection_define_table_iter = collection_define_table.iter()?;
                 while let Some(kv) = collection_define_table_iter.next() {
                     if let Ok((key_lock, value_lock)) = kv {
                         let key = key_lock.value();
                         let value = value_lock.value();
-                        let collection: Collection = serde_json::from_str(value.as_str()).unwrap();
+                        let collection: Collection = serde_json::from_str(value.as_str())?;
                         collection_define_map.insert(key, collection);
                     }
                 }
             }
 
             {
-                let collection = collection_define_map.get(collection_name).unwrap();
+                let collection = collection_define_map.get(collection_name).ok_or_else(|| MgError::UnknownCollection(collection_name.clone()))?;
 
                 for index_string in &collection.indexes_string_unique_list {
                     let collection_name_index = format!("{}@stringU@{}", collection_name, index_string);
-                    let index_table = open_table_read::<&str, u32>(&collection_name_index, &read_txn);
-                    let mut index_table_iter = index_table.iter().unwrap();
+                    let index_table = open_table_read::<&str, u32>(&collection_name_index, &read_txn)?;
+                    let mut index_table_iter = index_table.iter()?;
                     while let Some(kv) = index_table_iter.next() {
                         if let Ok((key_lock, value_lock)) = kv {
                             let key = key_lock.value();
@@ -569,8 +781,8 @@ impl MgDb {
 
                 for index_f64 in &collection.indexes_f64_list {
                     let collection_name_index = format!("{}@f64@{}", collection_name, index_f64);
-                    let index_table = open_table_read::<(MyF64, u32), ()>(&collection_name_index, &read_txn);
-                    let mut index_table_iter = index_table.iter().unwrap();
+                    let index_table = open_table_read::<(MyF64, u32), ()>(&collection_name_index, &read_txn)?;
+                    let mut index_table_iter = index_table.iter()?;
                     while let Some(kv_counter) = index_table_iter.next() {
                         if let Ok((key_lock, value_lock)) = kv_counter {
                             let key = key_lock.value();
@@ -585,13 +797,13 @@ impl MgDb {
                 for index_string in &collection.indexes_string_list {
                     let collection_name_index = format!("{}@string@{}", collection_name, index_string);
                     let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
-                    let index_table = read_txn.open_multimap_table(index_table_define).unwrap();
-                    let mut index_table_iter = index_table.iter().unwrap();
+                    let index_table = read_txn.open_multimap_table(index_table_define)?;
+                    let mut index_table_iter = index_table.iter()?;
                     while let Some(kv) = index_table_iter.next() {
                         if let Ok((key_lock, value_lock)) = kv {
                             let key = key_lock.value();
                             for id_lock in value_lock {
-                                let record_id = id_lock.unwrap().value();
+                                let record_id = id_lock?.value();
                                 let index_str = format!("${}->{}", key, record_id);
                                 index_list.push(index_str);
                             }
@@ -600,25 +812,377 @@ impl MgDb {
                 }
             }
         }
-        return (primary_key_map, counter_map,
+        return Ok((primary_key_map, counter_map,
                 // collection_define_map,
-                index_list);
+                index_list));
+    }
+
+    fn _read_db(&self) -> Result<ReadTransaction, MgError> {
+        let read_txn = self.db.begin_read()?;
+        Ok(read_txn)
+    }
+}
+
+/// 主键表, 计数器与全部索引项, 调试时查看 collection 内部数据
+type _CollectionInner = (HashMap<String, u32>, HashMap<String, u32>, Vec<String>);
+
+/// 单个写事务内对一个 collection 的读写, 负责维护主键表与全部索引表
+pub(crate) struct CollectionWriter<'a> {
+    collection: &'a Collection,
+    write_txn: &'a WriteTransaction,
+    collection_table: Table<'a, u32, String>,
+    primary_key_table: Table<'a, &'static str, u32>,
+    f64_table: Table<'a, (u32, u32), f64>,
+}
+
+impl<'a> CollectionWriter<'a> {
+    pub(crate) fn new(collection: &'a Collection, write_txn: &'a WriteTransaction) -> Result<Self, MgError> {
+        let collection_name = &collection.collection_name;
+        let collection_table = write_txn.open_table(TableDefinition::<u32, String>::new(collection_name.as_str()))?;
+        let collection_name_primary = format!("{}@primary", collection_name);
+        let primary_key_table = write_txn.open_table(TableDefinition::<&str, u32>::new(collection_name_primary.as_str()))?;
+        let collection_name_f64 = format!("{collection_name}#f64#");
+        let f64_table = write_txn.open_table(TableDefinition::<(u32, u32), f64>::new(collection_name_f64.as_str()))?;
+        Ok(CollectionWriter {
+            collection,
+            write_txn,
+            collection_table,
+            primary_key_table,
+            f64_table,
+        })
+    }
+
+    pub(crate) fn get_record_id(&self, record_key: &str) -> Result<Option<u32>, MgError> {
+        Ok(self.primary_key_table.get(record_key)?.map(|id| id.value()))
+    }
+
+    pub(crate) fn get_record(&self, record_id: u32) -> Result<Option<Value>, MgError> {
+        let record_str_option = self.collection_table.get(record_id)?;
+        record_str_option.map(|record_str| serde_json::from_str(record_str.value().as_str())).transpose().map_err(MgError::from)
+    }
+
+    /// 写入 record 并维护索引, old_record 为 None 表示新建
+    /// 主键或唯一索引冲突时返回 `MgError::Constraint(字段@值)`, 此时不做任何修改
+    pub(crate) fn put_record(&mut self, record_id: u32, old_record: Option<&Value>, record: &Value) -> Result<(), MgError> {
+        let collection_name = &self.collection.collection_name;
+        let primary_key = &self.collection.primary_key;
+
+        //先检查全部冲突, 避免部分索引已写入
+        let record_key_option = primary_key_of(record, primary_key);
+        if let Some(record_key) = record_key_option {
+            if let Some(conflict_id) = self.get_record_id(record_key)? {
+                if conflict_id != record_id {
+                    return Err(MgError::Constraint(format!("{primary_key}@{record_key}")));
+                }
+            }
+        }
+        for index_string in &self.collection.indexes_string_unique_list {
+            let strs = index_strings(Some(record), index_string);
+            if strs.is_empty() {
+                continue;
+            }
+            let collection_name_index = format!("{}@stringU@{}", collection_name, index_string);
+            let index_table = open_table_write::<&str, u32>(&collection_name_index, self.write_txn)?;
+            for str in strs {
+                let conflict_record_id_option = index_table.get(str)?;
+                if let Some(conflict_record_id) = conflict_record_id_option {
+                    if conflict_record_id.value() != record_id {
+                        return Err(MgError::Constraint(format!("{index_string}@{str}")));
+                    }
+                }
+            }
+        }
+
+        self.update_indexes(record_id, old_record, Some(record))?;
+
+        let record_str = serde_json::to_string(record)?;
+        self.collection_table.insert(record_id, record_str)?;
+        Ok(())
+    }
+
+    /// 按新旧 record 的差异维护主键表与索引表, record 为 None 表示删除
+    fn update_indexes(&mut self, record_id: u32, old_record: Option<&Value>, record: Option<&Value>) -> Result<(), MgError> {
+        let collection_name = &self.collection.collection_name;
+        let primary_key = &self.collection.primary_key;
+
+        let old_record_key_option = old_record.and_then(|r| primary_key_of(r, primary_key));
+        let record_key_option = record.and_then(|r| primary_key_of(r, primary_key));
+        if old_record_key_option != record_key_option {
+            if let Some(old_record_key) = old_record_key_option {
+                self.primary_key_table.remove(old_record_key)?;
+            }
+            if let Some(record_key) = record_key_option {
+                self.primary_key_table.insert(record_key, record_id)?;
+            }
+        }
+
+        for index_string in &self.collection.indexes_string_unique_list {
+            let old_strs = index_strings(old_record, index_string);
+            let strs = index_strings(record, index_string);
+            if old_strs == strs {
+                continue;
+            }
+            let collection_name_index = format!("{}@stringU@{}", collection_name, index_string);
+            let mut index_table = open_table_write::<&str, u32>(&collection_name_index, self.write_txn)?;
+            for old_str in old_strs.difference(&strs) {
+                index_table.remove(*old_str)?;
+            }
+            for str in strs.difference(&old_strs) {
+                println!("插入索引 for: {} with {}", collection_name_index, str);
+                index_table.insert(*str, record_id)?;
+            }
+        }
+
+        for index_f64 in &self.collection.indexes_f64_list {
+            let field_id = hash_to_u32(index_f64);
+            let old_numbers = index_numbers(old_record, index_f64);
+            let numbers = index_numbers(record, index_f64);
+            if old_numbers == numbers {
+                continue;
+            }
+            let collection_name_index = format!("{}@f64@{}", collection_name, index_f64);
+            let mut index_table = open_table_write::<(MyF64, u32), ()>(&collection_name_index, self.write_txn)?;
+            for old_number in old_numbers.iter().filter(|n| !numbers.contains(n)) {
+                index_table.remove((MyF64(*old_number), record_id))?;
+            }
+            for number in numbers.iter().filter(|n| !old_numbers.contains(n)) {
+                println!("插入索引 for: {} with {}", collection_name_index, number);
+                index_table.insert((MyF64(*number), record_id), ())?;
+            }
+            //数组字段取最小值, 用于已筛选结果的排序
+            match numbers.first() {
+                Some(number) => { self.f64_table.insert((record_id, field_id), *number)?; }
+                None => { self.f64_table.remove((record_id, field_id))?; }
+            }
+        }
+
+        for index_string in &self.collection.indexes_string_list {
+            let old_strs = index_strings(old_record, index_string);
+            let strs = index_strings(record, index_string);
+            if old_strs == strs {
+                continue;
+            }
+            let collection_name_index = format!("{}@string@{}", collection_name, index_string);
+            let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
+            let mut index_table = self.write_txn.open_multimap_table(index_table_define)?;
+            for old_str in old_strs.difference(&strs) {
+                index_table.remove(*old_str, record_id)?;
+            }
+            for str in strs.difference(&old_strs) {
+                println!("插入索引 for: {} with {}", collection_name_index, str);
+                index_table.insert(*str, record_id)?;
+            }
+        }
+
+        for index_fields in &self.collection.indexes_compound_list {
+            let old_keys = compound_keys(old_record, index_fields);
+            let keys = compound_keys(record, index_fields);
+            if old_keys == keys {
+                continue;
+            }
+            let collection_name_index = compound_table_name(collection_name, index_fields);
+            let mut index_table = open_table_write::<(&[u8], u32), ()>(&collection_name_index, self.write_txn)?;
+            for old_key in old_keys.difference(&keys) {
+                index_table.remove((old_key.as_slice(), record_id))?;
+            }
+            for key in keys.difference(&old_keys) {
+                println!("插入索引 for: {}", collection_name_index);
+                index_table.insert((key.as_slice(), record_id), ())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 删除 record 及其全部索引, 返回被删除的 record
+    pub(crate) fn remove_record(&mut self, record_id: u32) -> Result<Option<Value>, MgError> {
+        let Some(old_record) = self.get_record(record_id)? else {
+            return Ok(None);
+        };
+        self.update_indexes(record_id, Some(&old_record), None)?;
+
+        //清理残留的 #f64# 动态值
+        let stale_keys = self.f64_table.range((record_id, 0)..=(record_id, u32::MAX))?
+            .map(|kv| kv.map(|(key, _value)| key.value())).collect::<Result<Vec<(u32, u32)>, _>>()?;
+        for key in stale_keys {
+            self.f64_table.remove(key)?;
+        }
+
+        self.collection_table.remove(record_id)?;
+        Ok(Some(old_record))
     }
+}
 
-    fn _read_db(&self) -> ReadTransaction {
-        let read_txn = self.db.begin_read().unwrap();
-        read_txn
+fn primary_key_of<'v>(record: &'v Value, primary_key: &str) -> Option<&'v str> {
+    get_value_by_path(record, primary_key).and_then(|value| value.as_str())
+}
+
+/// 索引路径上的字符串值, 支持 `author.name` 和 `tags[]`, 数组中每个元素各占一个索引项
+fn index_strings<'v>(record: Option<&'v Value>, path: &str) -> BTreeSet<&'v str> {
+    match record {
+        Some(record) => get_values_by_path(record, path).into_iter().filter_map(|value| value.as_str()).collect(),
+        None => BTreeSet::new(),
     }
 }
 
+/// 新建 collection 的数据表, @primary, #f64# 与全部索引表, 已存在的表保持不变
+fn open_collection_tables(collection: &Collection, write_txn: &WriteTransaction) -> Result<(), MgError> {
+    let collection_name = &collection.collection_name;
+    let collection_table_define: TableDefinition<u32, String> = TableDefinition::new(collection_name.as_str());
+    let _collection_table = write_txn.open_table(collection_table_define)?;
+
+    println!("新建_collection_table");
+
+    let collection_name_primary = format!("{}@primary", collection_name);
+    let _primary_key_table = open_table_write::<&str, u32>(&collection_name_primary, write_txn)?;
+
+    {
+        println!("新建_动态值_table");
+        let collection_name_f64 = format!("{collection_name}#f64#");
+        let _f64_table = open_table_write::<(u32, u32), f64>(&collection_name_f64, write_txn)?;
+        println!("新建_动态值 for: {}", collection_name_f64);
+    }
+
+    println!("新建_indexes_tables");
+    for index_f64 in &collection.indexes_f64_list {
+        let collection_name_index = format!("{}@f64@{}", collection_name, index_f64);
+        let _index_table = open_table_write::<(MyF64, u32), ()>(&collection_name_index, write_txn)?;
+        println!("新建_index_f64 for: {}", collection_name_index);
+    }
+
+    for index_string in &collection.indexes_string_list {
+        let collection_name_index = format!("{}@string@{}", collection_name, index_string);
+        let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
+        let _index_table = write_txn.open_multimap_table(index_table_define)?;
+        println!("新建index_string for: {}", collection_name_index);
+    }
+
+    for index_string in &collection.indexes_string_unique_list {
+        let collection_name_index = format!("{}@stringU@{}", collection_name, index_string);
+        let _index_table = open_table_write::<&str, u32>(&collection_name_index, write_txn)?;
+        println!("新建index_string_unique for: {}", collection_name_index);
+    }
+
+    for index_fields in &collection.indexes_compound_list {
+        let collection_name_index = compound_table_name(collection_name, index_fields);
+        let _index_table = open_table_write::<(&[u8], u32), ()>(&collection_name_index, write_txn)?;
+        println!("新建index_compound for: {}", collection_name_index);
+    }
+    Ok(())
+}
+
+/// 删除 collection 的数据表, @primary, #f64# 与全部索引表
+fn delete_collection_tables(collection: &Collection, write_txn: &WriteTransaction) -> Result<(), MgError> {
+    let collection_name = collection.collection_name.as_str();
+    write_txn.delete_table(TableDefinition::<u32, String>::new(collection_name))?;
+    write_txn.delete_table(TableDefinition::<&str, u32>::new(&format!("{collection_name}@primary")))?;
+    write_txn.delete_table(TableDefinition::<(u32, u32), f64>::new(&format!("{collection_name}#f64#")))?;
+    for index_define in collection_index_defines(collection) {
+        let collection_name_index = index_table_name(collection_name, &index_define);
+        match index_define {
+            IndexDefine::F64(_) => { write_txn.delete_table(TableDefinition::<(MyF64, u32), ()>::new(&collection_name_index))?; }
+            IndexDefine::String(_) => { write_txn.delete_multimap_table(MultimapTableDefinition::<&str, u32>::new(&collection_name_index))?; }
+            IndexDefine::StringUnique(_) => { write_txn.delete_table(TableDefinition::<&str, u32>::new(&collection_name_index))?; }
+            IndexDefine::Compound(_) => { write_txn.delete_table(TableDefinition::<(&[u8], u32), ()>::new(&collection_name_index))?; }
+        }
+    }
+    Ok(())
+}
+
+/// 将 collection 的数据表, @primary, #f64# 与全部索引表改到新名称下
+fn rename_collection_tables(collection: &Collection, new_collection_name: &str, write_txn: &WriteTransaction) -> Result<(), MgError> {
+    let collection_name = collection.collection_name.as_str();
+    write_txn.rename_table(TableDefinition::<u32, String>::new(collection_name), TableDefinition::<u32, String>::new(new_collection_name))?;
+    let (old_name, new_name) = (format!("{collection_name}@primary"), format!("{new_collection_name}@primary"));
+    write_txn.rename_table(TableDefinition::<&str, u32>::new(&old_name), TableDefinition::<&str, u32>::new(&new_name))?;
+    let (old_name, new_name) = (format!("{collection_name}#f64#"), format!("{new_collection_name}#f64#"));
+    write_txn.rename_table(TableDefinition::<(u32, u32), f64>::new(&old_name), TableDefinition::<(u32, u32), f64>::new(&new_name))?;
+    for index_define in collection_index_defines(collection) {
+        let (old_name, new_name) = (index_table_name(collection_name, &index_define), index_table_name(new_collection_name, &index_define));
+        match index_define {
+            IndexDefine::F64(_) => { write_txn.rename_table(TableDefinition::<(MyF64, u32), ()>::new(&old_name), TableDefinition::<(MyF64, u32), ()>::new(&new_name))?; }
+            IndexDefine::String(_) => { write_txn.rename_multimap_table(MultimapTableDefinition::<&str, u32>::new(&old_name), MultimapTableDefinition::<&str, u32>::new(&new_name))?; }
+            IndexDefine::StringUnique(_) => { write_txn.rename_table(TableDefinition::<&str, u32>::new(&old_name), TableDefinition::<&str, u32>::new(&new_name))?; }
+            IndexDefine::Compound(_) => { write_txn.rename_table(TableDefinition::<(&[u8], u32), ()>::new(&old_name), TableDefinition::<(&[u8], u32), ()>::new(&new_name))?; }
+        }
+    }
+    Ok(())
+}
+
+fn collection_index_defines(collection: &Collection) -> Vec<IndexDefine> {
+    let mut index_defines = vec![];
+    index_defines.extend(collection.indexes_f64_list.iter().cloned().map(IndexDefine::F64));
+    index_defines.extend(collection.indexes_string_list.iter().cloned().map(IndexDefine::String));
+    index_defines.extend(collection.indexes_string_unique_list.iter().cloned().map(IndexDefine::StringUnique));
+    index_defines.extend(collection.indexes_compound_list.iter().cloned().map(IndexDefine::Compound));
+    index_defines
+}
+
+/// collection 名称会用作表名, 不能为空, 不能含索引表名中的 `@` `#`, 也不能与内部表重名
+fn check_collection_name(collection_name: &str) -> Result<(), MgError> {
+    let is_reserved = collection_name == COLLECTION_DEFINE_TABLE.name() || collection_name == COUNTER_TABLE.name();
+    if collection_name.is_empty() || collection_name.contains(['@', '#']) || is_reserved {
+        return Err(MgError::Parse(format!("collection名称不可用: {collection_name}")));
+    }
+    Ok(())
+}
+
+fn index_table_name(collection_name: &str, index_define: &IndexDefine) -> String {
+    match index_define {
+        IndexDefine::F64(field) => format!("{}@f64@{}", collection_name, field),
+        IndexDefine::String(field) => format!("{}@string@{}", collection_name, field),
+        IndexDefine::StringUnique(field) => format!("{}@stringU@{}", collection_name, field),
+        IndexDefine::Compound(fields) => compound_table_name(collection_name, fields),
+    }
+}
+
+fn remove_from_list<T: PartialEq>(list: &mut Vec<T>, item: &T) -> bool {
+    let len = list.len();
+    list.retain(|existing| existing != item);
+    list.len() != len
+}
+
+pub(crate) fn compound_table_name(collection_name: &str, index_fields: &[String]) -> String {
+    format!("{}@compound@{}", collection_name, index_fields.join(","))
+}
+
+/// 复合索引的全部 key, 数组路径的每个元素组合各占一个索引项, 字段缺失时按 Null 编码
+fn compound_keys(record: Option<&Value>, index_fields: &[String]) -> BTreeSet<Vec<u8>> {
+    let Some(record) = record else {
+        return BTreeSet::new();
+    };
+    let mut keys = vec![Vec::new()];
+    for field in index_fields {
+        let values = get_values_by_path(record, field);
+        let values = if values.is_empty() { vec![&Value::Null] } else { values };
+        keys = keys.iter().flat_map(|key| values.iter().map(move |value| {
+            let mut key = key.clone();
+            encode_compound_value(&mut key, value);
+            key
+        })).collect();
+    }
+    keys.into_iter().collect()
+}
+
+/// 索引路径上的数值, 升序且去重
+fn index_numbers(record: Option<&Value>, path: &str) -> Vec<f64> {
+    let mut numbers: Vec<f64> = match record {
+        Some(record) => get_values_by_path(record, path).into_iter().filter_map(|value| value.as_f64()).collect(),
+        None => Vec::new(),
+    };
+    numbers.sort_by(|a, b| a.total_cmp(b));
+    numbers.dedup();
+    numbers
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use std::{env, fs};
     use std::collections::{HashSet};
     use std::ops::Deref;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::AtomicU32;
 
-    use redb::{Database, ReadableTableMetadata, TableDefinition};
+    use redb::{Database, MultimapTableHandle, ReadableTableMetadata, TableDefinition};
     use serde_json::json;
 
     use super::*;
@@ -627,6 +1191,56 @@ pub(crate) mod tests {
     pub(crate) const DB_NAME: &str = "AABBCC_10015";
     const COLLECTION_NAME: &str = "Books";
 
+    static MEMORY_WORKSPACE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    /// 新建一个空的内存 workspace, 不读写磁盘
+    pub(crate) fn memory_mgdb() -> Arc<MgDb> {
+        let workspace_id = format!("memory_{}", MEMORY_WORKSPACE_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let config = MgDbConfig { in_memory: true, ..MgDbConfig::default() };
+        get_mgdb_with_config(workspace_id, &config).unwrap()
+    }
+
+    /// 测试用的临时 data_root, 离开作用域时删除整个目录
+    pub(crate) struct TempDataRoot(pub(crate) PathBuf);
+
+    impl TempDataRoot {
+        pub(crate) fn new(name: &str) -> TempDataRoot {
+            TempDataRoot(env::temp_dir().join(format!("minimongo_{name}_{}_{}", std::process::id(), crate::common::helper::get_timestamp())))
+        }
+    }
+
+    impl Drop for TempDataRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// 新建内存 workspace, 并在 Books 中写入 10 本书
+    pub(crate) fn memory_mgdb_with_books() -> Arc<MgDb> {
+        let mg_db = memory_mgdb();
+        create_books_collection(&mg_db, COLLECTION_NAME);
+        let types = ["Math", "Physics", "History"];
+        let records = (0..10).map(|i| json!({
+            "name": format!("Book_{i}"),
+            "pa": i * 10,
+            "price": 95.5 + i as f64,
+            "book_type": types[i % 3],
+            "book_uid": format!("uid_{i}")
+        })).collect();
+        mg_db.update_records(&COLLECTION_NAME.to_string(), records, UpdateType::CreateOnlY).unwrap();
+        mg_db
+    }
+
+    pub(crate) fn create_books_collection(mg_db: &MgDb, collection_name: &str) {
+        let schema: Schema = Schema {
+            primary_key: "name".to_string(),
+            indexes_f64: vec!["price".to_string()],
+            indexes_string: vec!["book_type".to_string()],
+            indexes_string_unique: vec!["book_uid".to_string()],
+            indexes_compound: vec![],
+        };
+        mg_db.create_collection(collection_name.to_string(), schema).unwrap();
+    }
 
     #[test]
     fn do_some_test_01() -> Result<(), Box<i32>> {
@@ -659,7 +1273,7 @@ pub(crate) mod tests {
     //cargo test test_get_mgdb -- --show-output
     #[test]
     fn test_get_mgdb() -> Result<(), Box<i32>> {
-        let mg_db = get_mgdb(DB_NAME.to_string());
+        let mg_db = memory_mgdb();
         let write_txn = mg_db.deref().db.begin_write().unwrap();
 
         // let write_txn = mg_db.db.begin_write().unwrap();
@@ -678,19 +1292,49 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    //cargo test test_compound_keys_order -- --show-output
+    #[test]
+    fn test_compound_keys_order() {
+        let fields = vec!["a".to_string(), "b".to_string()];
+        //按值的顺序排列, 编码后的字节序应保持一致
+        let records = vec![
+            json!({"b": 1}),
+            json!({"a": -10.5, "b": "z"}),
+            json!({"a": -1}),
+            json!({"a": 0, "b": -3}),
+            json!({"a": 0, "b": 2}),
+            json!({"a": 0.5}),
+            json!({"a": 100, "b": "x"}),
+            json!({"a": ""}),
+            json!({"a": "a", "b": 7}),
+            json!({"a": "a\u{0}", "b": 1}),
+            json!({"a": "ab"}),
+        ];
+        let keys: Vec<Vec<u8>> = records.iter().map(|record| compound_keys(Some(record), &fields).into_iter().next().unwrap()).collect();
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+
+        let record = json!({"a": ["x", "y"], "b": [1, 2]});
+        let array_fields = vec!["a[]".to_string(), "b[]".to_string()];
+        assert_eq!(compound_keys(Some(&record), &array_fields).len(), 4);
+        assert_eq!(compound_keys(Some(&json!({"a": -0.0})), &fields), compound_keys(Some(&json!({"a": 0})), &fields));
+    }
+
     //cargo test test_create_collection -- --show-output
     #[test]
     fn test_create_collection() -> Result<(), Box<i32>> {
         println!("准备测试: test_create_collection");
-        let mg_db = get_mgdb(DB_NAME.to_string());
+        let mg_db = memory_mgdb();
         let schema: Schema = Schema {
             primary_key: "name".to_string(),
             indexes_f64: vec!["price".to_string()],
             indexes_string: vec!["book_type".to_string()],
             indexes_string_unique: vec!["book_uid".to_string()],
+            indexes_compound: vec![],
         };
-        mg_db.create_collection(COLLECTION_NAME.to_string(), schema);
-        let collections = mg_db.list_all_collections();
+        mg_db.create_collection(COLLECTION_NAME.to_string(), schema).unwrap();
+        let collections = mg_db.list_all_collections().unwrap();
         println!("collections_str {}: {:#?}", COLLECTION_NAME, collections);
         println!("测试完毕: test_create_collection");
         Ok(())
@@ -701,7 +1345,7 @@ pub(crate) mod tests {
     fn test_update_records_0() -> Result<(), Box<i32>> {
         println!("准备测试: test_update_records");
 
-        let mg_db = get_mgdb(DB_NAME.to_string());
+        let mg_db = memory_mgdb_with_books();
 
         let value_1 = json!(
             {
@@ -721,8 +1365,8 @@ pub(crate) mod tests {
 
         let records = vec![value_1, value_2];
 
-        mg_db.update_records(&COLLECTION_NAME.to_string(), records, UpdateType::Merge);
-        let records = mg_db._list_all_records(&COLLECTION_NAME.to_string());
+        mg_db.update_records(&COLLECTION_NAME.to_string(), records, UpdateType::Merge).unwrap();
+        let records = mg_db._list_all_records(&COLLECTION_NAME.to_string()).unwrap();
         for record in records {
             println!("{}", serde_json::to_string(&record).unwrap());
         }
@@ -735,7 +1379,7 @@ pub(crate) mod tests {
     fn test_update_records_1() -> Result<(), Box<i32>> {
         println!("准备测试: test_update_records");
 
-        let mg_db = get_mgdb(DB_NAME.to_string());
+        let mg_db = memory_mgdb_with_books();
 
         let value_1 = json!(
             {
@@ -756,14 +1400,14 @@ pub(crate) mod tests {
         let records = vec![value_1, value_2];
         let collection_name = COLLECTION_NAME.to_string();
 
-        // mg_db.update_records(&collection_name, records, UpdateType::UpdateOnly);
-        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY);
-        let records = mg_db._list_all_records(&collection_name);
+        // mg_db.update_records(&collection_name, records, UpdateType::UpdateOnly).unwrap();
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+        let records = mg_db._list_all_records(&collection_name).unwrap();
         for record in records {
             println!("{}", serde_json::to_string(&record).unwrap());
         }
 
-        let primary_key_map = mg_db._show_collection_inner(&collection_name);
+        let primary_key_map = mg_db._show_collection_inner(&collection_name).unwrap();
         println!("primary_key_map:{:#?}", primary_key_map);
 
         println!("测试完毕: test_update_records");
@@ -775,7 +1419,7 @@ pub(crate) mod tests {
     fn test_update_records_2() -> Result<(), Box<i32>> {
         println!("准备测试: test_update_records");
 
-        let mg_db = get_mgdb(DB_NAME.to_string());
+        let mg_db = memory_mgdb_with_books();
         let mut records = Vec::new();
 
         let types = vec!["Math", "Physics", "History"];
@@ -796,19 +1440,156 @@ pub(crate) mod tests {
 
         let collection_name = COLLECTION_NAME.to_string();
 
-        // mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY);
-        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY);
-        let records = mg_db._list_all_records(&collection_name);
+        // mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+        let records = mg_db._list_all_records(&collection_name).unwrap();
         for record in records {
             println!("{}", serde_json::to_string(&record).unwrap());
         }
 
-        let info = mg_db._show_collection_inner(&collection_name);
+        let info = mg_db._show_collection_inner(&collection_name).unwrap();
         println!("primary_key_map:{:#?}", info);
 
         println!("测试完毕: test_update_records");
         Ok(())
     }
+    //cargo test test_update_records_result -- --show-output
+    #[test]
+    fn test_update_records_result() -> Result<(), Box<i32>> {
+        println!("准备测试: test_update_records_result");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("ResultBooks_{}", crate::common::helper::get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let records = vec![
+            json!({"name": "R_1", "book_uid": "r_uid_1"}),
+            json!({"name": "R_2", "book_uid": "r_uid_2"}),
+            json!({"name": "R_3", "book_uid": "r_uid_1"}),
+            json!({"book_uid": "r_uid_4"}),
+        ];
+        let update_result = mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+        println!("update_result: {update_result:#?}");
+        assert_eq!((update_result.num_created, update_result.num_updated, update_result.num_skipped, update_result.num_conflicts), (2, 0, 0, 1));
+        assert_eq!(update_result.rejected.len(), 2);
+        assert_eq!(update_result.rejected[0].primary_key, Some("R_3".to_string()));
+        assert!(update_result.rejected[0].reason.contains("book_uid@r_uid_1"));
+        assert_eq!(update_result.rejected[1].primary_key, None);
+
+        let records = vec![json!({"name": "R_1", "book_uid": "r_uid_1"})];
+        let update_result = mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+        assert_eq!((update_result.num_created, update_result.num_skipped), (0, 1));
+
+        let records = vec![json!({"name": "R_1", "price": 10}), json!({"name": "R_9"})];
+        let update_result = mg_db.update_records(&collection_name, records, UpdateType::UpdateOnly).unwrap();
+        assert_eq!((update_result.num_created, update_result.num_updated, update_result.num_skipped), (0, 1, 1));
+
+        let records = vec![json!({"name": "R_2", "price": 20}), json!({"name": "R_4", "book_uid": "r_uid_4"})];
+        let update_result = mg_db.update_records(&collection_name, records, UpdateType::Merge).unwrap();
+        assert_eq!((update_result.num_created, update_result.num_updated, update_result.num_conflicts), (1, 1, 0));
+        assert!(update_result.rejected.is_empty());
+
+        println!("测试完毕: test_update_records_result");
+        Ok(())
+    }
+
+    //cargo test test_update_operators -- --show-output
+    #[test]
+    fn test_update_operators() -> Result<(), Box<i32>> {
+        println!("准备测试: test_update_operators");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("OperatorBooks_{}", crate::common::helper::get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let records = vec![json!({"name": "U_1", "price": 10, "book_type": "Math", "book_uid": "u_uid_1", "views": 0})];
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        //多个线程同时自增, 读取与写入在同一个写事务中, 不会丢失更新
+        let handles: Vec<_> = (0..8).map(|_| {
+            let mg_db = mg_db.clone();
+            let collection_name = collection_name.clone();
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    let records = vec![json!({"name": "U_1", "$inc": {"views": 1}})];
+                    mg_db.update_records(&collection_name, records, UpdateType::UpdateOnly).unwrap();
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let records = vec![json!({"name": "U_1", "$set": {"book_type": "Physics"}, "$mul": {"price": 2}, "$push": {"tags": "new"}})];
+        let update_result = mg_db.update_records(&collection_name, records, UpdateType::UpdateOnly).unwrap();
+        assert_eq!(update_result.num_updated, 1);
+
+        let records = vec![json!({"name": "U_1", "$inc": {"book_type": 1}})];
+        let update_result = mg_db.update_records(&collection_name, records, UpdateType::UpdateOnly).unwrap();
+        assert_eq!(update_result.num_updated, 0);
+        assert_eq!(update_result.rejected[0].primary_key, Some("U_1".to_string()));
+
+        let query = format!("SELECT ONE {collection_name}
+WHERE book_type=Physics AND price=20
+AS Updated
+
+SELECT {collection_name}
+WHERE book_type=Math
+AS OldType
+
+RETURN Updated, OldType");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        println!("final_result: {}", serde_json::to_string_pretty(&final_result).unwrap());
+        assert_eq!(final_result["Updated"]["views"], json!(200));
+        assert_eq!(final_result["Updated"]["tags"], json!(["new"]));
+        assert_eq!(final_result["OldType"], json!([]));
+
+        println!("测试完毕: test_update_operators");
+        Ok(())
+    }
+
+    //cargo test test_delete_records_by_id -- --show-output
+    #[test]
+    fn test_delete_records_by_id() -> Result<(), Box<i32>> {
+        println!("准备测试: test_delete_records_by_id");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("DeleteBooks_{}", crate::common::helper::get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let mut records = Vec::new();
+        for i in 0..5 {
+            records.push(json!({
+                "name": format!("D_{i}"),
+                "price": 10 + i,
+                "book_type": "Math",
+                "book_uid": format!("d_uid_{i}")
+            }));
+        }
+        let (record_ids, _update_result) = mg_db.write_records(&collection_name, records, &UpdateType::CreateOnlY, false).unwrap();
+        assert_eq!(record_ids.len(), 5);
+
+        let deleted_ids = vec![record_ids[1], record_ids[3]];
+        let deleted_records = mg_db.delete_records_by_id(mg_db.begin_write().unwrap(), &collection_name, &deleted_ids).unwrap();
+        assert_eq!(deleted_records.len(), 2);
+        assert_eq!(deleted_records[0]["name"], json!("D_1"));
+
+        let (primary_key_map, _counter_map, index_list) = mg_db._show_collection_inner(&collection_name).unwrap();
+        println!("index_list:{:#?}", index_list);
+        assert_eq!(primary_key_map.len(), 3);
+        assert!(!primary_key_map.contains_key("D_1"));
+        //f64/string/stringU 索引与 #f64# 动态值各剩 3 条
+        assert_eq!(index_list.len(), 3 * 4);
+        for deleted_id in deleted_ids {
+            assert!(index_list.iter().all(|index_str| !index_str.contains(&deleted_id.to_string())));
+        }
+        assert_eq!(mg_db._list_all_records(&collection_name).unwrap().len(), 3);
+
+        //唯一索引已释放, 可以重新使用
+        let records = vec![json!({"name": "D_5", "book_uid": "d_uid_1"})];
+        assert_eq!(mg_db.write_records(&collection_name, records, &UpdateType::CreateOnlY, false).unwrap().0.len(), 1);
+
+        println!("测试完毕: test_delete_records_by_id");
+        Ok(())
+    }
+
     //cargo test test_index_range_f64 -- --show-output
 
     #[test]
@@ -817,8 +1598,8 @@ pub(crate) mod tests {
         let collection_name = COLLECTION_NAME.to_string();
         let index_f64 = "price".to_string();
 
-        let mg_db = get_mgdb(DB_NAME.to_string());
-        let read_txn = mg_db._read_db();
+        let mg_db = memory_mgdb_with_books();
+        let read_txn = mg_db._read_db().unwrap();
         let collection_name_index = format!("{}@f64@{}", collection_name, index_f64);
         let index_table_define = TableDefinition::<(MyF64, u32), ()>::new(collection_name_index.as_str());
         let index_table = read_txn.open_table(index_table_define).unwrap();
@@ -854,8 +1635,8 @@ pub(crate) mod tests {
         let index_string = "book_type".to_string();
         let key = "Math";
 
-        let mg_db = get_mgdb(DB_NAME.to_string());
-        let read_txn = mg_db._read_db();
+        let mg_db = memory_mgdb_with_books();
+        let read_txn = mg_db._read_db().unwrap();
 
         let collection_name_index = format!("{}@string@{}", collection_name, index_string);
         let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
@@ -875,14 +1656,227 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    //cargo test test_add_and_drop_index -- --show-output
+    #[test]
+    fn test_add_and_drop_index() -> Result<(), Box<i32>> {
+        println!("准备测试: test_add_and_drop_index");
+        let mg_db = memory_mgdb();
+        let collection_name = format!("IndexBooks_{}", crate::common::helper::get_timestamp());
+        create_books_collection(&mg_db, &collection_name);
+
+        let records = vec![
+            json!({"name": "I_1", "author": "Lu Xun", "isbn": "978-1", "pages": 300}),
+            json!({"name": "I_2", "author": "Lao She", "isbn": "978-2", "pages": 120}),
+            json!({"name": "I_3", "author": "Lu Xun", "isbn": "978-1", "pages": 80}),
+        ];
+        mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap();
+
+        let author_ids = |mg_db: &MgDb, author: &str| -> Vec<u32> {
+            let read_txn = mg_db._read_db().unwrap();
+            let collection_name_index = format!("{}@string@author", collection_name);
+            let index_table_define: MultimapTableDefinition<&str, u32> = MultimapTableDefinition::new(collection_name_index.as_str());
+            let index_table = read_txn.open_multimap_table(index_table_define).unwrap();
+            let values = index_table.get(author).unwrap();
+            values.map(|v| v.unwrap().value()).collect()
+        };
+
+        //回填已有 records, 之后写入的 record 也会维护新索引
+        let collection = mg_db.add_index(&collection_name, IndexDefine::String("author".to_string())).unwrap();
+        assert!(collection.indexes_string_list.contains(&"author".to_string()));
+        assert_eq!(author_ids(&mg_db, "Lu Xun").len(), 2);
+        mg_db.update_records(&collection_name, vec![json!({"name": "I_4", "author": "Lu Xun", "isbn": "978-4", "pages": 200})], UpdateType::CreateOnlY).unwrap();
+        assert_eq!(author_ids(&mg_db, "Lu Xun").len(), 3);
+
+        //重复添加, 或已有 record 违反唯一约束时失败且定义不变
+        assert!(mg_db.add_index(&collection_name, IndexDefine::String("author".to_string())).is_err());
+        assert_eq!(mg_db.add_index(&collection_name, IndexDefine::StringUnique("isbn".to_string())).err(), Some(MgError::Constraint("isbn@978-1".to_string())));
+        assert!(mg_db.get_collection(&collection_name).unwrap().indexes_string_unique_list.iter().all(|index| index != "isbn"));
+        {
+            let read_txn = mg_db._read_db().unwrap();
+            let collection_name_index = format!("{}@stringU@isbn", collection_name);
+            assert!(read_txn.open_table(TableDefinition::<&str, u32>::new(collection_name_index.as_str())).is_err());
+        }
+
+        mg_db.add_index(&collection_name, IndexDefine::F64("pages".to_string())).unwrap();
+        let query = format!("SELECT {collection_name}
+WHERE author=\"Lu Xun\"
+ORDERBY pages DESC
+FIELD name
+AS ByPages
+
+RETURN ByPages");
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["ByPages"], json!([{"name": "I_1"}, {"name": "I_4"}, {"name": "I_3"}]));
+
+        //定义已持久化到 collection_define
+        {
+            let read_txn = mg_db._read_db().unwrap();
+            let collection_define_table = read_txn.open_table(COLLECTION_DEFINE_TABLE).unwrap();
+            let collection_str = collection_define_table.get(collection_name.clone()).unwrap().unwrap().value();
+            let collection: Collection = serde_json::from_str(collection_str.as_str()).unwrap();
+            assert!(collection.indexes_string_list.contains(&"author".to_string()));
+            assert!(collection.indexes_f64_list.contains(&"pages".to_string()));
+        }
+
+        mg_db.drop_index(&collection_name, IndexDefine::String("author".to_string())).unwrap();
+        mg_db.drop_index(&collection_name, IndexDefine::F64("pages".to_string())).unwrap();
+        assert!(mg_db.drop_index(&collection_name, IndexDefine::F64("pages".to_string())).is_err());
+        {
+            let read_txn = mg_db._read_db().unwrap();
+            let collection_name_index = format!("{}@string@author", collection_name);
+            assert!(read_txn.open_multimap_table(MultimapTableDefinition::<&str, u32>::new(collection_name_index.as_str())).is_err());
+            let collection_name_f64 = format!("{collection_name}#f64#");
+            let f64_table = read_txn.open_table(TableDefinition::<(u32, u32), f64>::new(collection_name_f64.as_str())).unwrap();
+            let pages_id = hash_to_u32(&"pages".to_string());
+            assert!(f64_table.iter().unwrap().all(|kv| kv.unwrap().0.value().1 != pages_id));
+        }
+        let final_result = mg_db.query_records(&query, BTreeMap::new()).unwrap();
+        assert_eq!(final_result["ByPages"], json!([{"name": "I_1"}, {"name": "I_4"}, {"name": "I_3"}]));
+
+        println!("测试完毕: test_add_and_drop_index");
+        Ok(())
+    }
+
+    //cargo test test_create_collection_name -- --show-output
+    #[test]
+    fn test_create_collection_name() -> Result<(), Box<i32>> {
+        println!("准备测试: test_create_collection_name");
+        let mg_db = memory_mgdb_with_books();
+        //与索引表, 内部表重名的名称不可用
+        for collection_name in ["", "Books@primary", "Books#f64#", "collection_define", "counter"] {
+            let schema = Schema { primary_key: "name".to_string(), indexes_f64: vec![], indexes_string: vec![], indexes_string_unique: vec![], indexes_compound: vec![] };
+            assert!(matches!(mg_db.create_collection(collection_name.to_string(), schema), Err(MgError::Parse(_))), "{collection_name}");
+        }
+        assert_eq!(mg_db.list_all_collections().unwrap().len(), 1);
+        assert_eq!(mg_db._list_all_records(&COLLECTION_NAME.to_string()).unwrap().len(), 10);
+        println!("测试完毕: test_create_collection_name");
+        Ok(())
+    }
+
+    //cargo test test_drop_rename_truncate_collection -- --show-output
+    #[test]
+    fn test_drop_rename_truncate_collection() -> Result<(), Box<i32>> {
+        println!("准备测试: test_drop_rename_truncate_collection");
+        let mg_db = memory_mgdb_with_books();
+        let collection_name = COLLECTION_NAME.to_string();
+        mg_db.add_index(&collection_name, IndexDefine::Compound(vec!["book_type".to_string(), "price".to_string()])).unwrap();
+        let table_names = |mg_db: &MgDb| -> Vec<String> {
+            let read_txn = mg_db._read_db().unwrap();
+            let mut names: Vec<String> = read_txn.list_tables().unwrap().map(|table| table.name().to_string()).collect();
+            names.extend(read_txn.list_multimap_tables().unwrap().map(|table| table.name().to_string()));
+            names.sort();
+            names
+        };
+        let books_tables = table_names(&mg_db);
+        println!("books_tables: {books_tables:#?}");
+
+        //清空后保留定义与全部表, 唯一索引可以重新使用
+        assert_eq!(mg_db.truncate_collection(&collection_name).unwrap(), 10);
+        assert!(mg_db._list_all_records(&collection_name).unwrap().is_empty());
+        let (primary_key_map, _counter_map, index_list) = mg_db._show_collection_inner(&collection_name).unwrap();
+        assert!(primary_key_map.is_empty() && index_list.is_empty());
+        assert_eq!(table_names(&mg_db), books_tables);
+        let records = vec![json!({"name": "Book_0", "price": 1, "book_type": "Math", "book_uid": "uid_0"})];
+        assert_eq!(mg_db.update_records(&collection_name, records, UpdateType::CreateOnlY).unwrap().num_created, 1);
+
+        //重命名后旧名称下没有任何表, 索引随之可用
+        let new_name = "Novels".to_string();
+        assert!(matches!(mg_db.rename_collection(&collection_name, &"Novels@primary".to_string()), Err(MgError::Parse(_))));
+        assert!(matches!(mg_db.rename_collection(&"Nothing".to_string(), &new_name), Err(MgError::UnknownCollection(_))));
+        create_books_collection(&mg_db, "Taken");
+        assert!(matches!(mg_db.rename_collection(&collection_name, &"Taken".to_string()), Err(MgError::Constraint(_))));
+        mg_db.drop_collection(&"Taken".to_string()).unwrap();
+
+        let collection = mg_db.rename_collection(&collection_name, &new_name).unwrap();
+        assert_eq!(collection.collection_name, new_name);
+        let novels_tables = table_names(&mg_db);
+        assert_eq!(novels_tables.len(), books_tables.len());
+        assert!(novels_tables.iter().all(|name| !name.starts_with("Books")));
+        let query = "SELECT Novels\nWHERE book_type=Math AND price=1\nAS Found\n\nRETURN Found";
+        let final_result = mg_db.query_records(&query.to_string(), BTreeMap::new()).unwrap();
+        assert_eq!(final_result["Found"].as_array().unwrap().len(), 1);
+        assert!(matches!(mg_db.update_records(&collection_name, vec![json!({"name": "X"})], UpdateType::Merge), Err(MgError::UnknownCollection(_))));
+        let (ids, _update_result) = mg_db.write_records(&new_name, vec![json!({"name": "Book_11"})], &UpdateType::CreateOnlY, false).unwrap();
+        let (_primary_key_map, counter_map, _index_list) = mg_db._show_collection_inner(&new_name).unwrap();
+        assert!(!counter_map.contains_key(&collection_name));
+        //record_id 接续原 counter: 之前已写入 10 + 1 条
+        assert_eq!(ids, vec![10_0000_0000 + 12]);
+        assert_eq!(counter_map[&new_name], ids[0]);
+
+        //删除后只剩内部表, 可以重新创建同名 collection
+        mg_db.drop_collection(&new_name).unwrap();
+        assert_eq!(table_names(&mg_db), vec!["collection_define".to_string(), "counter".to_string()]);
+        assert!(mg_db.list_all_collections().unwrap().is_empty());
+        assert!(matches!(mg_db.drop_collection(&new_name), Err(MgError::UnknownCollection(_))));
+        create_books_collection(&mg_db, &new_name);
+        assert!(mg_db._list_all_records(&new_name).unwrap().is_empty());
+
+        println!("测试完毕: test_drop_rename_truncate_collection");
+        Ok(())
+    }
+
+    //cargo test test_get_mgdb_with_config -- --show-output
+    #[test]
+    fn test_get_mgdb_with_config() -> Result<(), Box<i32>> {
+        println!("准备测试: test_get_mgdb_with_config");
+        let data_root = TempDataRoot::new("config");
+        let config = MgDbConfig {
+            data_root: data_root.0.clone(),
+            file_name: "T_{workspace}.mmg".to_string(),
+            cache_size: Some(4 * 1024 * 1024),
+            durability: crate::minimongo::config::MgDurability::Eventual,
+            read_only: false,
+            idle_timeout: None,
+            in_memory: false,
+            admin_token: None,
+        };
+        let collection_name = COLLECTION_NAME.to_string();
+        let mg_db = get_mgdb_with_config(DB_NAME.to_string(), &config).unwrap();
+        create_books_collection(&mg_db, COLLECTION_NAME);
+        mg_db.update_records(&collection_name, vec![json!({"name": "C_1", "price": 10.0, "book_type": "A", "book_uid": "c1"})], UpdateType::CreateOnlY).unwrap();
+        assert!(config.db_path(DB_NAME).exists());
+        //同一个数据文件返回同一个实例, 同名的内存 workspace 互不影响
+        assert!(Arc::ptr_eq(&mg_db, &get_mgdb_with_config(DB_NAME.to_string(), &config).unwrap()));
+        let memory_config = MgDbConfig { in_memory: true, ..config.clone() };
+        let memory_db = get_mgdb_with_config(DB_NAME.to_string(), &memory_config).unwrap();
+        assert!(!Arc::ptr_eq(&mg_db, &memory_db));
+        assert!(memory_db.list_all_collections().unwrap().is_empty());
+
+        //只读打开: 可以读取, 写入返回错误
+        let read_only_config = MgDbConfig { file_name: "R_{workspace}.mmg".to_string(), ..config.clone() };
+        {
+            let db = read_only_config.open_db(DB_NAME).unwrap();
+            let mg_db = MgDb { db, _workspace_nanoid: DB_NAME.to_string(), config: read_only_config.clone(), last_access: AtomicU64::new(0), collection_map: RwLock::new(BTreeMap::new()), counter_map: RwLock::new(BTreeMap::new()) };
+            create_books_collection(&mg_db, COLLECTION_NAME);
+        }
+        let read_only_config = MgDbConfig { read_only: true, ..read_only_config };
+        let mg_db = get_mgdb_with_config(DB_NAME.to_string(), &read_only_config).unwrap();
+        assert!(mg_db.get_collection(&collection_name).is_ok());
+        let result = mg_db.update_records(&collection_name, vec![json!({"name": "C_2"})], UpdateType::CreateOnlY);
+        assert!(matches!(result, Err(MgError::Constraint(_))));
+        let schema = Schema { primary_key: "name".to_string(), indexes_f64: vec![], indexes_string: vec![], indexes_string_unique: vec![], indexes_compound: vec![] };
+        assert!(matches!(mg_db.create_collection("Other".to_string(), schema), Err(MgError::Constraint(_))));
+
+        //只读打开不存在的文件失败
+        let missing_config = MgDbConfig { file_name: "M_{workspace}.mmg".to_string(), ..read_only_config.clone() };
+        assert!(get_mgdb_with_config(DB_NAME.to_string(), &missing_config).is_err());
+
+        for config in [&config, &memory_config, &read_only_config] {
+            crate::minimongo::workspace::close_workspace(config, DB_NAME).unwrap();
+        }
+
+        println!("测试完毕: test_get_mgdb_with_config");
+        Ok(())
+    }
+
     //cargo test test_show_collection_inner -- --show-output
     #[test]
     fn test_show_collection_inner() -> Result<(), Box<i32>> {
         println!("准备测试: test_update_records");
         let collection_name = COLLECTION_NAME.to_string();
 
-        let mg_db = get_mgdb(DB_NAME.to_string());
-        let info = mg_db._show_collection_inner(&collection_name);
+        let mg_db = memory_mgdb_with_books();
+        let info = mg_db._show_collection_inner(&collection_name).unwrap();
         println!("primary_key_map:{:#?}", info);
 
         println!("测试完毕: test_update_records");
@@ -1,9 +1,14 @@
 use std::collections::BTreeMap;
-use actix_web::{get, post, web};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
 use serde_json::Value;
+use crate::common::crypto6::sha256_hex;
 use crate::common::helper::get_timestamp;
-use crate::minimongo::minimongo::{Collection, get_mgdb, Schema};
+use crate::minimongo::config::{default_config, MgDbConfig};
+use crate::minimongo::error::MgError;
+use crate::minimongo::minimongo::{Collection, get_mgdb, IndexDefine, RejectedRecord, Schema};
 use crate::minimongo::query::UpdateType;
+use crate::minimongo::workspace::{close_workspace, delete_workspace, get_workspace_info, list_workspaces, normalize_workspace_id, open_workspace, opened_workspace_config, WorkspaceInfo, WorkspaceState};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -25,6 +30,183 @@ pub async fn greet(name: web::Path<String>) -> web::Json<Info> {
 }
 
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ErrorResponse {
+    timestamp: u128,
+    state: u64,
+    message: String,
+}
+
+/// 请求失败时返回对应的状态码, state 与状态码一致
+impl ResponseError for MgError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MgError::UnknownCollection(_) | MgError::UnknownWorkspace(_) => StatusCode::NOT_FOUND,
+            MgError::Parse(_) => StatusCode::BAD_REQUEST,
+            MgError::Constraint(_) => StatusCode::CONFLICT,
+            MgError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            MgError::Storage(_) | MgError::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status_code = self.status_code();
+        println!("请求失败: {self}");
+        HttpResponse::build(status_code).json(ErrorResponse {
+            timestamp: get_timestamp(),
+            state: status_code.as_u16() as u64,
+            message: self.to_string(),
+        })
+    }
+}
+
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct WorkspaceRequest {
+    workspace_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct WorkspaceInfoResponse {
+    timestamp: u128,
+    state: u64,
+    message: String,
+    /// 尚未打开过的 workspace 为 null
+    workspace: Option<WorkspaceInfo>,
+}
+
+/// 查询 workspace 规范化后的 id 与原始 id, 不会创建 workspace
+#[post("/workspace_info")]
+pub async fn workspace_info(data: web::Json<WorkspaceRequest>) -> Result<web::Json<WorkspaceInfoResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let workspace_id = normalize_workspace_id(&data.0.workspace_id)?;
+    let workspace = get_workspace_info(&default_config()?, &workspace_id)?;
+
+    let response = WorkspaceInfoResponse {
+        timestamp,
+        state: 200,
+        message: "查询成功".to_string(),
+        workspace,
+    };
+    Ok(web::Json(response))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OpenWorkspaceRequest {
+    workspace_id: String,
+    /// 是否使用内存存储, 不传时使用启动参数 --in-memory 的设置
+    in_memory: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OpenWorkspaceResponse {
+    timestamp: u128,
+    state: u64,
+    message: String,
+    in_memory: bool,
+}
+
+/// 按 workspace 选择存储方式打开, 之后的请求都使用这个实例, 直到 workspace 被关闭
+#[post("/open_workspace")]
+pub async fn open_workspace_api(data: web::Json<OpenWorkspaceRequest>) -> Result<web::Json<OpenWorkspaceResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let config = default_config()?;
+    let in_memory = data.0.in_memory.unwrap_or(config.in_memory);
+    let mg_db = open_workspace(&config, &data.0.workspace_id, in_memory)?;
+
+    let response = OpenWorkspaceResponse {
+        timestamp,
+        state: 200,
+        message: "workspace 已打开".to_string(),
+        in_memory: mg_db.config.in_memory,
+    };
+    Ok(web::Json(response))
+}
+
+/// admin 接口请求头中的 token
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+/// admin 接口要求请求头中的 token 与 --admin-token 一致, 未配置时拒绝全部 admin 请求
+fn check_admin_token(request: &HttpRequest, config: &MgDbConfig) -> Result<(), MgError> {
+    let Some(admin_token) = &config.admin_token else {
+        return Err(MgError::Unauthorized("未配置 --admin-token, admin 接口不可用".to_string()));
+    };
+    let request_token = request.headers().get(ADMIN_TOKEN_HEADER).map(|value| value.as_bytes()).unwrap_or_default();
+    //比较哈希值, 比较耗时与 token 内容无关
+    if sha256_hex(request_token) != sha256_hex(admin_token) {
+        return Err(MgError::Unauthorized(format!("{ADMIN_TOKEN_HEADER} 错误")));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListWorkspacesResponse {
+    timestamp: u128,
+    state: u64,
+    message: String,
+    workspaces: Vec<WorkspaceState>,
+}
+
+#[get("/admin/list_workspaces")]
+pub async fn admin_list_workspaces(request: HttpRequest) -> Result<web::Json<ListWorkspacesResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let config = default_config()?;
+    check_admin_token(&request, &config)?;
+    let workspaces = list_workspaces(&config)?;
+
+    let response = ListWorkspacesResponse {
+        timestamp,
+        state: 200,
+        message: "查询成功".to_string(),
+        workspaces,
+    };
+    Ok(web::Json(response))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct WorkspaceResponse {
+    timestamp: u128,
+    state: u64,
+    message: String,
+}
+
+#[post("/admin/close_workspace")]
+pub async fn admin_close_workspace(request: HttpRequest, data: web::Json<WorkspaceRequest>) -> Result<web::Json<WorkspaceResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let config = default_config()?;
+    check_admin_token(&request, &config)?;
+    let closed = close_workspace(&opened_workspace_config(&config, &data.0.workspace_id)?, &data.0.workspace_id)?;
+    let message = if closed { "workspace 已关闭" } else { "workspace 未打开" };
+
+    let response = WorkspaceResponse {
+        timestamp,
+        state: 200,
+        message: message.to_string(),
+    };
+    Ok(web::Json(response))
+}
+
+#[post("/admin/delete_workspace")]
+pub async fn admin_delete_workspace(request: HttpRequest, data: web::Json<WorkspaceRequest>) -> Result<web::Json<WorkspaceResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let config = default_config()?;
+    check_admin_token(&request, &config)?;
+    delete_workspace(&opened_workspace_config(&config, &data.0.workspace_id)?, &data.0.workspace_id)?;
+
+    let response = WorkspaceResponse {
+        timestamp,
+        state: 200,
+        message: "workspace 已删除".to_string(),
+    };
+    Ok(web::Json(response))
+}
+
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CreateCollectionRequest {
     workspace_id: String,
@@ -41,13 +223,13 @@ pub struct CreateCollectionResponse {
 }
 
 #[post("/create_collection")]
-pub async fn create_collection(data: web::Json<CreateCollectionRequest>) -> web::Json<CreateCollectionResponse> {
+pub async fn create_collection(data: web::Json<CreateCollectionRequest>) -> Result<web::Json<CreateCollectionResponse>, MgError> {
     // println!("准备创建collection: {data:#?}");
     let timestamp = get_timestamp();
 
-    let mg_db = get_mgdb(data.0.workspace_id);
-    mg_db.create_collection(data.0.collection_name, data.0.schema);
-    let collections = mg_db.list_all_collections();
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    mg_db.create_collection(data.0.collection_name, data.0.schema)?;
+    let collections = mg_db.list_all_collections()?;
 
     let response = CreateCollectionResponse {
         timestamp,
@@ -55,7 +237,129 @@ pub async fn create_collection(data: web::Json<CreateCollectionRequest>) -> web:
         message: "collection创建成功".to_string(),
         collections,
     };
-    web::Json(response)
+    Ok(web::Json(response))
+}
+
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CollectionRequest {
+    workspace_id: String,
+    collection_name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RenameCollectionRequest {
+    workspace_id: String,
+    collection_name: String,
+    new_collection_name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TruncateCollectionResponse {
+    timestamp: u128,
+    state: u64,
+    message: String,
+    num_deleted: u64,
+}
+
+#[post("/drop_collection")]
+pub async fn drop_collection(data: web::Json<CollectionRequest>) -> Result<web::Json<CreateCollectionResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    mg_db.drop_collection(&data.0.collection_name)?;
+    let collections = mg_db.list_all_collections()?;
+
+    let response = CreateCollectionResponse {
+        timestamp,
+        state: 200,
+        message: "collection删除成功".to_string(),
+        collections,
+    };
+    Ok(web::Json(response))
+}
+
+#[post("/rename_collection")]
+pub async fn rename_collection(data: web::Json<RenameCollectionRequest>) -> Result<web::Json<CreateCollectionResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    mg_db.rename_collection(&data.0.collection_name, &data.0.new_collection_name)?;
+    let collections = mg_db.list_all_collections()?;
+
+    let response = CreateCollectionResponse {
+        timestamp,
+        state: 200,
+        message: "collection重命名成功".to_string(),
+        collections,
+    };
+    Ok(web::Json(response))
+}
+
+#[post("/truncate_collection")]
+pub async fn truncate_collection(data: web::Json<CollectionRequest>) -> Result<web::Json<TruncateCollectionResponse>, MgError> {
+    let timestamp = get_timestamp();
+
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    let num_deleted = mg_db.truncate_collection(&data.0.collection_name)?;
+
+    let response = TruncateCollectionResponse {
+        timestamp,
+        state: 200,
+        message: "collection已清空".to_string(),
+        num_deleted,
+    };
+    Ok(web::Json(response))
+}
+
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct IndexRequest {
+    workspace_id: String,
+    collection_name: String,
+    index: IndexDefine,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct IndexResponse {
+    timestamp: u128,
+    state: u64,
+    message: String,
+    collection: Collection,
+}
+
+#[post("/add_index")]
+pub async fn add_index(data: web::Json<IndexRequest>) -> Result<web::Json<IndexResponse>, MgError> {
+    // println!("准备 add_index : {data:#?}");
+    let timestamp = get_timestamp();
+
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    let collection = mg_db.add_index(&data.0.collection_name, data.0.index)?;
+
+    let response = IndexResponse {
+        timestamp,
+        state: 200,
+        message: "索引创建成功".to_string(),
+        collection,
+    };
+    Ok(web::Json(response))
+}
+
+#[post("/drop_index")]
+pub async fn drop_index(data: web::Json<IndexRequest>) -> Result<web::Json<IndexResponse>, MgError> {
+    // println!("准备 drop_index : {data:#?}");
+    let timestamp = get_timestamp();
+
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    let collection = mg_db.drop_index(&data.0.collection_name, data.0.index)?;
+
+    let response = IndexResponse {
+        timestamp,
+        state: 200,
+        message: "索引删除成功".to_string(),
+        collection,
+    };
+    Ok(web::Json(response))
 }
 
 
@@ -74,24 +378,30 @@ pub struct UpdateCollectionResponse {
     message: String,
     num_created: u32,
     num_updated: u32,
+    num_skipped: u32,
+    num_conflicts: u32,
+    rejected: Vec<RejectedRecord>,
 }
 
 #[post("/update_collection")]
-pub async fn update_collection(data: web::Json<UpdateCollectionRequest>) -> web::Json<UpdateCollectionResponse> {
+pub async fn update_collection(data: web::Json<UpdateCollectionRequest>) -> Result<web::Json<UpdateCollectionResponse>, MgError> {
     // println!("准备 update_collection : {data:#?}");
     let timestamp = get_timestamp();
 
-    let mg_db = get_mgdb(data.0.workspace_id);
-    mg_db.update_records(&data.0.collection_name, data.0.collections, data.0.update_type);
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    let update_result = mg_db.update_records(&data.0.collection_name, data.0.collections, data.0.update_type)?;
 
     let response = UpdateCollectionResponse {
         timestamp,
         state: 200,
         message: "update collection 成功".to_string(),
-        num_created: 55,
-        num_updated: 51,
+        num_created: update_result.num_created,
+        num_updated: update_result.num_updated,
+        num_skipped: update_result.num_skipped,
+        num_conflicts: update_result.num_conflicts,
+        rejected: update_result.rejected,
     };
-    web::Json(response)
+    Ok(web::Json(response))
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -110,12 +420,12 @@ pub struct QueryResponse {
 }
 
 #[post("/query")]
-pub async fn query(data: web::Json<QueryRequest>) -> web::Json<QueryResponse> {
+pub async fn query(data: web::Json<QueryRequest>) -> Result<web::Json<QueryResponse>, MgError> {
     // println!("准备 query : {data:#?}");
     let timestamp = get_timestamp();
 
-    let mg_db = get_mgdb(data.0.workspace_id);
-    let final_result = mg_db.query_records(&data.0.query, data.0.params);
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    let final_result = mg_db.query_records(&data.0.query, data.0.params)?;
 
     let response = QueryResponse {
         timestamp,
@@ -123,16 +433,16 @@ pub async fn query(data: web::Json<QueryRequest>) -> web::Json<QueryResponse> {
         message: "query 执行成功".to_string(),
         final_result,
     };
-    web::Json(response)
+    Ok(web::Json(response))
 }
 
 #[post("/query_raw")]
-pub async fn query_raw(data: web::Json<QueryRequest>) -> web::Json<BTreeMap<String, Value>> {
+pub async fn query_raw(data: web::Json<QueryRequest>) -> Result<web::Json<BTreeMap<String, Value>>, MgError> {
     // println!("准备 query : {data:#?}");
     let _timestamp = get_timestamp();
 
-    let mg_db = get_mgdb(data.0.workspace_id);
-    let final_result = mg_db.query_records(&data.0.query, data.0.params);
+    let mg_db = get_mgdb(data.0.workspace_id)?;
+    let final_result = mg_db.query_records(&data.0.query, data.0.params)?;
 
-    web::Json(final_result)
+    Ok(web::Json(final_result))
 }
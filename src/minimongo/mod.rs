@@ -1,6 +1,11 @@
 pub mod minimongo;
+pub mod error;
+pub mod config;
+pub mod workspace;
 mod query;
 mod query_helper;
 mod executor;
 mod lazy_set;
+mod expression;
+mod update_operator;
 pub mod mmg;
\ No newline at end of file
@@ -1,10 +1,9 @@
-use std::collections::{BTreeSet};
 use std::sync::{LazyLock};
 use enum_stringify::EnumStringify;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use regex::Regex;
-use crate::minimongo::executor::DEFAULT_LIMIT;
+use crate::minimongo::error::MgError;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum UpdateType {
@@ -50,24 +49,6 @@ pub enum Number {
 } //todo add value_ref
 
 impl Number {
-    pub fn is_string(&self) -> bool {
-        match self {
-            Number::Int64(_) => {}
-            Number::Float64(_) => {}
-            Number::Infinity => {}
-            Number::NegInfinity => {}
-            Number::ValueRef(value_ref) => {
-                match value_ref {
-                    ValueRef::Ref(_) => {}
-                    ValueRef::Value(_) => {
-                        return true;
-                    }
-                }
-            }
-        }
-        return false;
-    }
-
     pub fn _is_ref(&self) -> bool {
         match self {
             Number::Int64(_) => {}
@@ -85,25 +66,15 @@ impl Number {
         }
         return false;
     }
-
-    pub fn to_f64(&self) -> f64 {
-        let f64: f64 = match self {
-            Number::Int64(int64) => { *int64 as f64 }
-            Number::Float64(f64) => { *f64 }
-            Number::Infinity => { f64::INFINITY }
-            Number::NegInfinity => { f64::NEG_INFINITY }
-            Number::ValueRef(_) => { -1.0 }
-        };
-        f64
-    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ExpressionEntity {
     IN { value_ref: ValueRef },
     EQUAL { value_ref: ValueRef },
-    RANGE { max: Number, min: Number },
+    RANGE { max: Number, min: Number, max_inclusive: bool, min_inclusive: bool },
     REGEX { reg: String },
+    PREFIX { value_ref: ValueRef },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -113,6 +84,7 @@ pub struct ConditionExpression {
     pub expression_entity: ExpressionEntity,
 }
 
+/// 逻辑运算符及其优先级, 数字越小结合越松, 未写 `[n]` 时 OR 为 0, AND 为 1
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ConditionOperation {
     NOT(u64),
@@ -120,21 +92,18 @@ pub enum ConditionOperation {
     AND(u64),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum ConditionResult {
-    OPERATION(ConditionOperation),
-    IDS(BTreeSet<u32>),
-}
-
+/// WHERE/HAVING 条件树, 空的 AND 匹配全部, 空的 OR 不匹配任何 record
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Condition {
-    OPERATION(ConditionOperation),
     EXPRESSION(ConditionExpression),
+    NOT(Box<Condition>),
+    AND(Vec<Condition>),
+    OR(Vec<Condition>),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Where {
-    pub conditions: Vec<Condition>,
+    pub condition: Condition,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -179,11 +148,16 @@ pub enum OrderDirection {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct OrderBy {
+pub struct OrderKey {
     pub field: String,
+    pub order_direction: OrderDirection,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderBy {
+    pub keys: Vec<OrderKey>,
     pub limit: ValueRef,
     pub skip: ValueRef,
-    pub order_direction: OrderDirection,
 } //todo value_ref
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -235,7 +209,7 @@ enum BaseWord {
     DESC, //降序
 }
 
-pub fn parse_query(query: &str) -> Vec<Query> {
+pub fn parse_query(query: &str) -> Result<Vec<Query>, MgError> {
     let mut query_chain: Vec<Query> = Vec::new();
     let mut lines = query.lines().map(|line| line.trim());
     let mut current_query = Query::default();
@@ -254,11 +228,11 @@ pub fn parse_query(query: &str) -> Vec<Query> {
         let first_word = words[0];
         if let Ok(keyword) = KeyWord::try_from(first_word) {
             if !current_words.is_empty() {
-                let end = parse_line(&current_keyword, &current_words, &mut current_query);
+                let end = parse_line(&current_keyword, &current_words, &mut current_query)?;
                 current_words.clear();
                 if keyword == KeyWord::RETURN {
                     // println!("返回指令");
-                    let _end = parse_line(&keyword, &words, &mut current_query);
+                    let _end = parse_line(&keyword, &words, &mut current_query)?;
                 }
 
                 if end {
@@ -275,10 +249,10 @@ pub fn parse_query(query: &str) -> Vec<Query> {
             // println!("内容行")
         }
     }
-    return query_chain;
+    Ok(query_chain)
 }
 
-fn parse_line(keyword: &KeyWord, words: &Vec<&str>, query: &mut Query) -> bool {
+fn parse_line(keyword: &KeyWord, words: &Vec<&str>, query: &mut Query) -> Result<bool, MgError> {
     // println!("处理keywords： {}", keyword);
     // println!("内容： {:#?}", current_words);
     match keyword {
@@ -286,20 +260,22 @@ fn parse_line(keyword: &KeyWord, words: &Vec<&str>, query: &mut Query) -> bool {
         KeyWord::SELECT => { parse_select(words, query); }
         KeyWord::GROUP => { parse_group(words, query); }
         KeyWord::AS => {
-            let as_action = words[1].to_string();
-            query.as_action = as_action;
-            return true;
+            let Some(as_action) = words.get(1) else {
+                return Err(MgError::Parse("AS 缺少名称".to_string()));
+            };
+            query.as_action = as_action.to_string();
+            return Ok(true);
         }
-        KeyWord::ORDERBY => { parse_order_by(words, query); }
+        KeyWord::ORDERBY => { parse_order_by(words, query)?; }
         KeyWord::RETURN => { parse_return(words, query); }
         KeyWord::UPDATE => { parse_update(words, query); }
         KeyWord::DELETE => { parse_delete(words, query); }
         KeyWord::FIELD => { parse_field(words, query); }
-        KeyWord::WHERE => { parse_where(words, query); }
-        KeyWord::HAVING => { parse_having(words, query); }
+        KeyWord::WHERE => { parse_where(words, query)?; }
+        KeyWord::HAVING => { parse_having(words, query)?; }
         // _ => {}
     }
-    false
+    Ok(false)
 }
 
 fn parse_create(words: &Vec<&str>, query: &mut Query) {
@@ -318,13 +294,24 @@ fn parse_create(words: &Vec<&str>, query: &mut Query) {
     } else {
         UpdateType::Merge
     };
-    let target_collection = words[1 + index_offset].to_string();
+    let Some(target_collection) = words.get(1 + index_offset).map(|word| word.to_string()) else {
+        return;
+    };
+
+    //CREATE [ONE] <Collection> [CREATEONLY|UPDATEONLY|MERGE] $param
+    let update_type_index = words.iter().position(|&w| w == "CREATEONLY" || w == "UPDATEONLY" || w == "MERGE");
+    let value_index = update_type_index.unwrap_or(1 + index_offset) + 1;
+    let value_ref = if value_index < words.len() {
+        parse_value(words[value_index])
+    } else {
+        ValueRef::Value(Value::Null)
+    };
 
     query.main_action = MainAction::CREATE {
         one,
         update_type,
         target_collection,
-        value_ref: ValueRef::Ref("bbb".to_string()), //todo
+        value_ref,
     };
 }
 
@@ -337,7 +324,9 @@ fn parse_select(words: &Vec<&str>, query: &mut Query) {
     if one {
         index_offset = 1;
     }
-    let target_collection = words[1 + index_offset].to_string();
+    let Some(target_collection) = words.get(1 + index_offset).map(|word| word.to_string()) else {
+        return;
+    };
     query.main_action = MainAction::SELECT { one, target_collection };
 }
 
@@ -352,37 +341,56 @@ fn parse_group(words: &Vec<&str>, query: &mut Query) {
     query.main_action = MainAction::GROUP { target_collection, by };
 }
 
-fn parse_order_by(words: &Vec<&str>, query: &mut Query) {
-    let len = words.len();
-    if len < 2 {
-        return;
+fn parse_order_direction(word: &str) -> Result<OrderDirection, MgError> {
+    match word {
+        "ASC" => Ok(OrderDirection::ASC),
+        "DESC" => Ok(OrderDirection::DESC),
+        _ => Err(MgError::Parse(format!("ORDERBY 未知的排序方向: {word}"))),
     }
-    let field = words[1].to_string();
-    let limit;
-    let skip;
+}
 
-    let skip_index = words.iter().position(|&w| w == "SKIP").unwrap_or(0);
-    if len > skip_index + 1 && skip_index > 0 {
-        let skip_str = words[skip_index + 1];
-        skip = parse_value(skip_str);
-    } else {
-        skip = ValueRef::Value(Value::from(0));
+fn parse_order_by(words: &Vec<&str>, query: &mut Query) -> Result<(), MgError> {
+    //ORDERBY price DESC, name ASC SKIP 0 LIMIT 10
+    //单个字段时排序方向也可以写在 SKIP/LIMIT 之后: ORDERBY price LIMIT 10 SKIP 0 DESC
+    let keys_end = words.iter().position(|&w| w == "SKIP" || w == "LIMIT").unwrap_or(words.len());
+    let keys_str = words[1.min(keys_end)..keys_end].join(" ");
+    let mut keys = Vec::new();
+    for part in keys_str.split(',') {
+        let key_words: Vec<&str> = part.split_whitespace().collect();
+        let key = match key_words.as_slice() {
+            [field] => OrderKey { field: field.to_string(), order_direction: OrderDirection::ASC },
+            [field, direction] => OrderKey { field: field.to_string(), order_direction: parse_order_direction(direction)? },
+            _ => { return Err(MgError::Parse(format!("ORDERBY 无法解析的排序字段: {}", part.trim()))); }
+        };
+        keys.push(key);
     }
 
-    let limit_index = words.iter().position(|&w| w == "LIMIT").unwrap_or(0);
-    if len > limit_index + 1 && limit_index > 0 {
-        let limit_str = words[limit_index + 1];
-        limit = parse_value(limit_str);
-    } else {
-        limit = ValueRef::Value(Value::from(DEFAULT_LIMIT));
+    let mut skip = ValueRef::Value(Value::from(0));
+    //未指定时由执行时决定: 查询默认 DEFAULT_LIMIT 条, UPDATE/DELETE 不限制
+    let mut limit = ValueRef::Value(Value::Null);
+    let mut tail_direction = None;
+    let mut tail = words[keys_end..].iter();
+    while let Some(&word) = tail.next() {
+        match word {
+            "SKIP" | "LIMIT" => {
+                let Some(value_str) = tail.next() else {
+                    return Err(MgError::Parse(format!("ORDERBY {word} 缺少值")));
+                };
+                if word == "SKIP" { skip = parse_value(value_str); } else { limit = parse_value(value_str); }
+            }
+            _ if tail_direction.is_none() => { tail_direction = Some(parse_order_direction(word)?); }
+            _ => { return Err(MgError::Parse(format!("ORDERBY 多余的内容: {word}"))); }
+        }
     }
-
-    let mut order_direction = OrderDirection::ASC;
-    if words.contains(&"DESC") {
-        order_direction = OrderDirection::DESC;
+    if let Some(order_direction) = tail_direction {
+        match keys.as_mut_slice() {
+            [key] if words[1..keys_end].len() == 1 => { key.order_direction = order_direction; }
+            _ => { return Err(MgError::Parse("ORDERBY 排序方向写在 SKIP/LIMIT 之后时只能有一个字段".to_string())); }
+        }
     }
 
-    query.order_by = Some(OrderBy { field, limit, skip, order_direction });
+    query.order_by = Some(OrderBy { keys, limit, skip });
+    Ok(())
 }
 
 fn parse_return(words: &Vec<&str>, query: &mut Query) {
@@ -404,8 +412,8 @@ fn parse_update(words: &Vec<&str>, query: &mut Query) {
         _ => { return; }
     }
 
-    let expressions_str = words[1..].join("");
-    let expressions_vec: Vec<&str> = expressions_str.split(",").collect();
+    let expressions_str = words[1..].join(" ");
+    let expressions_vec = split_top_level(expressions_str.as_str());
     let expressions: Vec<Expression> = expressions_vec.iter().filter(|s| s.len() > 0).map(|s| Expression { string: s.to_string() }).collect();
 
     query.write_action = WriteAction::UPDATE {
@@ -421,6 +429,35 @@ fn parse_delete(_words: &Vec<&str>, query: &mut Query) {
     query.write_action = WriteAction::DELETE;
 }
 
+/// 按顶层逗号切分, 忽略括号与引号内的逗号
+fn split_top_level(input: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+    for (index, c) in input.char_indices() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' | '`' => { quote = Some(c); }
+                '(' | '[' => { depth += 1; }
+                ')' | ']' => { depth -= 1; }
+                ',' if depth == 0 => {
+                    parts.push(input[start..index].trim());
+                    start = index + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+    parts.push(input[start..].trim());
+    parts
+}
+
 static EXPR_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     let reg: Regex = Regex::new(r"[+\-*/()=]").unwrap();
     reg
@@ -435,8 +472,8 @@ fn parse_field(words: &Vec<&str>, query: &mut Query) {
         return;
     }
 
-    let expressions_str = words[1..].join("");
-    let expressions_vec: Vec<&str> = expressions_str.split(",").collect();
+    let expressions_str = words[1..].join(" ");
+    let expressions_vec = split_top_level(expressions_str.as_str());
     let mut fields: Vec<Field> = Vec::new();
     for expression_str in expressions_vec {
         if expression_str.len() == 0 {
@@ -457,70 +494,226 @@ fn parse_field(words: &Vec<&str>, query: &mut Query) {
     });
 }
 
-fn parse_where(words: &Vec<&str>, query: &mut Query) {
-    query.wheres = Some(parse_condition_block(words));
+fn parse_where(words: &Vec<&str>, query: &mut Query) -> Result<(), MgError> {
+    query.wheres = Some(parse_condition_block(words)?);
+    Ok(())
 }
 
-fn parse_having(words: &Vec<&str>, query: &mut Query) {
-    query.having = Some(parse_condition_block(words));
+fn parse_having(words: &Vec<&str>, query: &mut Query) -> Result<(), MgError> {
+    query.having = Some(parse_condition_block(words)?);
+    Ok(())
 }
 
+/// 条件表达式中的运算关键字, 解析前在两侧补空格
+const CONDITION_KEYWORDS: [&str; 4] = ["IN", "REGEX", "STARTSWITH", "LIKE"];
+
 static LOGIC_KEYWORD_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    let reg: Regex = Regex::new(r"^(OR|AND|NOT)(?:\[(\d+)])?$").unwrap();
+    let reg: Regex = Regex::new(r"^(OR|AND|NOT)(?:\[(\d+)])?([()].*)?$").unwrap();
     reg
 });
 
-fn parse_keyword_and_number(input: &str) -> Option<(String, u64)> {
+/// 解析 `AND`, `OR[5]`, `NOT(`, `OR)` 等逻辑运算符, 返回运算符和紧跟其后的剩余部分
+fn parse_keyword_and_number(input: &str) -> Option<(ConditionOperation, &str)> {
     if let Some(cap) = LOGIC_KEYWORD_REGEX.captures(input) {
-        let keyword = cap[1].to_string();
-        let number = cap.get(2).map_or(0, |m| m.as_str().parse::<u64>().unwrap_or(0));
-        return Some((keyword, number));
+        let number = cap.get(2).and_then(|m| m.as_str().parse::<u64>().ok());
+        let condition_operation = match &cap[1] {
+            "NOT" => { ConditionOperation::NOT(number.unwrap_or(0)) }
+            "AND" => { ConditionOperation::AND(number.unwrap_or(1)) }
+            _ => { ConditionOperation::OR(number.unwrap_or(0)) }
+        };
+        let rest = cap.get(3).map_or("", |m| m.as_str());
+        return Some((condition_operation, rest));
     }
     None
 }
 
-fn parse_condition_block(words: &Vec<&str>) -> Where {
-    let block_words = words[1..].to_vec();
+#[derive(Debug)]
+enum ConditionToken {
+    Operation(ConditionOperation),
+    LeftParen,
+    RightParen,
+    Word(String),
+}
 
-    let mut conditions = Vec::new();
+/// 拆分出括号和逻辑运算符, 引号内的内容保持原样; 词中间的括号(如正则 `^(a|b)$`)不作分组
+fn tokenize_condition_words(words: &[&str]) -> Vec<ConditionToken> {
+    let mut tokens = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut current = String::new();
+
+    for &word in words {
+        let mut word = word;
+        if quote.is_none() {
+            if let Some((condition_operation, rest)) = parse_keyword_and_number(word) {
+                tokens.push(ConditionToken::Operation(condition_operation));
+                word = rest;
+            } else if CONDITION_KEYWORDS.contains(&word) {
+                tokens.push(ConditionToken::Word(format!(" {word} ")));
+                continue;
+            }
+        }
 
-    let mut current_words: Vec<&str> = Vec::new();
+        let mut depth = 0;
+        for c in word.chars() {
+            match quote {
+                Some(q) => {
+                    if c == q {
+                        quote = None;
+                    }
+                    current.push(c);
+                }
+                None => match c {
+                    '"' | '\'' | '`' => {
+                        quote = Some(c);
+                        current.push(c);
+                    }
+                    '(' if current.is_empty() => { tokens.push(ConditionToken::LeftParen); }
+                    '(' => {
+                        depth += 1;
+                        current.push(c);
+                    }
+                    ')' if depth > 0 => {
+                        depth -= 1;
+                        current.push(c);
+                    }
+                    ')' => {
+                        if !current.is_empty() {
+                            tokens.push(ConditionToken::Word(current.clone()));
+                            current.clear();
+                        }
+                        tokens.push(ConditionToken::RightParen);
+                    }
+                    _ => { current.push(c); }
+                }
+            }
+        }
+        // 引号内的空格属于同一个值, 继续拼接下一个 word
+        if quote.is_some() {
+            current.push(' ');
+        } else if !current.is_empty() {
+            tokens.push(ConditionToken::Word(current.clone()));
+            current.clear();
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(ConditionToken::Word(current.trim_end().to_string()));
+    }
+    tokens
+}
+
+fn parse_condition_block(words: &Vec<&str>) -> Result<Where, MgError> {
+    let tokens = tokenize_condition_words(&words[1..]);
+    let mut pos = 0;
+    let condition = parse_condition_tokens(&tokens, &mut pos, 0)?;
+    Ok(Where { condition })
+}
 
-    for word in block_words {
-        match parse_keyword_and_number(word) {
-            None => {
-                match word {
-                    "IN" => { current_words.push(" IN "); }
-                    "REGEX" => { current_words.push(" REGEX "); }
-                    &_ => { current_words.push(word); }
+/// 解析同一层括号内的 `操作数 运算符 操作数 ...`, 缺少运算符时按 AND 连接
+/// 括号不成对, 运算符前后缺少操作数时返回错误
+fn parse_condition_tokens(tokens: &[ConditionToken], pos: &mut usize, depth: usize) -> Result<Condition, MgError> {
+    let mut operands = Vec::new();
+    let mut operations = Vec::new();
+    let mut closed = false;
+
+    while *pos < tokens.len() {
+        match &tokens[*pos] {
+            ConditionToken::RightParen => {
+                if depth == 0 {
+                    return Err(MgError::Parse("条件中多余的 ')'".to_string()));
                 }
+                *pos += 1;
+                closed = true;
+                break;
             }
-            Some((keyword, number)) => {
-                let condition_operation = match keyword.as_str() {
-                    "NOT" => { ConditionOperation::NOT(number) }
-                    "OR" => { ConditionOperation::OR(number) }
-                    "AND" => { ConditionOperation::AND(number) }
-                    &_ => { ConditionOperation::OR(number) }
-                };
-                // println!("处理逻辑运算符 {:?}", condition_operation);
-                if current_words.len() > 0 {
-                    let expression = current_words.join("");
-                    let condition_expression = parse_condition_expression(expression);
-                    conditions.push(Condition::EXPRESSION(condition_expression));
-                    current_words.clear();
+            ConditionToken::Operation(ConditionOperation::NOT(_)) | ConditionToken::LeftParen | ConditionToken::Word(_) => {
+                if operands.len() > operations.len() {
+                    operations.push(ConditionOperation::AND(1));
+                }
+                operands.push(parse_condition_operand(tokens, pos, depth)?);
+            }
+            ConditionToken::Operation(condition_operation) => {
+                if operands.len() == operations.len() {
+                    return Err(MgError::Parse(format!("条件中 {condition_operation:?} 之前缺少操作数")));
                 }
+                operations.push(condition_operation.clone());
+                *pos += 1;
+            }
+        }
+    }
+    if depth > 0 && !closed {
+        return Err(MgError::Parse("条件中的 '(' 缺少对应的 ')'".to_string()));
+    }
+    if operands.is_empty() {
+        return Err(MgError::Parse("条件为空".to_string()));
+    }
+    if operands.len() == operations.len() {
+        return Err(MgError::Parse(format!("条件中 {:?} 之后缺少操作数", operations[operations.len() - 1])));
+    }
+    Ok(build_condition_tree(operands, operations))
+}
 
-                conditions.push(Condition::OPERATION(condition_operation));
+fn parse_condition_operand(tokens: &[ConditionToken], pos: &mut usize, depth: usize) -> Result<Condition, MgError> {
+    let condition = match tokens.get(*pos) {
+        Some(ConditionToken::Operation(ConditionOperation::NOT(_))) => {
+            //NOT 只作用于紧随其后的操作数, NOT[n] 中的优先级被忽略
+            *pos += 1;
+            Condition::NOT(Box::new(parse_condition_operand(tokens, pos, depth)?))
+        }
+        Some(ConditionToken::LeftParen) => {
+            *pos += 1;
+            parse_condition_tokens(tokens, pos, depth + 1)?
+        }
+        Some(ConditionToken::Word(_)) => {
+            let mut expression = String::new();
+            while let Some(ConditionToken::Word(word)) = tokens.get(*pos) {
+                expression.push_str(word);
+                *pos += 1;
             }
+            parse_condition(expression)?
         }
+        _ => { return Err(MgError::Parse("条件中 NOT 之后缺少操作数".to_string())); }
+    };
+    Ok(condition)
+}
+
+/// 在优先级数字最小的运算符处拆分(相同时取最左), 同类运算符合并为一层
+fn build_condition_tree(mut operands: Vec<Condition>, mut operations: Vec<ConditionOperation>) -> Condition {
+    if operands.len() <= 1 {
+        return operands.pop().unwrap_or(Condition::AND(Vec::new()));
     }
-    if current_words.len() > 0 {
-        let expression = current_words.join("");
-        let condition_expression = parse_condition_expression(expression);
-        conditions.push(Condition::EXPRESSION(condition_expression));
+
+    let mut split_index = 0;
+    let mut min_priority = u64::MAX;
+    for (i, condition_operation) in operations.iter().enumerate() {
+        let priority = match condition_operation {
+            ConditionOperation::NOT(p) | ConditionOperation::OR(p) | ConditionOperation::AND(p) => *p,
+        };
+        if priority < min_priority {
+            min_priority = priority;
+            split_index = i;
+        }
     }
 
-    Where { conditions }
+    let right_operands = operands.split_off(split_index + 1);
+    let right_operations = operations.split_off(split_index + 1);
+    let condition_operation = operations.pop().unwrap_or(ConditionOperation::AND(1));
+    let left = build_condition_tree(operands, operations);
+    let right = build_condition_tree(right_operands, right_operations);
+
+    let is_and = matches!(condition_operation, ConditionOperation::AND(_));
+    let mut children = Vec::new();
+    for child in [left, right] {
+        match child {
+            Condition::AND(list) if is_and => { children.extend(list); }
+            Condition::OR(list) if !is_and => { children.extend(list); }
+            child => { children.push(child); }
+        }
+    }
+    if is_and {
+        Condition::AND(children)
+    } else {
+        Condition::OR(children)
+    }
 }
 
 fn parse_value(value_ref_str: &str) -> ValueRef {
@@ -539,16 +732,16 @@ fn string_to_json_value(input: &str) -> Value {
     if let Ok(parsed_int) = input.parse::<i64>() {
         Value::Number(serde_json::Number::from(parsed_int))
     } else if let Ok(parsed_float) = input.parse::<f64>() {
-        Value::Number(serde_json::Number::from_f64(parsed_float).unwrap())
+        serde_json::Number::from_f64(parsed_float).map(Value::Number).unwrap_or_else(|| json!(input))
     } else {
         json!(input)
     }
 }
 
-fn parse_condition_expression(expression: String) -> ConditionExpression {
+fn parse_condition_expression(expression: String) -> Result<ConditionExpression, MgError> {
     let expression = expression.trim().to_string();
 
-    if expression.contains(" IN ") {
+    let condition_expression = if expression.contains(" IN ") {
         // 处理 IN 表达式
         let parts: Vec<&str> = expression.split(" IN ").collect();
         let target_field = parts[0].trim().to_string();
@@ -564,37 +757,97 @@ fn parse_condition_expression(expression: String) -> ConditionExpression {
         // 处理 REGEX 表达式
         let parts: Vec<&str> = expression.split(" REGEX ").collect();
         let target_field = parts[0].trim().to_string();
-        let reg = parts[1].trim().to_string();
+        let reg = trim_quotes(parts[1].trim()).to_string();
 
         ConditionExpression {
             expression,
             target_field,
             expression_entity: ExpressionEntity::REGEX { reg },
         }
-    } else if expression.contains('=') {
-        // 处理 EQUAL 表达式
-        let parts: Vec<&str> = expression.split('=').collect();
+    } else if expression.contains(" STARTSWITH ") {
+        // 处理 STARTSWITH 表达式
+        let parts: Vec<&str> = expression.split(" STARTSWITH ").collect();
         let target_field = parts[0].trim().to_string();
-        let value_ref_str = parts[1].trim();
+        let value_ref = parse_value(trim_quotes(parts[1].trim()));
+
+        ConditionExpression {
+            expression,
+            target_field,
+            expression_entity: ExpressionEntity::PREFIX { value_ref },
+        }
+    } else if expression.contains(" LIKE ") {
+        // 处理 LIKE 表达式, `abc%` 按前缀匹配, 其余模式转为正则
+        let parts: Vec<&str> = expression.split(" LIKE ").collect();
+        let target_field = parts[0].trim().to_string();
+        let expression_entity = parse_like_pattern(trim_quotes(parts[1].trim()));
+
+        ConditionExpression {
+            expression,
+            target_field,
+            expression_entity,
+        }
+    } else if let Some((index, '=')) = find_comparison_operator(&expression) {
+        // 处理 EQUAL 表达式
+        let target_field = expression[..index].trim().to_string();
+        let value_ref_str = expression[index + 1..].trim();
         let value_ref = parse_value(value_ref_str);
         ConditionExpression {
             expression,
             target_field,
             expression_entity: ExpressionEntity::EQUAL { value_ref },
         }
-    } else if expression.contains('>') || expression.contains('<') {
-        let (min, target_field, max) = parse_range_expression(expression.as_str());
+    } else if let Some((_, '<' | '>')) = find_comparison_operator(&expression) {
+        let Some((min, min_inclusive, target_field, max, max_inclusive)) = parse_range_expression(expression.as_str()) else {
+            return Err(MgError::Parse(format!("范围条件缺少字段: {expression}")));
+        };
         ConditionExpression {
             expression,
             target_field,
-            expression_entity: ExpressionEntity::RANGE { max, min },
+            expression_entity: ExpressionEntity::RANGE { max, min, max_inclusive, min_inclusive },
         }
     } else {
-        panic!("Unsupported expression type");
+        return Err(MgError::Parse(format!("不支持的条件: {expression}")));
+    };
+    Ok(condition_expression)
+}
+
+/// `a!=b` 解析为 `NOT a=b`, 字段不存在的 record 也会匹配
+fn parse_condition(expression: String) -> Result<Condition, MgError> {
+    if !CONDITION_KEYWORDS.iter().any(|keyword| expression.contains(format!(" {keyword} ").as_str())) {
+        if let Some((index, '!')) = find_comparison_operator(&expression) {
+            let equal_expression = format!("{}{}", &expression[..index], &expression[index + 1..]);
+            return Ok(Condition::NOT(Box::new(Condition::EXPRESSION(parse_condition_expression(equal_expression)?))));
+        }
+    }
+    Ok(Condition::EXPRESSION(parse_condition_expression(expression)?))
+}
+
+/// 找到引号外的第一个比较运算符 `=`, `!=`(返回 `!`), `<`, `>`
+fn find_comparison_operator(expression: &str) -> Option<(usize, char)> {
+    let mut quote: Option<char> = None;
+    let mut chars = expression.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' | '`' => { quote = Some(c); }
+                '=' | '<' | '>' => { return Some((i, c)); }
+                '!' if chars.peek().map(|(_, next)| *next) == Some('=') => { return Some((i, c)); }
+                _ => {}
+            }
+        }
     }
+    None
 }
 
 fn string_to_number(input: &str) -> Number {
+    if is_quoted(input) {
+        return Number::ValueRef(ValueRef::Value(Value::String(input[1..input.len() - 1].to_string())));
+    }
     if let Ok(parsed_int) = input.parse::<i64>() {
         Number::Int64(parsed_int)
     } else if let Ok(parsed_float) = input.parse::<f64>() {
@@ -609,7 +862,41 @@ fn string_to_number(input: &str) -> Number {
     }
 }
 
-fn parse_range_expression(expression: &str) -> (Number, String, Number) {
+fn trim_quotes(input: &str) -> &str {
+    if is_quoted(input) { &input[1..input.len() - 1] } else { input }
+}
+
+/// LIKE 模式: `%` 匹配任意个字符, `_` 匹配单个字符
+fn parse_like_pattern(pattern: &str) -> ExpressionEntity {
+    if let Some(prefix) = pattern.strip_suffix('%') {
+        if !prefix.contains(['%', '_']) {
+            return ExpressionEntity::PREFIX { value_ref: ValueRef::Value(Value::String(prefix.to_string())) };
+        }
+    }
+    let mut reg = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '%' => reg.push_str(".*"),
+            '_' => reg.push('.'),
+            c => reg.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    reg.push('$');
+    ExpressionEntity::REGEX { reg }
+}
+
+fn is_quoted(input: &str) -> bool {
+    input.len() >= 2 && ['"', '\'', '`'].iter().any(|&q| input.starts_with(q) && input.ends_with(q))
+}
+
+/// 范围表达式中不带引号, 不是数字也不是 `$参数` 的部分为字段名, `"a" <= name < "m"` 中的字符串为边界
+fn is_range_field(part: &str) -> bool {
+    !is_quoted(part) && !part.starts_with('$') && part.parse::<f64>().is_err()
+}
+
+/// 解析 `10<price<=20`, `price>=10` 等范围表达式, 返回 (min, min 是否包含, 字段, max, max 是否包含)
+/// 找不到字段时返回 None
+fn parse_range_expression(expression: &str) -> Option<(Number, bool, String, Number, bool)> {
     let asc_parts = expression.split('<').collect::<Vec<&str>>();
     let desc_parts = expression.split('>').collect::<Vec<&str>>();
     // println!("test_string_parse:{:#?}", asc_parts);
@@ -619,37 +906,41 @@ fn parse_range_expression(expression: &str) -> (Number, String, Number) {
         is_asc = false;
         desc_parts
     } else { vec![] };
+    //第 i 个运算符位于 parts[i] 与 parts[i + 1] 之间, 后一部分以 `=` 开头即包含边界
+    let operator_inclusive: Vec<bool> = parts.iter().skip(1).map(|part| part.trim_start().starts_with('=')).collect();
+    let parts: Vec<&str> = parts.iter().map(|part| part.trim().trim_start_matches('=').trim()).collect();
+
     let mut target_field = "".to_string();
     let mut min = Number::NegInfinity;
     let mut max = Number::Infinity;
-    let mut target_index = parts.len();
+    let mut min_inclusive = true;
+    let mut max_inclusive = true;
+    let target_index = parts.iter().position(|part| is_range_field(part))?;
     for (index, part) in parts.iter().enumerate() {
-        let number = string_to_number(part.trim());
+        if index == target_index {
+            target_field = part.to_string();
+            continue;
+        }
+        let number = string_to_number(part);
         // println!("parse: {:#?} @ {}", number, index);
-        if number.is_string() {
-            target_field = part.trim().to_string();
-            target_index = index;
-        } else if is_asc {
-            if index < target_index {
-                min = number;
-            } else if index > target_index {
-                max = number;
-            }
-        } else if !is_asc {
-            if index < target_index {
-                max = number;
-            } else if index > target_index {
-                min = number;
-            }
+        let is_inclusive = if index < target_index { operator_inclusive[index] } else { operator_inclusive[index - 1] };
+        if (index < target_index) == is_asc {
+            min = number;
+            min_inclusive = is_inclusive;
+        } else {
+            max = number;
+            max_inclusive = is_inclusive;
         }
     }
-    (min, target_field, max)
+    Some((min, min_inclusive, target_field, max, max_inclusive))
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::minimongo::query::{parse_condition_expression, parse_query, parse_range_expression};
+    use serde_json::Value;
+    use crate::minimongo::error::MgError;
+    use crate::minimongo::query::{Condition, Number, ValueRef, parse_condition_block, parse_condition_expression, parse_query, parse_range_expression};
 
     //cargo test do_some_test_02 -- --show-output
     const SQL_STR_1: &str = include_str!("Test1.SQL");
@@ -667,7 +958,7 @@ mod tests {
     fn test_parse_query() -> Result<(), Box<i32>> {
         println!("test_parse_query");
         // println!("{}", SQL_STR_1);
-        let query_chain = parse_query(SQL_STR_1);
+        let query_chain = parse_query(SQL_STR_1).unwrap();
         println!("{:#?}", query_chain);
         println!("test_parse_query done");
         Ok(())
@@ -691,11 +982,64 @@ mod tests {
         ];
 
         for expression in expressions {
-            let parsed = parse_condition_expression(expression.to_string());
+            let parsed = parse_condition_expression(expression.to_string()).unwrap();
             println!("{:#?}", parsed);
         }
     }
 
+    fn condition_to_string(condition: &Condition) -> String {
+        let join = |children: &Vec<Condition>, operation: &str| {
+            let parts: Vec<String> = children.iter().map(condition_to_string).collect();
+            format!("({})", parts.join(operation))
+        };
+        match condition {
+            Condition::EXPRESSION(expression) => expression.target_field.clone(),
+            Condition::NOT(inner) => format!("!{}", condition_to_string(inner)),
+            Condition::AND(children) => join(children, " & "),
+            Condition::OR(children) => join(children, " | "),
+        }
+    }
+
+    //cargo test test_parse_condition_block -- --show-output
+    #[test]
+    fn test_parse_condition_block() {
+        let cases = vec![
+            ("WHERE a=1 OR b=2 AND c=3", "(a | (b & c))"),
+            ("WHERE a=1 AND b=2 OR c=3", "((a & b) | c)"),
+            ("WHERE (a=1 OR b=2) AND c=3", "((a | b) & c)"),
+            ("WHERE NOT a=1 AND b=2", "(!a & b)"),
+            ("WHERE NOT(a=1 OR b=2) AND c=3", "(!(a | b) & c)"),
+            ("WHERE NOT NOT a=1", "!!a"),
+            ("WHERE a=1 AND[0] b=2 OR c=3", "(a & (b | c))"),
+            ("WHERE a=1 OR[1] b=2 AND[2] c=3", "(a | (b & c))"),
+            ("WHERE a=1 AND b=2 AND c=3 OR d=4", "((a & b & c) | d)"),
+            ("WHERE ( ( a=1 ) )", "a"),
+            ("WHERE name REGEX ^(A|B)$ AND 1<price<5", "(name & price)"),
+            ("WHERE author.name=\"Lu Xun\" OR b=2", "(author.name | b)"),
+            ("WHERE name=\"(x)\" OR b=2", "(name | b)"),
+            ("WHERE a!=1 AND b>=2", "(!a & b)"),
+        ];
+        for (input, expected) in cases {
+            let words: Vec<&str> = input.split_whitespace().collect();
+            let wheres = parse_condition_block(&words).unwrap();
+            assert_eq!(condition_to_string(&wheres.condition), expected, "{input}");
+        }
+    }
+
+    //cargo test test_parse_errors -- --show-output
+    #[test]
+    fn test_parse_errors() {
+        let cases = vec![
+            "SELECT Books\nWHERE 1<2\nAS A",
+            "SELECT Books\nWHERE price\nAS A",
+            "SELECT Books\nWHERE price=1\nAS\n\nRETURN A",
+        ];
+        for input in cases {
+            assert!(matches!(parse_query(input), Err(MgError::Parse(_))), "{input}");
+        }
+        assert!(parse_query("SELECT ONE\nWHERE price=1e999\nAS A").is_ok());
+    }
+
     //cargo test test_string_parse -- --show-output
     #[test]
     fn test_string_parse() {
@@ -706,7 +1050,32 @@ mod tests {
         let expression_str = "-656 > XXX";
         // let expression_str = "-656 < XXX";
 
-        let (min, target_field, max) = parse_range_expression(expression_str);
-        println!("parse: {:?} < {} < {:?}", min, target_field, max);
+        let (min, min_inclusive, target_field, max, max_inclusive) = parse_range_expression(expression_str).unwrap();
+        println!("parse: {:?} {} {} {} {:?}", min, if min_inclusive { "<=" } else { "<" }, target_field, if max_inclusive { "<=" } else { "<" }, max);
+
+        let cases = vec![
+            ("price>10", 10.0, false, f64::INFINITY, true),
+            ("price>=10", 10.0, true, f64::INFINITY, true),
+            ("price<=10", f64::NEG_INFINITY, true, 10.0, true),
+            ("10<price<=20", 10.0, false, 20.0, true),
+            ("20>=price>10", 10.0, false, 20.0, true),
+            ("10 <= price < 20", 10.0, true, 20.0, false),
+        ];
+        let to_f64 = |number: &Number| match number {
+            Number::Int64(int64) => *int64 as f64,
+            Number::Float64(f64) => *f64,
+            Number::Infinity => f64::INFINITY,
+            Number::NegInfinity => f64::NEG_INFINITY,
+            Number::ValueRef(_) => f64::NAN,
+        };
+        for (input, min, min_inclusive, max, max_inclusive) in cases {
+            let parsed = parse_range_expression(input).unwrap();
+            assert_eq!((to_f64(&parsed.0), parsed.1, parsed.2.as_str(), to_f64(&parsed.3), parsed.4), (min, min_inclusive, "price", max, max_inclusive), "{input}");
+        }
+
+        let (min, _, target_field, max, _) = parse_range_expression("\"a\"<=name<\"m\"").unwrap();
+        assert_eq!(target_field, "name");
+        assert!(matches!(min, Number::ValueRef(ValueRef::Value(Value::String(ref a))) if a == "a"));
+        assert!(matches!(max, Number::ValueRef(ValueRef::Value(Value::String(ref m))) if m == "m"));
     }
 }
@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
 use std::fmt::{Debug, Formatter};
 use redb::{Key, ReadOnlyTable, ReadTransaction, Table, TableDefinition, TypeName, Value, WriteTransaction};
+use crate::minimongo::error::MgError;
 // use regex::Regex;
 
 // #[derive(Debug)]
@@ -37,8 +38,8 @@ use redb::{Key, ReadOnlyTable, ReadTransaction, Table, TableDefinition, TypeName
 pub struct MyF64(pub f64);
 
 impl Debug for MyF64 {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> std::fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MyF64({})", self.0)
     }
 }
 
@@ -84,14 +85,83 @@ impl Key for MyF64 {
     }
 }
 
-pub fn open_table_write<'a, K: Key + 'static, V: Value + 'static>(table_name: &'a String, write_txn: &'a WriteTransaction) -> Table<'a, K, V> {
+/// 复合索引 key 中各字段值的类型标记, 字节序即 Null < 数值 < 字符串
+pub const COMPOUND_TAG_NULL: u8 = 0x00;
+pub const COMPOUND_TAG_NUMBER: u8 = 0x01;
+pub const COMPOUND_TAG_STRING: u8 = 0x02;
+/// 大于任何类型标记, 用作某个前缀下全部 key 的上界
+pub const COMPOUND_TAG_END: u8 = 0xFF;
+
+/// 把一个字段值追加到复合索引 key, 编码后的字节序与值的顺序一致
+/// 数值: 标记 + 8 字节大端 (正数翻转符号位, 负数全部取反)
+/// 字符串: 标记 + 内容 (0x00 转义为 0x00 0xFF) + 0x00 0x00 结尾
+/// 其余类型按 Null 处理
+pub fn encode_compound_value(buffer: &mut Vec<u8>, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::Number(number) => {
+            let number = number.as_f64().unwrap_or(0.0);
+            //-0.0 与 0.0 编码一致
+            let number = if number == 0.0 { 0.0 } else { number };
+            let bits = number.to_bits();
+            let bits = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+            buffer.push(COMPOUND_TAG_NUMBER);
+            buffer.extend_from_slice(&bits.to_be_bytes());
+        }
+        serde_json::Value::String(string) => {
+            encode_compound_string_prefix(buffer, string);
+            buffer.extend_from_slice(&[0x00, 0x00]);
+        }
+        _ => { buffer.push(COMPOUND_TAG_NULL); }
+    }
+}
+
+/// 复合索引 key 中前 value_count 个字段值编码后的字节长度
+pub fn compound_key_prefix_len(key: &[u8], value_count: usize) -> usize {
+    let mut position = 0;
+    for _ in 0..value_count {
+        match key.get(position) {
+            Some(&COMPOUND_TAG_NUMBER) => { position += 9; }
+            Some(&COMPOUND_TAG_STRING) => {
+                position += 1;
+                //0x00 0xFF 为转义的 0x00, 0x00 0x00 为结尾
+                while let Some(&byte) = key.get(position) {
+                    if byte != 0x00 {
+                        position += 1;
+                        continue;
+                    }
+                    position += 2;
+                    if key.get(position - 1) == Some(&0x00) {
+                        break;
+                    }
+                }
+            }
+            Some(_) => { position += 1; }
+            None => { break; }
+        }
+    }
+    position.min(key.len())
+}
+
+/// 字符串编码去掉结尾, 以它开头的 key 即以该前缀开头的字符串
+pub fn encode_compound_string_prefix(buffer: &mut Vec<u8>, prefix: &str) {
+    buffer.push(COMPOUND_TAG_STRING);
+    for byte in prefix.bytes() {
+        if byte == 0x00 {
+            buffer.extend_from_slice(&[0x00, 0xFF]);
+        } else {
+            buffer.push(byte);
+        }
+    }
+}
+
+pub fn open_table_write<'a, K: Key + 'static, V: Value + 'static>(table_name: &'a String, write_txn: &'a WriteTransaction) -> Result<Table<'a, K, V>, MgError> {
     let table_define: TableDefinition<'a, K, V> = TableDefinition::new(table_name.as_str());
-    let table = write_txn.open_table(table_define).unwrap();
-    table
+    let table = write_txn.open_table(table_define)?;
+    Ok(table)
 }
 
-pub fn open_table_read<'a, K: Key + 'static, V: Value + 'static>(table_name:&'a String, read_txn: &'a ReadTransaction) -> ReadOnlyTable<K, V> {
+pub fn open_table_read<'a, K: Key + 'static, V: Value + 'static>(table_name:&'a String, read_txn: &'a ReadTransaction) -> Result<ReadOnlyTable<K, V>, MgError> {
     let table_define: TableDefinition<'a, K, V> = TableDefinition::new(table_name.as_str());
-    let table = read_txn.open_table(table_define).unwrap();
-    table
+    let table = read_txn.open_table(table_define)?;
+    Ok(table)
 }
@@ -0,0 +1,187 @@
+use std::cmp::Ordering;
+use serde_json::{Number, Value};
+use crate::minimongo::expression::{compare_values, get_value_by_path, remove_value_by_path, set_value_by_path, values_equal};
+use crate::minimongo::query::UpdateType;
+
+/// 支持的部分更新操作, 如 `{"name": "A", "$inc": {"stats.views": 1}}`
+const UPDATE_OPERATORS: [&str; 10] = ["$set", "$unset", "$inc", "$mul", "$min", "$max", "$push", "$pull", "$addToSet", "$rename"];
+
+/// 由已存储的 record 与传入的 record 得到要写入的 record
+/// 不含更新操作时: Merge 按顶层字段合并, 其余整条替换
+/// 含更新操作时: 先合并顶层普通字段, 再依次执行更新操作, 新建的 record 从空对象开始
+pub fn build_record(old_record: Option<&Value>, record: Value, update_type: &UpdateType) -> Result<Value, String> {
+    let Value::Object(mut fields) = record else {
+        return Err("record 不是对象".to_string());
+    };
+    let operations: Vec<(String, Value)> = UPDATE_OPERATORS.iter().filter_map(|operator| fields.remove(*operator).map(|operand| (operator.to_string(), operand))).collect();
+    if let Some(unknown) = fields.keys().find(|key| key.starts_with('$')) {
+        return Err(format!("未知的更新操作: {unknown}"));
+    }
+
+    let is_merge = matches!(update_type, UpdateType::Merge) || !operations.is_empty();
+    let mut new_record = match old_record {
+        Some(Value::Object(old_fields)) if is_merge => {
+            let mut merged = old_fields.clone();
+            merged.extend(fields);
+            Value::Object(merged)
+        }
+        _ => Value::Object(fields),
+    };
+    for (operator, operand) in operations {
+        apply_operator(&mut new_record, &operator, operand)?;
+    }
+    Ok(new_record)
+}
+
+fn apply_operator(record: &mut Value, operator: &str, operand: Value) -> Result<(), String> {
+    //$unset 也可以是字段列表
+    if let ("$unset", Value::Array(paths)) = (operator, &operand) {
+        for path in paths.iter().filter_map(|path| path.as_str()) {
+            remove_value_by_path(record, path);
+        }
+        return Ok(());
+    }
+    let Value::Object(pairs) = operand else {
+        return Err(format!("{operator} 的参数必须是对象"));
+    };
+
+    for (path, value) in pairs {
+        let current = get_value_by_path(record, &path).cloned();
+        match operator {
+            "$set" => { set_value_by_path(record, &path, value); }
+            "$unset" => { remove_value_by_path(record, &path); }
+            "$inc" | "$mul" => {
+                let result = match current {
+                    None => if operator == "$inc" { number_operand(operator, &path, &value)?; value } else { Value::from(0) },
+                    Some(current) => calculate(operator, &path, &current, &value)?,
+                };
+                set_value_by_path(record, &path, result);
+            }
+            "$min" | "$max" => {
+                let wanted = if operator == "$min" { Ordering::Less } else { Ordering::Greater };
+                if current.is_none_or(|current| compare_values(&value, &current) == wanted) {
+                    set_value_by_path(record, &path, value);
+                }
+            }
+            "$push" | "$addToSet" => {
+                let mut list = match current {
+                    None => Vec::new(),
+                    Some(Value::Array(list)) => list,
+                    Some(_) => { return Err(format!("{operator} 的字段不是数组: {path}")); }
+                };
+                //`{"$each": [..]}` 一次追加多个元素
+                let items = match value {
+                    Value::Object(mut each) if each.contains_key("$each") => match each.remove("$each") {
+                        Some(Value::Array(items)) => items,
+                        _ => { return Err(format!("{operator} 的 $each 必须是数组: {path}")); }
+                    },
+                    value => vec![value],
+                };
+                for item in items {
+                    if operator == "$push" || !list.iter().any(|existing| values_equal(existing, &item)) {
+                        list.push(item);
+                    }
+                }
+                set_value_by_path(record, &path, Value::Array(list));
+            }
+            "$pull" => {
+                match current {
+                    None => {}
+                    Some(Value::Array(mut list)) => {
+                        list.retain(|existing| !values_equal(existing, &value));
+                        set_value_by_path(record, &path, Value::Array(list));
+                    }
+                    Some(_) => { return Err(format!("$pull 的字段不是数组: {path}")); }
+                }
+            }
+            "$rename" => {
+                let Value::String(new_path) = value else {
+                    return Err(format!("$rename 的目标必须是字段名: {path}"));
+                };
+                if let Some(moved) = remove_value_by_path(record, &path) {
+                    set_value_by_path(record, &new_path, moved);
+                }
+            }
+            _ => { return Err(format!("未知的更新操作: {operator}")); }
+        }
+    }
+    Ok(())
+}
+
+fn number_operand<'v>(operator: &str, path: &str, value: &'v Value) -> Result<&'v Number, String> {
+    match value {
+        Value::Number(number) => Ok(number),
+        _ => Err(format!("{operator} 的参数必须是数值: {path}")),
+    }
+}
+
+/// 整数之间的运算保持整数, 溢出或含小数时按 f64 计算
+fn calculate(operator: &str, path: &str, current: &Value, value: &Value) -> Result<Value, String> {
+    let Value::Number(current) = current else {
+        return Err(format!("{operator} 的字段不是数值: {path}"));
+    };
+    let operand = number_operand(operator, path, value)?;
+    if let (Some(a), Some(b)) = (current.as_i64(), operand.as_i64()) {
+        let result = if operator == "$inc" { a.checked_add(b) } else { a.checked_mul(b) };
+        if let Some(result) = result {
+            return Ok(Value::from(result));
+        }
+    }
+    let (a, b) = (current.as_f64().unwrap_or(0.0), operand.as_f64().unwrap_or(0.0));
+    let result = if operator == "$inc" { a + b } else { a * b };
+    Ok(Number::from_f64(result).map(Value::Number).unwrap_or(Value::Null))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::minimongo::query::UpdateType;
+    use crate::minimongo::update_operator::build_record;
+
+    //cargo test test_build_record -- --show-output
+    #[test]
+    fn test_build_record() -> Result<(), Box<i32>> {
+        println!("准备测试: test_build_record");
+        let old_record = json!({"name": "A", "price": 10, "stats": {"views": 5}, "tags": ["x"], "old": 1});
+
+        //不含更新操作: Merge 按顶层字段合并, UpdateOnly 整条替换
+        let record = build_record(Some(&old_record), json!({"name": "A", "price": 12}), &UpdateType::Merge).unwrap();
+        assert_eq!(record, json!({"name": "A", "price": 12, "stats": {"views": 5}, "tags": ["x"], "old": 1}));
+        let record = build_record(Some(&old_record), json!({"name": "A", "price": 12}), &UpdateType::UpdateOnly).unwrap();
+        assert_eq!(record, json!({"name": "A", "price": 12}));
+
+        let operations = json!({
+            "name": "A",
+            "$set": {"author.name": "Lu Xun"},
+            "$unset": ["old"],
+            "$inc": {"stats.views": 2, "stats.likes": 1},
+            "$mul": {"price": 1.5},
+            "$min": {"low": 3},
+            "$max": {"stats.views": 100},
+            "$push": {"tags": {"$each": ["y", "x"]}},
+            "$addToSet": {"labels": "a"},
+            "$rename": {"author": "writer"}
+        });
+        let record = build_record(Some(&old_record), operations, &UpdateType::UpdateOnly).unwrap();
+        assert_eq!(record, json!({
+            "name": "A", "price": 15.0, "stats": {"views": 100, "likes": 1}, "tags": ["x", "y", "x"],
+            "low": 3, "labels": ["a"], "writer": {"name": "Lu Xun"}
+        }));
+
+        let record = build_record(Some(&record), json!({"name": "A", "$pull": {"tags": "x"}, "$addToSet": {"labels": {"$each": ["a", "b"]}}, "$min": {"low": 5}}), &UpdateType::Merge).unwrap();
+        assert_eq!(record["tags"], json!(["y"]));
+        assert_eq!(record["labels"], json!(["a", "b"]));
+        assert_eq!(record["low"], json!(3));
+
+        //新建的 record 从空对象开始
+        let record = build_record(None, json!({"name": "B", "$inc": {"count": 1}, "$mul": {"score": 3}}), &UpdateType::Merge).unwrap();
+        assert_eq!(record, json!({"name": "B", "count": 1, "score": 0}));
+
+        assert!(build_record(Some(&old_record), json!({"name": "A", "$inc": {"tags": 1}}), &UpdateType::Merge).is_err());
+        assert!(build_record(Some(&old_record), json!({"name": "A", "$inc": {"price": "1"}}), &UpdateType::Merge).is_err());
+        assert!(build_record(Some(&old_record), json!({"name": "A", "$push": {"price": 1}}), &UpdateType::Merge).is_err());
+        assert!(build_record(Some(&old_record), json!({"name": "A", "$foo": {"price": 1}}), &UpdateType::Merge).is_err());
+        println!("测试完毕: test_build_record");
+        Ok(())
+    }
+}
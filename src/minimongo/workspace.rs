@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, LazyLock, RwLock, Weak};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use crate::common::crypto6::sha256_base62;
+use crate::common::helper::get_timestamp;
+use crate::minimongo::config::MgDbConfig;
+use crate::minimongo::error::MgError;
+use crate::minimongo::minimongo::{get_mgdb_with_config, MgDb, MGDB_MAP};
+
+/// 可以直接用作文件名的 workspace id 最大长度
+const MAX_SAFE_WORKSPACE_ID_LEN: usize = 64;
+/// 哈希后的 workspace id 前缀, `.` 不属于安全字符, 不会与原样使用的 id 重复
+const HASHED_WORKSPACE_ID_PREFIX: &str = "h.";
+/// 登记表文件名, `@` 不属于安全字符, 不会与 workspace 数据文件重名
+const REGISTRY_FILE_NAME: &str = "@registry.redb";
+/// 规范化后的 workspace id => WorkspaceInfo json
+const WORKSPACE_TABLE: TableDefinition<&str, &str> = TableDefinition::new("workspace");
+
+/// 已打开的登记表, key 为 data_root
+static REGISTRY_MAP: LazyLock<RwLock<HashMap<String, Arc<Database>>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+/// 已关闭但关闭时仍有请求在使用的实例, key 同 MGDB_MAP; 删除数据文件前须全部释放
+static CLOSED_MGDB_MAP: LazyLock<RwLock<HashMap<String, Vec<Weak<MgDb>>>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// 登记表中的一条 workspace 记录
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct WorkspaceInfo {
+    /// 规范化后的 id, 用于数据文件名
+    pub workspace_id: String,
+    /// 请求中传入的原始 id
+    pub display_name: String,
+    pub created_at: u128,
+}
+
+/// list_workspaces 的返回项
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct WorkspaceState {
+    #[serde(flatten)]
+    pub info: WorkspaceInfo,
+    /// 当前是否已打开
+    pub opened: bool,
+}
+
+fn is_safe_workspace_id(workspace_id: &str) -> bool {
+    workspace_id.len() <= MAX_SAFE_WORKSPACE_ID_LEN
+        && workspace_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// 规范化 workspace id: 只含字母数字 `_` `-` 的 id 原样使用,
+/// 其余 (如含 `/` `..` 或过长) 用 sha256_base62 哈希, 保证数据文件只落在 data_root 下
+pub fn normalize_workspace_id(workspace_id: &str) -> Result<String, MgError> {
+    if workspace_id.is_empty() {
+        return Err(MgError::Parse("workspace_id 不能为空".to_string()));
+    }
+    if is_safe_workspace_id(workspace_id) {
+        return Ok(workspace_id.to_string());
+    }
+    Ok(format!("{HASHED_WORKSPACE_ID_PREFIX}{}", sha256_base62(workspace_id)))
+}
+
+fn get_registry(config: &MgDbConfig) -> Result<Arc<Database>, MgError> {
+    let registry_key = config.data_root.to_string_lossy().to_string();
+    let mut registry_map_lock = REGISTRY_MAP.write()?;
+    if let Some(registry) = registry_map_lock.get(&registry_key) {
+        return Ok(registry.clone());
+    }
+    fs::create_dir_all(&config.data_root)?;
+    let registry = Arc::new(Database::create(config.data_root.join(REGISTRY_FILE_NAME))?);
+    registry_map_lock.insert(registry_key, registry.clone());
+    Ok(registry)
+}
+
+/// 登记 workspace 的原始 id, 已登记过的保持不变
+pub(crate) fn register_workspace(config: &MgDbConfig, workspace_id: &str, display_name: &str) -> Result<(), MgError> {
+    let registry = get_registry(config)?;
+    let write_txn = registry.begin_write()?;
+    {
+        let mut table = write_txn.open_table(WORKSPACE_TABLE)?;
+        if table.get(workspace_id)?.is_none() {
+            let workspace_info = WorkspaceInfo {
+                workspace_id: workspace_id.to_string(),
+                display_name: display_name.to_string(),
+                created_at: get_timestamp(),
+            };
+            let workspace_info_str = serde_json::to_string(&workspace_info)?;
+            table.insert(workspace_id, workspace_info_str.as_str())?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+/// 按规范化后的 id 查询登记信息
+pub fn get_workspace_info(config: &MgDbConfig, workspace_id: &str) -> Result<Option<WorkspaceInfo>, MgError> {
+    let registry = get_registry(config)?;
+    let read_txn = registry.begin_read()?;
+    let Ok(table) = read_txn.open_table(WORKSPACE_TABLE) else {
+        return Ok(None);
+    };
+    let Some(workspace_info_str) = table.get(workspace_id)? else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(workspace_info_str.value())?))
+}
+
+/// 删除登记信息, 返回是否登记过
+fn unregister_workspace(config: &MgDbConfig, workspace_id: &str) -> Result<bool, MgError> {
+    let registry = get_registry(config)?;
+    let write_txn = registry.begin_write()?;
+    let removed;
+    {
+        let mut table = write_txn.open_table(WORKSPACE_TABLE)?;
+        removed = table.remove(workspace_id)?.is_some();
+    }
+    write_txn.commit()?;
+    Ok(removed)
+}
+
+/// 列出 data_root 下登记过的 workspace, 内存 workspace 不登记, 不在其中
+pub fn list_workspaces(config: &MgDbConfig) -> Result<Vec<WorkspaceState>, MgError> {
+    let registry = get_registry(config)?;
+    let read_txn = registry.begin_read()?;
+    let Ok(table) = read_txn.open_table(WORKSPACE_TABLE) else {
+        return Ok(vec![]);
+    };
+    let db_map_lock = MGDB_MAP.read()?;
+    let mut workspace_list = vec![];
+    for kv in table.iter()? {
+        let (_key, value) = kv?;
+        let info: WorkspaceInfo = serde_json::from_str(value.value())?;
+        let opened = db_map_lock.contains_key(&config.db_key(&info.workspace_id));
+        workspace_list.push(WorkspaceState { info, opened });
+    }
+    Ok(workspace_list)
+}
+
+/// 按指定的存储方式打开 workspace, in_memory 为 true 时使用内存存储, 不受默认配置的 in_memory 影响
+/// 同一 workspace 已按另一种存储方式打开时返回 `MgError::Constraint`
+pub fn open_workspace(config: &MgDbConfig, workspace_id: &str, in_memory: bool) -> Result<Arc<MgDb>, MgError> {
+    let workspace_nanoid = normalize_workspace_id(workspace_id)?;
+    let other_db_key = config.with_in_memory(!in_memory).db_key(&workspace_nanoid);
+    if MGDB_MAP.read()?.contains_key(&other_db_key) {
+        let storage = if in_memory { "文件" } else { "内存" };
+        return Err(MgError::Constraint(format!("workspace 已按{storage}存储打开: {workspace_nanoid}")));
+    }
+    get_mgdb_with_config(workspace_id.to_string(), &config.with_in_memory(in_memory))
+}
+
+/// 未指定存储方式的请求使用的配置: workspace 只按另一种存储方式打开时改用该方式, 否则为 config
+pub fn opened_workspace_config(config: &MgDbConfig, workspace_id: &str) -> Result<MgDbConfig, MgError> {
+    let workspace_nanoid = normalize_workspace_id(workspace_id)?;
+    let other_config = config.with_in_memory(!config.in_memory);
+    let db_map_lock = MGDB_MAP.read()?;
+    if !db_map_lock.contains_key(&config.db_key(&workspace_nanoid)) && db_map_lock.contains_key(&other_config.db_key(&workspace_nanoid)) {
+        return Ok(other_config);
+    }
+    Ok(config.clone())
+}
+
+/// 关闭 workspace: 从已打开列表中移除, 仍在使用的请求结束后数据库随之关闭
+/// 返回关闭前是否已打开
+pub fn close_workspace(config: &MgDbConfig, workspace_id: &str) -> Result<bool, MgError> {
+    let workspace_nanoid = normalize_workspace_id(workspace_id)?;
+    let db_key = config.db_key(&workspace_nanoid);
+    let mut db_map_lock = MGDB_MAP.write()?;
+    let Some(db) = db_map_lock.remove(&db_key) else {
+        return Ok(false);
+    };
+    if Arc::strong_count(&db) > 1 {
+        CLOSED_MGDB_MAP.write()?.entry(db_key).or_default().push(Arc::downgrade(&db));
+    }
+    println!("关闭 workspace: {workspace_nanoid}");
+    Ok(true)
+}
+
+/// 删除 workspace 的数据文件与登记信息, 仍有请求在使用 (包括已关闭的实例) 时返回错误
+pub fn delete_workspace(config: &MgDbConfig, workspace_id: &str) -> Result<(), MgError> {
+    if config.read_only {
+        return Err(MgError::Constraint(format!("workspace 为只读: {workspace_id}")));
+    }
+    let workspace_nanoid = normalize_workspace_id(workspace_id)?;
+    let db_key = config.db_key(&workspace_nanoid);
+    //持有写锁直到文件删除, 期间不会被重新打开
+    let mut db_map_lock = MGDB_MAP.write()?;
+    let mut closed_map_lock = CLOSED_MGDB_MAP.write()?;
+    if let Some(closed_dbs) = closed_map_lock.get_mut(&db_key) {
+        closed_dbs.retain(|db| db.strong_count() > 0);
+        if !closed_dbs.is_empty() {
+            return Err(MgError::Constraint(format!("workspace 正在使用: {workspace_nanoid}")));
+        }
+        closed_map_lock.remove(&db_key);
+    }
+    let opened = db_map_lock.get(&db_key).is_some();
+    if let Some(db) = db_map_lock.get(&db_key) {
+        if Arc::strong_count(db) > 1 {
+            return Err(MgError::Constraint(format!("workspace 正在使用: {workspace_nanoid}")));
+        }
+        db_map_lock.remove(&db_key);
+    }
+
+    //内存 workspace 关闭即删除
+    if config.in_memory {
+        if !opened {
+            return Err(MgError::UnknownWorkspace(workspace_id.to_string()));
+        }
+        return Ok(());
+    }
+
+    let db_path = config.db_path(&workspace_nanoid);
+    let file_existed = db_path.exists();
+    if file_existed {
+        fs::remove_file(&db_path)?;
+    }
+    let registered = unregister_workspace(config, &workspace_nanoid)?;
+    if !file_existed && !registered {
+        return Err(MgError::UnknownWorkspace(workspace_id.to_string()));
+    }
+    println!("删除 workspace: {workspace_nanoid}");
+    Ok(())
+}
+
+/// 关闭空闲超过 idle_timeout 且没有请求在使用的 workspace, 返回被关闭的数据文件
+pub fn evict_idle_workspaces(idle_timeout: Duration) -> Result<Vec<String>, MgError> {
+    evict_idle_workspaces_matching(idle_timeout, |_db_key| true)
+}
+
+/// 只在 is_candidate 选中的数据文件中关闭空闲的 workspace
+fn evict_idle_workspaces_matching(idle_timeout: Duration, is_candidate: impl Fn(&str) -> bool) -> Result<Vec<String>, MgError> {
+    let deadline = (get_timestamp() as u64).saturating_sub(idle_timeout.as_millis() as u64);
+    let mut db_map_lock = MGDB_MAP.write()?;
+    let idle_keys: Vec<String> = db_map_lock.iter()
+        .filter(|(db_key, db)| is_candidate(db_key) && Arc::strong_count(db) == 1 && db.last_access.load(Ordering::Relaxed) <= deadline)
+        .map(|(db_key, _db)| db_key.clone())
+        .collect();
+    for db_key in idle_keys.iter() {
+        db_map_lock.remove(db_key);
+    }
+    Ok(idle_keys)
+}
+
+/// 在子线程中定期关闭空闲的 workspace
+pub fn start_idle_eviction_sub_thread(idle_timeout: Duration) {
+    let interval = idle_timeout.clamp(Duration::from_secs(1), Duration::from_secs(60));
+    thread::spawn(move || {
+        println!("开启空闲 workspace 清理, 超时: {idle_timeout:?}");
+        loop {
+            thread::sleep(interval);
+            match evict_idle_workspaces(idle_timeout) {
+                Ok(evicted) if !evicted.is_empty() => { println!("关闭空闲 workspace: {evicted:?}"); }
+                Ok(_) => {}
+                Err(err) => { println!("清理空闲 workspace 失败: {err}"); }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::minimongo::config::MgDbConfig;
+    use crate::minimongo::error::MgError;
+    use crate::minimongo::minimongo::get_mgdb_with_config;
+    use crate::minimongo::minimongo::tests::{create_books_collection, TempDataRoot};
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use crate::minimongo::workspace::{close_workspace, delete_workspace, evict_idle_workspaces_matching, get_workspace_info, list_workspaces, normalize_workspace_id, open_workspace, opened_workspace_config};
+
+    //cargo test test_normalize_workspace_id -- --show-output
+    #[test]
+    fn test_normalize_workspace_id() -> Result<(), Box<i32>> {
+        println!("准备测试: test_normalize_workspace_id");
+        assert_eq!(normalize_workspace_id("AABBCC_10015").unwrap(), "AABBCC_10015");
+        assert_eq!(normalize_workspace_id("a-b_C9").unwrap(), "a-b_C9");
+        assert!(matches!(normalize_workspace_id(""), Err(MgError::Parse(_))));
+
+        for workspace_id in ["../../etc/x", "/tmp/x", "a.b", "..", "工作区", &"a".repeat(65)] {
+            let normalized = normalize_workspace_id(workspace_id).unwrap();
+            println!("{workspace_id} => {normalized}");
+            assert!(normalized.starts_with("h."));
+            assert!(normalized[2..].chars().all(|c| c.is_ascii_alphanumeric()));
+            assert_eq!(normalized, normalize_workspace_id(workspace_id).unwrap());
+        }
+        assert_ne!(normalize_workspace_id("a/b").unwrap(), normalize_workspace_id("a\\b").unwrap());
+        println!("测试完毕: test_normalize_workspace_id");
+        Ok(())
+    }
+
+    //cargo test test_workspace_registry -- --show-output
+    #[test]
+    fn test_workspace_registry() -> Result<(), Box<i32>> {
+        println!("准备测试: test_workspace_registry");
+        let data_root = TempDataRoot::new("registry");
+        let config = MgDbConfig {
+            data_root: data_root.0.clone(),
+            ..MgDbConfig::default()
+        };
+        let workspace_id = "../../escape/x";
+        let mg_db = get_mgdb_with_config(workspace_id.to_string(), &config).unwrap();
+        let normalized = normalize_workspace_id(workspace_id).unwrap();
+        assert_eq!(mg_db._workspace_nanoid, normalized);
+        assert!(config.db_path(&normalized).exists());
+        assert!(config.db_path(&normalized).parent() == Some(config.data_root.as_path()));
+
+        let workspace_info = get_workspace_info(&config, &normalized).unwrap().unwrap();
+        assert_eq!(workspace_info.display_name, workspace_id);
+        assert!(get_workspace_info(&config, "not_opened").unwrap().is_none());
+        close_workspace(&config, workspace_id).unwrap();
+        println!("测试完毕: test_workspace_registry");
+        Ok(())
+    }
+
+    //cargo test test_workspace_lifecycle -- --show-output
+    #[test]
+    fn test_workspace_lifecycle() -> Result<(), Box<i32>> {
+        println!("准备测试: test_workspace_lifecycle");
+        let data_root = TempDataRoot::new("lifecycle");
+        let config = MgDbConfig {
+            data_root: data_root.0.clone(),
+            ..MgDbConfig::default()
+        };
+        let mg_db_1 = get_mgdb_with_config("W1".to_string(), &config).unwrap();
+        let mg_db_2 = get_mgdb_with_config("team/W2".to_string(), &config).unwrap();
+        let w2 = mg_db_2._workspace_nanoid.clone();
+        drop(mg_db_2);
+
+        let mut workspace_list = list_workspaces(&config).unwrap();
+        workspace_list.sort_by(|a, b| a.info.display_name.cmp(&b.info.display_name));
+        let names: Vec<(&str, bool)> = workspace_list.iter().map(|state| (state.info.display_name.as_str(), state.opened)).collect();
+        assert_eq!(names, vec![("W1", true), ("team/W2", true)]);
+
+        //关闭后已取得的实例仍可使用, 再次打开得到新的实例
+        assert!(close_workspace(&config, "W1").unwrap());
+        assert!(!close_workspace(&config, "W1").unwrap());
+        assert!(mg_db_1.list_all_collections().is_ok());
+        //已关闭的实例仍在使用时不能删除
+        assert!(matches!(delete_workspace(&config, "W1"), Err(MgError::Constraint(_))));
+        assert!(config.db_path("W1").exists());
+        drop(mg_db_1);
+        let reopened = get_mgdb_with_config("W1".to_string(), &config).unwrap();
+
+        //仍在使用时不能删除
+        assert!(matches!(delete_workspace(&config, "W1"), Err(MgError::Constraint(_))));
+        drop(reopened);
+        delete_workspace(&config, "W1").unwrap();
+        assert!(!config.db_path("W1").exists());
+        assert!(matches!(delete_workspace(&config, "W1"), Err(MgError::UnknownWorkspace(_))));
+        let names: Vec<String> = list_workspaces(&config).unwrap().into_iter().map(|state| state.info.workspace_id).collect();
+        assert_eq!(names, vec![w2.clone()]);
+
+        //空闲关闭只关闭没有在使用的 workspace
+        let mg_db_3 = get_mgdb_with_config("W3".to_string(), &config).unwrap();
+        mg_db_3.last_access.store(0, Ordering::Relaxed);
+        let w2_db = get_mgdb_with_config("team/W2".to_string(), &config).unwrap();
+        w2_db.last_access.store(0, Ordering::Relaxed);
+        drop(w2_db);
+        //只清理本测试的 workspace, 不影响并行运行的其他测试
+        let test_keys = [config.db_key(&w2), config.db_key("W3")];
+        let evicted = evict_idle_workspaces_matching(Duration::from_secs(60), |db_key| test_keys.iter().any(|key| key == db_key)).unwrap();
+        assert!(evicted.contains(&config.db_key(&w2)));
+        assert!(!evicted.contains(&config.db_key("W3")));
+        assert!(!close_workspace(&config, "team/W2").unwrap());
+        assert!(Arc::strong_count(&mg_db_3) == 2);
+        assert!(close_workspace(&config, "W3").unwrap());
+        println!("测试完毕: test_workspace_lifecycle");
+        Ok(())
+    }
+
+    //cargo test test_workspace_storage_mix -- --show-output
+    #[test]
+    fn test_workspace_storage_mix() -> Result<(), Box<i32>> {
+        println!("准备测试: test_workspace_storage_mix");
+        let data_root = TempDataRoot::new("storage_mix");
+        let config = MgDbConfig {
+            data_root: data_root.0.clone(),
+            ..MgDbConfig::default()
+        };
+        //同一进程, 同一默认配置下: M1 使用内存存储, F1 使用文件存储
+        let memory_db = open_workspace(&config, "M1", true).unwrap();
+        let file_db = open_workspace(&config, "F1", false).unwrap();
+        assert!(memory_db.config.in_memory);
+        assert!(!file_db.config.in_memory);
+        assert!(!config.db_path("M1").exists());
+        assert!(config.db_path("F1").exists());
+        let names: Vec<String> = list_workspaces(&config).unwrap().into_iter().map(|state| state.info.workspace_id).collect();
+        assert_eq!(names, vec!["F1".to_string()]);
+
+        //未指定存储方式的请求找到已打开的实例
+        let memory_config = opened_workspace_config(&config, "M1").unwrap();
+        assert!(memory_config.in_memory);
+        assert!(Arc::ptr_eq(&memory_db, &get_mgdb_with_config("M1".to_string(), &memory_config).unwrap()));
+        assert!(!opened_workspace_config(&config, "F1").unwrap().in_memory);
+        assert!(!opened_workspace_config(&config, "not_opened").unwrap().in_memory);
+
+        //已按另一种存储方式打开时不能再打开
+        assert!(matches!(open_workspace(&config, "M1", false), Err(MgError::Constraint(_))));
+        assert!(matches!(open_workspace(&config, "F1", true), Err(MgError::Constraint(_))));
+        assert!(Arc::ptr_eq(&memory_db, &open_workspace(&config, "M1", true).unwrap()));
+
+        //内存 workspace 关闭后数据丢失, 文件 workspace 重新打开后数据仍在
+        create_books_collection(&memory_db, "Books");
+        create_books_collection(&file_db, "Books");
+        drop(memory_db);
+        drop(file_db);
+        assert!(close_workspace(&memory_config, "M1").unwrap());
+        assert!(close_workspace(&config, "F1").unwrap());
+        assert!(open_workspace(&config, "M1", true).unwrap().list_all_collections().unwrap().is_empty());
+        assert_eq!(open_workspace(&config, "F1", false).unwrap().list_all_collections().unwrap().len(), 1);
+
+        delete_workspace(&opened_workspace_config(&config, "M1").unwrap(), "M1").unwrap();
+        delete_workspace(&opened_workspace_config(&config, "F1").unwrap(), "F1").unwrap();
+        assert!(!config.db_path("F1").exists());
+        println!("测试完毕: test_workspace_storage_mix");
+        Ok(())
+    }
+}
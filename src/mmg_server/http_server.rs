@@ -28,7 +28,17 @@ pub async fn start_mmg_server() -> std::io::Result<()> {
             .service(mmg::query_raw)
             .service(mmg::query)
             .service(mmg::update_collection)
-            .service(mmg::create_collection);
+            .service(mmg::create_collection)
+            .service(mmg::drop_collection)
+            .service(mmg::rename_collection)
+            .service(mmg::truncate_collection)
+            .service(mmg::add_index)
+            .service(mmg::drop_index)
+            .service(mmg::workspace_info)
+            .service(mmg::open_workspace_api)
+            .service(mmg::admin_list_workspaces)
+            .service(mmg::admin_close_workspace)
+            .service(mmg::admin_delete_workspace);
         App::new()
             .wrap(Cors::permissive())
             .service(cdp_scope)